@@ -0,0 +1,170 @@
+use std::fs::File;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use log::*;
+use sevenz_rust::{Password, SevenZReader};
+use zip::ZipArchive;
+
+/// Signatures at the start of the supported archives
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const SEVENZ_MAGIC: &[u8] = &[b'7', b'z', 0xBC, 0xAF, 0x27, 0x1C];
+
+/// Reads the ROM at `path`, extracted first if it is a zip, gzip or 7z archive
+/// `entry` selects the archive entry by name, otherwise the first `.gba` file is loaded
+pub fn read_rom(path: &Path, entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut file = File::open(path)?;
+
+    let mut magic = [0; 6];
+    let len = file.read(&mut magic)?;
+    file.rewind()?;
+
+    match &magic[..len] {
+        magic if magic.starts_with(ZIP_MAGIC) => read_zip(file, entry),
+        magic if magic.starts_with(SEVENZ_MAGIC) => read_7z(file, entry),
+        // A gzip file holds a single ROM, there is nothing to choose
+        magic if magic.starts_with(GZIP_MAGIC) => {
+            let mut rom = Vec::new();
+            GzDecoder::new(file).read_to_end(&mut rom)?;
+            Ok(rom)
+        }
+        _ => {
+            let mut rom = Vec::new();
+            file.read_to_end(&mut rom)?;
+            Ok(rom)
+        }
+    }
+}
+
+/// The archive entry `name` is the ROM to load
+fn is_selected(name: &str, entry: Option<&str>) -> bool {
+    match entry {
+        // Either the full path in the archive or only the file name
+        Some(entry) => name == entry || name.rsplit('/').next() == Some(entry),
+        None => name.to_ascii_lowercase().ends_with(".gba"),
+    }
+}
+
+fn not_found(entry: Option<&str>) -> io::Error {
+    let msg = match entry {
+        Some(entry) => format!("No entry `{entry}` in the archive"),
+        None => String::from("No `.gba` file in the archive"),
+    };
+    io::Error::new(io::ErrorKind::NotFound, msg)
+}
+
+fn read_zip(file: File, entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(file)?;
+
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        if file.is_dir() || !is_selected(file.name(), entry) {
+            continue;
+        }
+
+        info!("Loading `{}` from zip archive", file.name());
+        let mut rom = Vec::new();
+        file.read_to_end(&mut rom)?;
+        return Ok(rom);
+    }
+
+    Err(not_found(entry))
+}
+
+fn read_7z(mut file: File, entry: Option<&str>) -> io::Result<Vec<u8>> {
+    let len = file.seek(io::SeekFrom::End(0))?;
+    file.rewind()?;
+
+    let invalid = |e: sevenz_rust::Error| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut archive = SevenZReader::new(file, len, Password::empty()).map_err(invalid)?;
+
+    // Entries are decoded in order, solid archives are read through up to the ROM
+    let mut rom = None;
+    archive
+        .for_each_entries(|file, reader| {
+            if rom.is_some() {
+                return Ok(false);
+            }
+
+            match !file.is_directory() && is_selected(file.name(), entry) {
+                true => {
+                    info!("Loading `{}` from 7z archive", file.name());
+                    let mut data = Vec::new();
+                    reader.read_to_end(&mut data)?;
+                    rom = Some(data);
+                    Ok(false)
+                }
+                false => {
+                    io::copy(reader, &mut io::sink())?;
+                    Ok(true)
+                }
+            }
+        })
+        .map_err(invalid)?;
+
+    rom.ok_or_else(|| not_found(entry))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+
+    #[test]
+    fn rom_archives() {
+        let rom: Vec<u8> = (0..0x400).map(|i| i as u8).collect();
+        let other = vec![0xFF; 0x100];
+
+        fn check(path: &Path, data: &[u8], entry: Option<&str>, expected: &[u8]) {
+            std::fs::write(path, data).unwrap();
+            let result = read_rom(path, entry);
+            std::fs::remove_file(path).unwrap();
+            assert_eq!(result.unwrap(), expected);
+        }
+
+        let path = |ext: &str| {
+            std::env::temp_dir().join(format!("gba_archive_{}.{ext}", std::process::id()))
+        };
+
+        // The first `.gba` entry is loaded unless one is picked by name
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        zip.start_file("readme.txt", options).unwrap();
+        zip.write_all(b"readme").unwrap();
+        zip.start_file("roms/game.GBA", options).unwrap();
+        zip.write_all(&rom).unwrap();
+        zip.start_file("roms/other.gba", options).unwrap();
+        zip.write_all(&other).unwrap();
+        let zip = zip.finish().unwrap().into_inner();
+
+        check(&path("zip"), &zip, None, &rom);
+        check(&path("zip"), &zip, Some("other.gba"), &other);
+        check(&path("zip"), &zip, Some("roms/other.gba"), &other);
+
+        std::fs::write(path("zip"), &zip).unwrap();
+        let missing = read_rom(&path("zip"), Some("missing.gba"));
+        std::fs::remove_file(path("zip")).unwrap();
+        assert_eq!(missing.unwrap_err().kind(), std::io::ErrorKind::NotFound);
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&rom).unwrap();
+        check(&path("gba.gz"), &gz.finish().unwrap(), None, &rom);
+
+        let mut sevenz = sevenz_rust::SevenZWriter::new(Cursor::new(Vec::new())).unwrap();
+        for (name, data) in [("other.bin", &other), ("game.gba", &rom)] {
+            let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+            entry.name = String::from(name);
+            sevenz
+                .push_archive_entry(entry, Some(data.as_slice()))
+                .unwrap();
+        }
+        let sevenz = sevenz.finish().unwrap().into_inner();
+        check(&path("7z"), &sevenz, None, &rom);
+        check(&path("7z"), &sevenz, Some("other.bin"), &other);
+
+        // Other files are loaded as they are
+        check(&path("gba"), &rom, Some("ignored.gba"), &rom);
+    }
+}
@@ -0,0 +1,556 @@
+use std::path::Path;
+
+use log::*;
+
+/// Size of the battery backed SRAM
+pub const SRAM_SIZE: usize = 32 * 1024;
+
+/// Flash is addressed in 64 KB banks
+const FLASH_BANK_SIZE: usize = 64 * 1024;
+
+/// Atmel chips program whole 128 byte pages instead of single bytes
+const ATMEL_PAGE_SIZE: usize = 128;
+
+/// EEPROM sizes, selected by the 6 or 14 bit address width of the game
+pub const EEPROM_SIZE_512: usize = 512;
+pub const EEPROM_SIZE_8K: usize = 8 * 1024;
+
+/// An EEPROM read returns 4 dummy bits followed by the 64 bit block
+const EEPROM_READ_BITS: usize = 4 + 64;
+
+/// Flash chips found in cartridges, identified by (manufacturer, device) in ID mode
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FlashChip {
+    /// Panasonic MN63F805MNP, 64 KB
+    Panasonic,
+    /// Atmel AT29LV512, 64 KB
+    Atmel,
+    /// Macronix MX29L512, 64 KB
+    Macronix64,
+    /// Macronix MX29L010, 128 KB
+    Macronix128,
+    /// Sanyo LE26FV10N1TS, 128 KB
+    Sanyo,
+}
+
+impl FlashChip {
+    fn id(self) -> (u8, u8) {
+        match self {
+            FlashChip::Panasonic => (0x32, 0x1B),
+            FlashChip::Atmel => (0x1F, 0x3D),
+            FlashChip::Macronix64 => (0xC2, 0x1C),
+            FlashChip::Macronix128 => (0xC2, 0x09),
+            FlashChip::Sanyo => (0x62, 0x13),
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            FlashChip::Panasonic | FlashChip::Atmel | FlashChip::Macronix64 => 64 * 1024,
+            FlashChip::Macronix128 | FlashChip::Sanyo => 128 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BackupType {
+    Sram,
+    Flash(FlashChip),
+    /// 512 B or 8 KB, detected from the first DMA to the EEPROM or the save size
+    Eeprom,
+}
+
+impl BackupType {
+    /// Parses a backup type name as given on the command line
+    pub fn parse(name: &str) -> Option<BackupType> {
+        match name {
+            "sram" => Some(BackupType::Sram),
+            "flash64" | "flash-panasonic" => Some(BackupType::Flash(FlashChip::Panasonic)),
+            "flash-atmel" => Some(BackupType::Flash(FlashChip::Atmel)),
+            "flash-macronix64" => Some(BackupType::Flash(FlashChip::Macronix64)),
+            "flash128" | "flash-macronix128" => Some(BackupType::Flash(FlashChip::Macronix128)),
+            "flash-sanyo" => Some(BackupType::Flash(FlashChip::Sanyo)),
+            "eeprom" => Some(BackupType::Eeprom),
+            _ => None,
+        }
+    }
+
+    fn size(self) -> usize {
+        match self {
+            BackupType::Sram => SRAM_SIZE,
+            BackupType::Flash(chip) => chip.size(),
+            BackupType::Eeprom => EEPROM_SIZE_512,
+        }
+    }
+}
+
+/// Command state machine of the Flash chips
+#[derive(Default)]
+struct FlashState {
+    /// Bytes of the 0xAA to 0x5555, 0x55 to 0x2AAA unlock sequence received
+    unlock: u8,
+    /// Manufacturer and device ID are read at 0x0000 and 0x0001
+    id_mode: bool,
+    /// Erase command received, the next command selects chip or sector erase
+    erase: bool,
+    /// Bytes left to program
+    program: usize,
+    /// The next write to 0x0000 selects the bank
+    bank_switch: bool,
+    bank: usize,
+}
+
+/// ID strings the Nintendo save libraries leave in the ROM
+pub const BACKUP_ID_STRINGS: &[(&[u8], BackupType)] = &[
+    (b"EEPROM_V", BackupType::Eeprom),
+    (b"SRAM_V", BackupType::Sram),
+    (b"SRAM_F_V", BackupType::Sram),
+    (b"FLASH_V", BackupType::Flash(FlashChip::Panasonic)),
+    (b"FLASH512_V", BackupType::Flash(FlashChip::Panasonic)),
+    (b"FLASH1M_V", BackupType::Flash(FlashChip::Macronix128)),
+];
+
+/// Games whose backup chip is not identified by the ID strings, keyed by game code
+const GAME_BACKUP_DATABASE: &[(&[u8; 4], BackupType)] = &[
+    (b"AXVE", BackupType::Flash(FlashChip::Macronix128)), // Pokemon Ruby
+    (b"AXPE", BackupType::Flash(FlashChip::Macronix128)), // Pokemon Sapphire
+    (b"BPEE", BackupType::Flash(FlashChip::Macronix128)), // Pokemon Emerald
+    (b"BPRE", BackupType::Flash(FlashChip::Macronix128)), // Pokemon FireRed
+    (b"BPGE", BackupType::Flash(FlashChip::Macronix128)), // Pokemon LeafGreen
+    (b"AX4E", BackupType::Flash(FlashChip::Macronix128)), // Super Mario Advance 4
+    (b"AX4P", BackupType::Flash(FlashChip::Macronix128)), // Super Mario Advance 4
+];
+
+/// Selects the backup type from the game database, then from the ID strings found in the ROM
+pub fn detect(game_code: Option<&str>, id_strings: &[&[u8]]) -> Option<BackupType> {
+    let known = GAME_BACKUP_DATABASE
+        .iter()
+        .find(|(code, _)| Some(code.as_slice()) == game_code.map(str::as_bytes));
+
+    if let Some((_, kind)) = known {
+        return Some(*kind);
+    }
+
+    // The first ID string in the ROM wins
+    id_strings.iter().find_map(|found| {
+        BACKUP_ID_STRINGS
+            .iter()
+            .find(|(id, _)| id == found)
+            .map(|(_, kind)| *kind)
+    })
+}
+
+/// Bit-serial protocol of the EEPROM, accessed one bit per halfword through DMA3
+#[derive(Default)]
+struct EepromState {
+    /// Bits of the command being received, first bit highest
+    command: u128,
+    command_bits: usize,
+    /// Block and number of bits sent of a requested read
+    read: Option<(usize, usize)>,
+}
+
+/// Cartridge backup memory in the SRAM region, mirrored over 0x0E000000-0x0FFFFFFF
+/// The region has an 8 bit bus, wider accesses are handled by the CPU
+pub struct Backup {
+    pub kind: BackupType,
+    pub data: Vec<u8>,
+    /// Written since the last save
+    pub dirty: bool,
+    flash: FlashState,
+    eeprom: EepromState,
+}
+
+impl Backup {
+    pub fn new(kind: BackupType) -> Self {
+        Self {
+            kind,
+            data: vec![0xFF; kind.size()],
+            dirty: false,
+            flash: FlashState::default(),
+            eeprom: EepromState::default(),
+        }
+    }
+
+    pub fn read_u8(&self, addr: u32) -> u8 {
+        match self.kind {
+            BackupType::Sram => self.data[(addr as usize) % self.data.len()],
+            BackupType::Flash(chip) => {
+                let offset = (addr & 0xFFFF) as usize;
+
+                match (self.flash.id_mode, offset) {
+                    (true, 0x0000) => chip.id().0,
+                    (true, 0x0001) => chip.id().1,
+                    _ => self.data[self.flash.bank * FLASH_BANK_SIZE + offset],
+                }
+            }
+            // Nothing is connected to the SRAM region
+            BackupType::Eeprom => 0xFF,
+        }
+    }
+
+    pub fn write_u8(&mut self, addr: u32, val: u8) {
+        match self.kind {
+            BackupType::Sram => {
+                let len = self.data.len();
+                self.data[(addr as usize) % len] = val;
+                self.dirty = true;
+            }
+            BackupType::Flash(chip) => self.flash_write(chip, (addr & 0xFFFF) as usize, val),
+            BackupType::Eeprom => {}
+        }
+    }
+
+    fn flash_write(&mut self, chip: FlashChip, offset: usize, val: u8) {
+        let bank = self.flash.bank * FLASH_BANK_SIZE;
+
+        if self.flash.program > 0 {
+            // Atmel pages are erased when the first byte is loaded
+            if chip == FlashChip::Atmel && self.flash.program == ATMEL_PAGE_SIZE {
+                let page = bank + (offset & !(ATMEL_PAGE_SIZE - 1));
+                self.data[page..page + ATMEL_PAGE_SIZE].fill(0xFF);
+            }
+
+            self.data[bank + offset] = val;
+            self.flash.program -= 1;
+            self.dirty = true;
+            return;
+        }
+
+        if self.flash.bank_switch {
+            if offset == 0x0000 {
+                self.flash.bank = (val as usize) % (chip.size() / FLASH_BANK_SIZE);
+            }
+            self.flash.bank_switch = false;
+            return;
+        }
+
+        match (self.flash.unlock, offset, val) {
+            (0, 0x5555, 0xAA) => self.flash.unlock = 1,
+            (1, 0x2AAA, 0x55) => self.flash.unlock = 2,
+            // Sector erase, the sector is selected by the address
+            (2, _, 0x30) if self.flash.erase && (offset & 0x0FFF) == 0 => {
+                let sector = bank + offset;
+                self.data[sector..sector + 0x1000].fill(0xFF);
+                self.flash.erase = false;
+                self.flash.unlock = 0;
+                self.dirty = true;
+            }
+            (2, 0x5555, command) => {
+                self.flash_command(chip, command);
+                self.flash.unlock = 0;
+            }
+            // Macronix chips also exit ID mode without the unlock sequence
+            (_, _, 0xF0) => {
+                self.flash.id_mode = false;
+                self.flash.unlock = 0;
+            }
+            _ => self.flash.unlock = 0,
+        }
+    }
+
+    fn flash_command(&mut self, chip: FlashChip, command: u8) {
+        let erase = std::mem::take(&mut self.flash.erase);
+
+        match command {
+            0x90 => self.flash.id_mode = true,
+            0xF0 => self.flash.id_mode = false,
+            0x80 => self.flash.erase = true,
+            0x10 if erase => {
+                self.data.fill(0xFF);
+                self.dirty = true;
+            }
+            0xA0 => {
+                self.flash.program = match chip {
+                    FlashChip::Atmel => ATMEL_PAGE_SIZE,
+                    _ => 1,
+                }
+            }
+            0xB0 if chip.size() > FLASH_BANK_SIZE => self.flash.bank_switch = true,
+            _ => {}
+        }
+    }
+
+    /// Address width of the EEPROM commands in bits
+    fn eeprom_addr_bits(&self) -> usize {
+        match self.data.len() {
+            EEPROM_SIZE_8K => 14,
+            _ => 6,
+        }
+    }
+
+    /// Selects the EEPROM size from the length in halfwords of a DMA to the EEPROM
+    /// Read requests are 2 + address + 1 bits long, writes add 64 data bits
+    pub fn eeprom_dma(&mut self, count: u32) {
+        let size = match count {
+            9 | 73 => EEPROM_SIZE_512,
+            17 | 81 => EEPROM_SIZE_8K,
+            _ => return,
+        };
+
+        if self.data.len() != size {
+            info!("EEPROM: {count} bit transfer, using a {size} bytes EEPROM");
+            self.data.resize(size, 0xFF);
+        }
+    }
+
+    /// Receives the next bit of a command
+    pub fn eeprom_write(&mut self, val: u16) {
+        self.eeprom.command = (self.eeprom.command << 1) | (val & 0x1) as u128;
+        self.eeprom.command_bits += 1;
+
+        let bits = self.eeprom.command_bits;
+        if bits < 2 {
+            return;
+        }
+
+        // Read requests are 11, address, 0 and writes 10, address, 64 data bits, 0
+        let addr_bits = self.eeprom_addr_bits();
+        let command = self.eeprom.command;
+        let request = command >> (bits - 2);
+        let complete = match request {
+            0b11 => bits == 2 + addr_bits + 1,
+            0b10 => bits == 2 + addr_bits + 64 + 1,
+            _ => true,
+        };
+
+        if !complete {
+            return;
+        }
+
+        let addr_shift = match request {
+            0b11 => 1,
+            _ => 64 + 1,
+        };
+        let block = ((command >> addr_shift) as usize & ((1 << addr_bits) - 1)) * 8;
+        let block = block % self.data.len();
+
+        match request {
+            0b11 => self.eeprom.read = Some((block, 0)),
+            0b10 => {
+                let data = (command >> 1) as u64;
+                self.data[block..block + 8].copy_from_slice(&data.to_be_bytes());
+                self.dirty = true;
+            }
+            _ => warn!("EEPROM: invalid request `{request:02b}`"),
+        }
+
+        self.eeprom.command = 0;
+        self.eeprom.command_bits = 0;
+    }
+
+    /// Sends the next bit of a read, otherwise the ready bit as writes complete immediately
+    pub fn eeprom_read(&mut self) -> u16 {
+        let (block, sent) = match self.eeprom.read {
+            Some(read) => read,
+            None => return 1,
+        };
+
+        self.eeprom.read = match sent + 1 {
+            EEPROM_READ_BITS => None,
+            sent => Some((block, sent)),
+        };
+
+        match sent.checked_sub(4) {
+            Some(bit) => ((self.data[block + bit / 8] >> (7 - bit % 8)) & 0x1) as u16,
+            None => 0,
+        }
+    }
+
+    /// Loads the contents of a save file, missing bytes are left erased
+    pub fn load(&mut self, save: &[u8]) {
+        // The save size selects the EEPROM size
+        if self.kind == BackupType::Eeprom && save.len() == EEPROM_SIZE_8K {
+            self.data.resize(EEPROM_SIZE_8K, 0xFF);
+        }
+
+        let len = save.len().min(self.data.len());
+        self.data.fill(0xFF);
+        self.data[..len].copy_from_slice(&save[..len]);
+        self.dirty = false;
+    }
+
+    /// Writes the contents to the save file at `path`
+    pub fn save(&mut self, path: &Path) -> std::io::Result<()> {
+        std::fs::write(path, &self.data)?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sram_save() {
+        let mut backup = Backup::new(BackupType::Sram);
+        backup.write_u8(0x0010, 0xA5);
+        assert_eq!(backup.read_u8(0x8010), 0xA5);
+        assert!(backup.dirty);
+
+        let path = std::env::temp_dir().join(format!("gba_sram_{}.sav", std::process::id()));
+        backup.save(&path).unwrap();
+        assert!(!backup.dirty);
+
+        // Missing bytes of a short save are left erased
+        let mut backup = Backup::new(BackupType::Sram);
+        backup.load(&std::fs::read(&path).unwrap()[..0x20]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(backup.read_u8(0x0010), 0xA5);
+        assert_eq!(backup.read_u8(0x0000), 0xFF);
+        assert_eq!(backup.read_u8(0x0020), 0xFF);
+        assert_eq!(backup.data.len(), 32 * 1024);
+    }
+
+    #[test]
+    fn flash_commands() {
+        fn command(backup: &mut Backup, command: u8) {
+            backup.write_u8(0x5555, 0xAA);
+            backup.write_u8(0x2AAA, 0x55);
+            backup.write_u8(0x5555, command);
+        }
+
+        let mut backup = Backup::new(BackupType::Flash(FlashChip::Macronix128));
+
+        // ID mode
+        command(&mut backup, 0x90);
+        assert_eq!(backup.read_u8(0x0000), 0xC2);
+        assert_eq!(backup.read_u8(0x0001), 0x09);
+        command(&mut backup, 0xF0);
+        assert_eq!(backup.read_u8(0x0000), 0xFF);
+
+        // Byte program, plain writes are ignored
+        command(&mut backup, 0xA0);
+        backup.write_u8(0x1234, 0x42);
+        backup.write_u8(0x1235, 0x43);
+        assert_eq!(backup.read_u8(0x1234), 0x42);
+        assert_eq!(backup.read_u8(0x1235), 0xFF);
+
+        // Second bank
+        command(&mut backup, 0xB0);
+        backup.write_u8(0x0000, 1);
+        command(&mut backup, 0xA0);
+        backup.write_u8(0x1234, 0x99);
+        assert_eq!(backup.read_u8(0x1234), 0x99);
+        assert_eq!(backup.data[0x11234], 0x99);
+        command(&mut backup, 0xB0);
+        backup.write_u8(0x0000, 0);
+        assert_eq!(backup.read_u8(0x1234), 0x42);
+
+        // Sector erase only clears the 4 KB sector
+        command(&mut backup, 0xA0);
+        backup.write_u8(0x2000, 0x11);
+        command(&mut backup, 0x80);
+        backup.write_u8(0x5555, 0xAA);
+        backup.write_u8(0x2AAA, 0x55);
+        backup.write_u8(0x1000, 0x30);
+        assert_eq!(backup.read_u8(0x1234), 0xFF);
+        assert_eq!(backup.read_u8(0x2000), 0x11);
+
+        // Chip erase clears both banks
+        command(&mut backup, 0x80);
+        command(&mut backup, 0x10);
+        assert!(backup.data.iter().all(|b| *b == 0xFF));
+        assert!(backup.dirty);
+
+        // Atmel programs 128 byte pages
+        let mut backup = Backup::new(BackupType::Flash(FlashChip::Atmel));
+        backup.data[0x100..0x180].fill(0x00);
+        command(&mut backup, 0x90);
+        assert_eq!(backup.read_u8(0x0000), 0x1F);
+        assert_eq!(backup.read_u8(0x0001), 0x3D);
+        command(&mut backup, 0xF0);
+        command(&mut backup, 0xA0);
+        backup.write_u8(0x0100, 0x55);
+        assert_eq!(backup.read_u8(0x0100), 0x55);
+        assert_eq!(backup.read_u8(0x0101), 0xFF);
+        assert_eq!(backup.data.len(), 64 * 1024);
+    }
+
+    #[test]
+    fn eeprom_commands() {
+        /// Sends `bits` (first bit highest) as one DMA transfer
+        fn send(backup: &mut Backup, bits: &[(u64, usize)]) {
+            let count = bits.iter().map(|(_, len)| len).sum::<usize>();
+            backup.eeprom_dma(count as u32);
+
+            for (val, len) in bits {
+                for i in (0..*len).rev() {
+                    backup.eeprom_write(((val >> i) & 0x1) as u16);
+                }
+            }
+        }
+
+        /// Reads the 64 bit block at `addr`, after 4 ignored bits
+        fn receive(backup: &mut Backup, addr: u64, addr_bits: usize) -> u64 {
+            send(backup, &[(0b11, 2), (addr, addr_bits), (0, 1)]);
+
+            let bits: Vec<u16> = (0..68).map(|_| backup.eeprom_read()).collect();
+            assert_eq!(bits[..4], [0, 0, 0, 0]);
+            bits[4..]
+                .iter()
+                .fold(0, |data, bit| (data << 1) | (*bit as u64))
+        }
+
+        let mut backup = Backup::new(BackupType::Eeprom);
+
+        // 6 bit addresses select the 512 B EEPROM
+        send(
+            &mut backup,
+            &[(0b10, 2), (3, 6), (0x0123456789ABCDEF, 64), (0, 1)],
+        );
+        assert_eq!(backup.data.len(), 512);
+        assert_eq!(backup.data[24..32], 0x0123456789ABCDEFu64.to_be_bytes());
+        assert!(backup.dirty);
+
+        // Ready once the write completes
+        assert_eq!(backup.eeprom_read(), 1);
+        assert_eq!(receive(&mut backup, 3, 6), 0x0123456789ABCDEF);
+        assert_eq!(receive(&mut backup, 4, 6), 0xFFFFFFFFFFFFFFFF);
+
+        // 14 bit addresses select the 8 KB EEPROM
+        let mut backup = Backup::new(BackupType::Eeprom);
+        send(
+            &mut backup,
+            &[(0b10, 2), (0x3FF, 14), (0xFEDCBA9876543210, 64), (0, 1)],
+        );
+        assert_eq!(backup.data.len(), 8 * 1024);
+        assert_eq!(receive(&mut backup, 0x3FF, 14), 0xFEDCBA9876543210);
+        assert_eq!(backup.data[0x1FF8], 0xFE);
+
+        // The save size selects the EEPROM size
+        let mut backup = Backup::new(BackupType::Eeprom);
+        backup.load(&[0x12; 8 * 1024]);
+        assert_eq!(receive(&mut backup, 0x3FF, 14), 0x1212121212121212);
+    }
+
+    #[test]
+    fn detection() {
+        assert_eq!(detect(Some("TEST"), &[]), None);
+        assert_eq!(
+            detect(Some("TEST"), &[b"EEPROM_V"]),
+            Some(BackupType::Eeprom)
+        );
+        assert_eq!(
+            detect(Some("TEST"), &[b"FLASH_V"]),
+            Some(BackupType::Flash(FlashChip::Panasonic))
+        );
+        assert_eq!(
+            detect(None, &[b"FLASH1M_V"]),
+            Some(BackupType::Flash(FlashChip::Macronix128))
+        );
+
+        // The first ID string in the ROM wins, other libraries are ignored
+        assert_eq!(
+            detect(Some("TEST"), &[b"SIIRTC_V", b"SRAM_V", b"EEPROM_V"]),
+            Some(BackupType::Sram)
+        );
+
+        // The game database wins over the ID strings
+        assert_eq!(
+            detect(Some("BPEE"), &[b"SRAM_V"]),
+            Some(BackupType::Flash(FlashChip::Macronix128))
+        );
+    }
+}
@@ -0,0 +1,83 @@
+/// Size of the BIOS area at 0x00000000
+pub const BIOS_SIZE: usize = 16 * 1024;
+
+/// Returns to the caller in ARM or Thumb, target of the SoundGetJumpList entries
+pub const SOUND_DRIVER_STUB: u32 = 0x78;
+
+/// Minimal open-source replacement for the GBA BIOS
+/// SWIs are executed in HLE by the CPU, the image only provides the exception vectors,
+/// a reset routine booting straight into the ROM and the IRQ dispatcher
+const REPLACEMENT_BIOS: &[(u32, &[u32])] = &[
+    // Exception vectors
+    (
+        0x0000,
+        &[
+            0xEA000006, // 00: b      20h                  ;Reset
+            0xEAFFFFFE, // 04: b      04h                  ;Undefined instruction
+            0xE1B0F00E, // 08: movs   r15,r14              ;SWI (handled in HLE)
+            0xEAFFFFFE, // 0C: b      0Ch                  ;Prefetch abort
+            0xEAFFFFFE, // 10: b      10h                  ;Data abort
+            0xEAFFFFFE, // 14: b      14h                  ;Reserved
+            0xEA000042, // 18: b      128h                 ;IRQ
+            0xE25EF004, // 1C: subs   r15,r14,4h           ;FIQ
+        ],
+    ),
+    // Reset: set up the IO and stacks as the original BIOS leaves them and boot the ROM,
+    // there is no logo to show
+    (
+        0x0020,
+        &[
+            0xE3A01301, // 20: mov    r1,4000000h
+            0xE3A00C02, // 24: mov    r0,200h
+            0xE1C108B8, // 28: strh   r0,[r1,88h]          ;SOUNDBIAS
+            0xE3A00902, // 2C: mov    r0,8000h
+            0xE2812C01, // 30: add    r2,r1,100h
+            0xE1C203B4, // 34: strh   r0,[r2,34h]          ;RCNT
+            0xE3A00001, // 38: mov    r0,1h
+            0xE5C10300, // 3C: strb   r0,[r1,300h]         ;POSTFLG
+            0xE3A000D2, // 40: mov    r0,0D2h              ;IRQ mode, IRQ/FIQ disabled
+            0xE121F000, // 44: msr    cpsr_c,r0
+            0xE59FD018, // 48: ldr    r13,=3007FA0h
+            0xE3A000D3, // 4C: mov    r0,0D3h              ;Supervisor mode
+            0xE121F000, // 50: msr    cpsr_c,r0
+            0xE59FD014, // 54: ldr    r13,=3007FE0h
+            0xE3A0001F, // 58: mov    r0,1Fh               ;System mode, IRQs enabled
+            0xE121F000, // 5C: msr    cpsr_c,r0
+            0xE59FD00C, // 60: ldr    r13,=3007F00h
+            0xE3A0F302, // 64: mov    r15,8000000h         ;Jump to ROM
+            0x03007FA0, // 68: SP_irq
+            0xE129F000, // 6C: msr    cpsr_fc,r0           ;Latched after the boot
+            0x03007FE0, // 70: SP_svc
+            0x03007F00, // 74: SP_sys
+            0xE12FFF1E, // 78: bx     r14                  ;Sound driver jump list entries
+        ],
+    ),
+    // IRQ dispatcher, same layout as the original BIOS
+    (
+        0x0128,
+        &[
+            0xE92D500F, // 128: stmfd  r13!,r0-r3,r12,r14 ;save registers to SP_irq
+            0xE3A00301, // 12C: mov    r0,4000000h         ;ptr+4 to 03FFFFFC (mirror of 03007FFC)
+            0xE28FE000, // 130: add    r14,r15,0h          ;retadr for USER handler $+8=138h
+            0xE510F004, // 134: ldr    r15,[r0,-4h]        ;jump to [03FFFFFC] USER handler
+            0xE8BD500F, // 138: ldmfd  r13!,r0-r3,r12,r14 ;restore registers from SP_irq
+            0xE25EF004, // 13C: subs   r15,r14,4h          ;return from IRQ (PC=LR-4, CPSR=SPSR)
+            0x00000000, // 140:
+            0xE55EC002, // 144: ldrb   r12,[r14,-2h]       ;Latched after an IRQ
+        ],
+    ),
+];
+
+/// Builds the 16 KB replacement BIOS image
+pub fn replacement_bios() -> Vec<u8> {
+    let mut bios = vec![0; BIOS_SIZE];
+
+    for (addr, code) in REPLACEMENT_BIOS {
+        for (i, opcode) in code.iter().enumerate() {
+            let offset = (*addr as usize) + i * 4;
+            bios[offset..offset + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+    }
+
+    bios
+}
@@ -1,17 +1,83 @@
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 
 use log::*;
 
 use crate::{
+    backup::{self, Backup, BackupType},
+    bios,
     debugger::{MgbaDebug, MGBA_REG_DEBUG_ENABLE, MGBA_REG_DEBUG_FLAGS},
+    decode::{self, ArmInstr, ThumbInstr},
+    gpio::{Gpio, Peripherals, RtcClock, TiltSensor, GPIO_DATA},
+    header::{self, RomHeader},
     keypad::Keypad,
     lcd::LCD,
+    scheduler::Scheduler,
     serial::Serial,
+    sound::Sound,
+    timer::Timer,
 };
 
+mod blocks;
+mod events;
+mod idle;
+mod io;
+mod pages;
+mod run;
+
+use blocks::BlockCache;
+use pages::{PageTable, Region};
+
+type ArmHandler = fn(&mut CPU, u32);
+type ThumbHandler = fn(&mut CPU, u16);
+
 const ROM_WRITING: bool = false;
 const INTERNAL_PANIC: bool = false;
 
+/// IRQ flags acknowledged by the user IRQ handler for IntrWait (mirror at 0x03FFFFF8)
+pub const BIOS_IF: u32 = 0x03007FF8;
+
+/// Pointer to the work area of the BIOS sound driver
+pub const BIOS_SOUND_AREA: u32 = 0x03007FF0;
+
+/// Number of function pointers written by SoundGetJumpList
+const SOUND_JUMP_LIST_ENTRIES: u32 = 0x24;
+
+/// Last fetched BIOS opcode after the boot and after a SWI, returned by protected BIOS reads
+pub const BIOS_LATCH_STARTUP: u32 = 0xE129F000;
+pub const BIOS_LATCH_SWI: u32 = 0xE3A02004;
+
+/// SOUNDBIAS and the delay between the steps of the SoundBias SWI
+const REG_SOUNDBIAS: u32 = 0x088;
+const SOUND_BIAS_STEP_CYCLES: usize = 32;
+
+/// Sine table of the BIOS (1.1.14 fixed point), a full turn in 256 steps
+const BIOS_SINE_TABLE: [i16; 256] = [
+    0x0000, 0x0192, 0x0323, 0x04B5, 0x0645, 0x07D5, 0x0964, 0x0AF1, 0x0C7C, 0x0E05, 0x0F8C, 0x1111,
+    0x1294, 0x1413, 0x1590, 0x1709, 0x187D, 0x19EF, 0x1B5D, 0x1CC6, 0x1E2B, 0x1F8B, 0x20E7, 0x223D,
+    0x238E, 0x24DA, 0x261F, 0x275F, 0x2899, 0x29CD, 0x2AFA, 0x2C21, 0x2D41, 0x2E5A, 0x2F6B, 0x3076,
+    0x3179, 0x3274, 0x3367, 0x3453, 0x3536, 0x3612, 0x36E5, 0x37AF, 0x3871, 0x392A, 0x39DA, 0x3A82,
+    0x3B20, 0x3BB6, 0x3C42, 0x3CC5, 0x3D3E, 0x3DAE, 0x3E14, 0x3E71, 0x3EC5, 0x3F0E, 0x3F4E, 0x3F84,
+    0x3FB1, 0x3FD3, 0x3FEC, 0x3FFB, 0x4000, 0x3FFB, 0x3FEC, 0x3FD3, 0x3FB1, 0x3F84, 0x3F4E, 0x3F0E,
+    0x3EC5, 0x3E71, 0x3E14, 0x3DAE, 0x3D3E, 0x3CC5, 0x3C42, 0x3BB6, 0x3B20, 0x3A82, 0x39DA, 0x392A,
+    0x3871, 0x37AF, 0x36E5, 0x3612, 0x3536, 0x3453, 0x3367, 0x3274, 0x3179, 0x3076, 0x2F6B, 0x2E5A,
+    0x2D41, 0x2C21, 0x2AFA, 0x29CD, 0x2899, 0x275F, 0x261F, 0x24DA, 0x238E, 0x223D, 0x20E7, 0x1F8B,
+    0x1E2B, 0x1CC6, 0x1B5D, 0x19EF, 0x187D, 0x1709, 0x1590, 0x1413, 0x1294, 0x1111, 0x0F8C, 0x0E05,
+    0x0C7C, 0x0AF1, 0x0964, 0x07D5, 0x0645, 0x04B5, 0x0323, 0x0192, 0x0000, -0x0192, -0x0323,
+    -0x04B5, -0x0645, -0x07D5, -0x0964, -0x0AF1, -0x0C7C, -0x0E05, -0x0F8C, -0x1111, -0x1294,
+    -0x1413, -0x1590, -0x1709, -0x187D, -0x19EF, -0x1B5D, -0x1CC6, -0x1E2B, -0x1F8B, -0x20E7,
+    -0x223D, -0x238E, -0x24DA, -0x261F, -0x275F, -0x2899, -0x29CD, -0x2AFA, -0x2C21, -0x2D41,
+    -0x2E5A, -0x2F6B, -0x3076, -0x3179, -0x3274, -0x3367, -0x3453, -0x3536, -0x3612, -0x36E5,
+    -0x37AF, -0x3871, -0x392A, -0x39DA, -0x3A82, -0x3B20, -0x3BB6, -0x3C42, -0x3CC5, -0x3D3E,
+    -0x3DAE, -0x3E14, -0x3E71, -0x3EC5, -0x3F0E, -0x3F4E, -0x3F84, -0x3FB1, -0x3FD3, -0x3FEC,
+    -0x3FFB, -0x4000, -0x3FFB, -0x3FEC, -0x3FD3, -0x3FB1, -0x3F84, -0x3F4E, -0x3F0E, -0x3EC5,
+    -0x3E71, -0x3E14, -0x3DAE, -0x3D3E, -0x3CC5, -0x3C42, -0x3BB6, -0x3B20, -0x3A82, -0x39DA,
+    -0x392A, -0x3871, -0x37AF, -0x36E5, -0x3612, -0x3536, -0x3453, -0x3367, -0x3274, -0x3179,
+    -0x3076, -0x2F6B, -0x2E5A, -0x2D41, -0x2C21, -0x2AFA, -0x29CD, -0x2899, -0x275F, -0x261F,
+    -0x24DA, -0x238E, -0x223D, -0x20E7, -0x1F8B, -0x1E2B, -0x1CC6, -0x1B5D, -0x19EF, -0x187D,
+    -0x1709, -0x1590, -0x1413, -0x1294, -0x1111, -0x0F8C, -0x0E05, -0x0C7C, -0x0AF1, -0x0964,
+    -0x07D5, -0x0645, -0x04B5, -0x0323, -0x0192,
+];
+
 pub trait MMU {
     fn read_u8(&mut self, intern: bool, addr: u32) -> u8;
     fn read_u16(&mut self, intern: bool, addr: u32) -> u16;
@@ -33,18 +99,33 @@ pub struct CPU {
     pub regs_und: [u32; 2],   // R13, R14
     pub ram_work1: [u8; 256 * 1024],
     pub ram_work2: [u8; 32 * 1024],
-    pub ram_sram: [u8; 64 * 1024],
-    pub ram_palette: Arc<Mutex<Vec<u8>>>,
-    pub ram_video: Arc<Mutex<Vec<u8>>>,
-    pub ram_obj_attr: Arc<Mutex<Vec<u8>>>,
+    pub backup: Backup,
+    /// Backup type used instead of the detected one by `load_rom`
+    pub backup_override: Option<BackupType>,
+    pub gpio: Gpio,
+    /// Peripherals connected instead of the detected ones by `load_rom`
+    pub peripherals_override: Option<Peripherals>,
+    pub rtc_clock: RtcClock,
+    /// Light level of the solar sensor, 0 (dark) to 255 (direct sunlight)
+    pub light_level: u8,
+    pub tilt: Option<TiltSensor>,
+    pub ram_palette: Vec<u8>,
+    pub ram_video: Vec<u8>,
+    pub ram_obj_attr: Vec<u8>,
     pub panic: bool,
     pub rom: Vec<u8>,
+    /// Header of the loaded ROM, None if the ROM is too small to have one
+    pub header: Option<RomHeader>,
     pub bios: Vec<u8>,
+    /// Last opcode prefetched from the BIOS, outside the BIOS it is read instead of the BIOS
+    pub bios_latch: u32,
     pub mem_ptr: u32,
 
     // IO Registers
     pub dma: [u8; 4 * 3 * 4],
-    pub timers: [u8; 2 * 2 * 4],
+    pub dma_src: [u32; 4],  // Internal source address, latched on enable
+    pub dma_dest: [u32; 4], // Internal destination address, latched on enable
+    pub timers: [Timer; 4],
     pub io_waitcnt: u16,
 
     // IO -- Interrupt Control
@@ -56,23 +137,55 @@ pub struct CPU {
     pub keypad: Keypad,
     pub lcd: LCD,
     pub serial: Serial,
+    pub sound: Sound,
+
+    // IO -- Power-Down Control
+    pub io_postflg: u8,
 
     pub halt: bool,
-    pub io_bios_if: u16,
+    pub stop: bool,
+    pub intr_wait: Option<u16>,
 
     pub cycle_count: usize,
+    pub scheduler: Scheduler,
+    pub lcd_paused: bool,
     pub mgba_debug: MgbaDebug,
+
+    // Idle loop detection
+    pub idle_loop_skip: bool,
+    pub idle_skipped_cycles: usize,
+    idle_timer_read: bool,
+    idle_loops: HashMap<(u32, u32, bool), bool>,
+
+    // Memory map of RAM and ROM pages
+    pages: PageTable,
+
+    // Cached basic blocks
+    pub block_cache: bool,
+    blocks: BlockCache,
+
+    // Decode tables of instruction handlers
+    arm_table: Vec<ArmHandler>,
+    thumb_table: Vec<ThumbHandler>,
 }
 
 impl MMU for CPU {
     fn read_u8(&mut self, intern: bool, addr: u32) -> u8 {
         let addr = addr & 0x0FFFFFFF;
 
+        if let Some((region, offset)) = self.pages.read(addr, 1) {
+            if intern {
+                self.mem_ptr = addr;
+            }
+            return self.region(region)[offset];
+        }
+
         if intern {
             self.mem_ptr = addr;
 
-            if addr < 0x00003FFF {
-                panic!("In bios `{:08X}`", addr);
+            // Only the BIOS itself can read the BIOS, otherwise the latched opcode is read
+            if addr <= 0x00003FFF && self.get_program_counter() > 0x00003FFF {
+                return (self.bios_latch >> ((addr & 0x3) * 8)) as u8;
             }
         }
 
@@ -88,47 +201,25 @@ impl MMU for CPU {
                 self.ram_work2[offset]
             }
             0x04000000..=0x040003FE => {
-                if intern {
-                    warn!("Read8 from IO register `{:08X}`", addr);
-                }
-
-                let io_addr = addr & 0x3FF;
-
-                match io_addr {
-                    0x00..=0x56 => self.lcd.registers[io_addr],
-                    // Sound => 0x60..=0xA7
-                    0x60..=0xA7 => {
-                        if intern {
-                            warn!("Read8 from Sound IO `{:08X}`", addr);
-                        }
-                        0
-                    }
-                    0xB0..=0xDE => {
-                        let offset = (io_addr - 0xB0) as usize;
-                        self.dma[offset]
-                    }
-                    0x134 => (self.serial.rcnt & 0xFF) as u8,
-                    0x135 => ((self.serial.rcnt >> 8) & 0xFF) as u8,
-                    _ => {
-                        if intern {
-                            panic!(
-                                "Read8 from unimplemented IO register `{:08X}` (PC={:08X})",
-                                addr,
-                                self.get_program_counter()
-                            )
-                        }
-                        0
-                    }
-                }
+                let val = self.io_read_u16(intern, (addr & 0x3FE) as u32);
+                (val >> ((addr & 0x1) * 8)) as u8
             }
             0x04FFF600..=0x04FFF800 => self.mgba_debug.read_u8(addr - 0x04FFF600),
-            0x05000000..=0x050003FF => self.ram_palette.lock().unwrap()[offset],
-            0x06000000..=0x06017FFF => self.ram_video.lock().unwrap()[offset],
-            0x07000000..=0x070003FF => self.ram_obj_attr.lock().unwrap()[offset],
+            0x05000000..=0x050003FF => self.ram_palette[offset],
+            0x06000000..=0x06017FFF => self.ram_video[offset],
+            0x07000000..=0x070003FF => self.ram_obj_attr[offset],
+            gpio if self.is_gpio(gpio as u32) => match self.gpio.read_u16(offset as u32) {
+                Some(val) => (val >> ((addr & 0x1) * 8)) as u8,
+                None => self.rom[offset],
+            },
             0x08000000..=0x09FFFFFC | 0x0A000000..=0x0BFFFFFC | 0x0C000000..=0x0DFFFFFC => {
                 self.rom[offset]
             }
-            0x0E000000..=0x0E00FFFF => self.ram_sram[offset],
+            tilt if self.is_tilt(tilt as u32) => self
+                .tilt
+                .as_ref()
+                .map_or(0xFF, |tilt| tilt.read_u8(addr as u32)),
+            0x0E000000..=0x0FFFFFFF => self.backup.read_u8(addr as u32),
             _ => {
                 if INTERNAL_PANIC {
                     error!(
@@ -150,7 +241,25 @@ impl MMU for CPU {
     }
 
     fn read_u16(&mut self, intern: bool, addr: u32) -> u16 {
-        match addr {
+        if let Some((region, offset)) = self.pages.read(addr, 2) {
+            if intern {
+                self.mem_ptr = addr & 0x0FFFFFFF;
+            }
+            let bytes = &self.region(region)[offset..offset + 2];
+            return u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        match addr & 0x0FFFFFFF {
+            0x04000000..=0x040003FE => {
+                if intern {
+                    self.mem_ptr = addr & 0x0FFFFFFF;
+                }
+
+                self.io_read_u16(intern, addr & 0x3FE)
+            }
+            eeprom if self.is_eeprom(eeprom) => self.backup.eeprom_read(),
+            // 8 bit bus, the byte is replicated
+            0x0E000000..=0x0FFFFFFF => (self.read_u8(intern, addr) as u16) * 0x0101,
             _ => {
                 let low = self.read_u8(intern, addr) as u16;
                 let high = self.read_u8(intern, addr + 1) as u16;
@@ -163,87 +272,38 @@ impl MMU for CPU {
     fn read_u32(&mut self, intern: bool, addr: u32) -> u32 {
         let addr = addr & 0x0FFFFFFF;
 
+        if let Some((region, offset)) = self.pages.read(addr, 4) {
+            if intern {
+                self.mem_ptr = addr;
+            }
+            let bytes = &self.region(region)[offset..offset + 4];
+            return u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
         if intern {
             self.mem_ptr = addr;
 
-            if addr < 0x00003FFF {
-                panic!(
-                    "[0x{:08X}] In bios `{:08X}`",
-                    self.get_program_counter(),
-                    addr
-                );
+            // Only the BIOS itself can read the BIOS, otherwise the latched opcode is read
+            if addr <= 0x00003FFF && self.get_program_counter() > 0x00003FFF {
+                return self.bios_latch;
             }
         }
 
         let offset = (addr & 0x00FFFFFF) as usize;
 
         match addr {
-            0x03007FF8 => self.io_bios_if as u32,
             0x04000000..=0x040003FE => {
-                if intern {
-                    warn!("Read32 from IO register `{:08X}`", addr);
-                }
+                let io_addr = addr & 0x3FC;
+                let low = self.io_read_u16(intern, io_addr) as u32;
+                let high = self.io_read_u16(intern, io_addr + 2) as u32;
 
-                let io_addr = addr & 0x3FF;
+                (high << 16) | low
+            }
+            gpio if self.is_gpio(gpio) => {
+                let low = self.read_u16(intern, addr) as u32;
+                let high = self.read_u16(intern, addr + 2) as u32;
 
-                match io_addr {
-                    0x00..=0x54 => {
-                        let offset = io_addr as usize;
-                        ((self.lcd.registers[offset + 3] as u32) << 24)
-                            | ((self.lcd.registers[offset + 2] as u32) << 16)
-                            | ((self.lcd.registers[offset + 1] as u32) << 8)
-                            | (self.lcd.registers[offset] as u32)
-                    }
-                    0x60..=0xA4 => {
-                        warn!("Read32 from Sound IO `{:08X}`", addr);
-                        0
-                    }
-                    0xB0..=0xDC => {
-                        let offset = (io_addr - 0xB0) as usize;
-                        ((self.dma[offset + 3] as u32) << 24)
-                            | ((self.dma[offset + 2] as u32) << 16)
-                            | ((self.dma[offset + 1] as u32) << 8)
-                            | (self.dma[offset] as u32)
-                    }
-                    0xDE => {
-                        let offset = (io_addr - 0xB0) as usize;
-                        ((self.dma[offset + 1] as u32) << 8) | (self.dma[offset] as u32)
-                    }
-                    // Timers
-                    0x100..=0x10E => {
-                        let offset = (io_addr - 0x100) as usize;
-                        ((self.timers[offset + 3] as u32) << 24)
-                            | ((self.timers[offset + 2] as u32) << 16)
-                            | ((self.timers[offset + 1] as u32) << 8)
-                            | (self.timers[offset] as u32)
-                    }
-                    0x120..=0x12A => self.serial.read_u32(io_addr),
-                    // Keypad
-                    0x130 => {
-                        warn!("Reading from keyinput: {:010b}", self.keypad.keyinput);
-                        self.keypad.keyinput as u32
-                    }
-                    0x132 => self.keypad.keycnt as u32,
-                    // Serial (2)
-                    0x134..=0x158 => self.serial.read_u32(io_addr),
-                    0x200 => {
-                        let ie_bytes = self.io_ie.to_le_bytes();
-                        let if_bytes = self.io_if.to_le_bytes();
-                        ((if_bytes[1] as u32) << 24)
-                            | ((if_bytes[0] as u32) << 16)
-                            | ((ie_bytes[1] as u32) << 8)
-                            | (ie_bytes[0] as u32)
-                    }
-                    0x202 => self.io_if as u32,
-                    0x204 => self.io_waitcnt as u32,
-                    0x208 => self.io_ime as u32,
-                    _ => {
-                        if intern {
-                            panic!("Read32 from unimplemented IO register `{:08X}`", addr)
-                        }
-                        0
-                    }
-                }
+                (high << 16) | low
             }
             0x08000000..=0x09FFFFFC | 0x0A000000..=0x0BFFFFFC | 0x0C000000..=0x0DFFFFFC => {
                 ((self.rom[offset + 3] as u32) << 24)
@@ -251,6 +311,7 @@ impl MMU for CPU {
                     | ((self.rom[offset + 1] as u32) << 8)
                     | (self.rom[offset] as u32)
             }
+            0x0E000000..=0x0FFFFFFF => (self.read_u8(intern, addr) as u32) * 0x01010101,
             _ => {
                 let b0 = self.read_u8(intern, addr + 0) as u32;
                 let b1 = self.read_u8(intern, addr + 1) as u32;
@@ -265,6 +326,15 @@ impl MMU for CPU {
     fn write_u8(&mut self, intern: bool, addr: u32, val: u8) {
         let addr = addr & 0x0FFFFFFF;
 
+        if let Some((region, offset)) = self.pages.write(addr, 1) {
+            if intern {
+                self.mem_ptr = addr;
+            }
+            self.region_mut(region)[offset] = val;
+            self.blocks.written(region, offset, 1);
+            return;
+        }
+
         if intern {
             self.mem_ptr = addr;
         }
@@ -280,40 +350,38 @@ impl MMU for CPU {
                 );
                 self.panic = true
             } //self.bios[addr] = val,
-            0x02000000..=0x0203FFFF => self.ram_work1[offset] = val,
-            0x03000000..=0x03007FFF => self.ram_work2[offset] = val,
-            0x03FFFF00..=0x03FFFFFF => self.ram_work2[(offset & 0xFF) | 0x7F00] = val,
+            0x02000000..=0x0203FFFF => {
+                self.ram_work1[offset] = val;
+                self.blocks.written(Region::WorkRam1, offset, 1);
+            }
+            0x03000000..=0x03007FFF => {
+                self.ram_work2[offset] = val;
+                self.blocks.written(Region::WorkRam2, offset, 1);
+            }
+            0x03FFFF00..=0x03FFFFFF => {
+                let offset = (offset & 0xFF) | 0x7F00;
+                self.ram_work2[offset] = val;
+                self.blocks.written(Region::WorkRam2, offset, 1);
+            }
             0x04000000..=0x040003FE => {
-                warn!("Write8 to IO register `{:08X} = {:02X}`", addr, val);
-
-                let io_addr = addr & 0x3FF;
-
-                match io_addr {
-                    0x00..=0x55 => self.lcd.registers[io_addr] = val,
-                    0x60..=0xA7 => {
-                        warn!("Write8 to Sound IO `{:08X}` => {:02X}", addr, val);
-                    }
-                    0xB0..=0xDE => {
-                        let offset = (io_addr - 0xB0) as usize;
-                        self.dma[offset] = val;
-                    }
-                    0x120..=0x12B => self.serial.write_u8(io_addr, val),
-                    0x134..=0x159 => self.serial.write_u8(io_addr, val),
-                    0x208 => self.io_ime = val,
-                    _ => {
-                        if intern {
-                            panic!(
-                                "Write8 to unimplemented IO register `{:08X}` => {:02X}",
-                                addr, val
-                            )
-                        }
-                    }
-                }
+                let shift = (addr & 0x1) * 8;
+                self.io_write_u16(
+                    intern,
+                    (addr & 0x3FE) as u32,
+                    (val as u16) << shift,
+                    0xFF << shift,
+                );
             }
             0x04FFF600..=0x04FFF800 => self.mgba_debug.write_u8(addr - 0x04FFF600, val),
-            0x05000000..=0x050003FF => self.ram_palette.lock().unwrap()[offset] = val,
-            0x06000000..=0x06017FFF => self.ram_video.lock().unwrap()[offset] = val,
-            0x07000000..=0x070003FF => self.ram_obj_attr.lock().unwrap()[offset] = val,
+            0x05000000..=0x050003FF => self.ram_palette[offset] = val,
+            0x06000000..=0x06017FFF => self.ram_video[offset] = val,
+            0x07000000..=0x070003FF => self.ram_obj_attr[offset] = val,
+            // The registers are in the low byte
+            gpio if self.is_gpio(gpio as u32) => {
+                if (addr & 0x1) == 0 {
+                    self.gpio.write_u16(offset as u32, val as u16);
+                }
+            }
             0x08000000..=0x09FFFFFC | 0x0A000000..=0x0BFFFFFC | 0x0C000000..=0x0DFFFFFC => {
                 if ROM_WRITING {
                     warn!("Write8 to ROM `{:08X} => {:02X}`", addr, val);
@@ -323,7 +391,12 @@ impl MMU for CPU {
                     self.panic = true;
                 }
             }
-            0x0E000000..=0x0E00FFFF => self.ram_sram[offset] = val,
+            tilt if self.is_tilt(tilt as u32) => {
+                if let Some(tilt) = &mut self.tilt {
+                    tilt.write_u8(addr as u32, val);
+                }
+            }
+            0x0E000000..=0x0FFFFFFF => self.backup.write_u8(addr as u32, val),
             _ => {
                 if INTERNAL_PANIC {
                     error!(
@@ -344,10 +417,32 @@ impl MMU for CPU {
     }
 
     fn write_u16(&mut self, intern: bool, addr: u32, val: u16) {
+        if let Some((region, offset)) = self.pages.write(addr, 2) {
+            if intern {
+                self.mem_ptr = addr & 0x0FFFFFFF;
+            }
+            self.region_mut(region)[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+            self.blocks.written(region, offset, 2);
+            return;
+        }
+
         let high = (val >> 8) as u8;
         let low = (val & 0xFF) as u8;
 
-        match addr as usize {
+        match addr & 0x0FFFFFFF {
+            0x04000000..=0x040003FE => {
+                if intern {
+                    self.mem_ptr = addr & 0x0FFFFFFF;
+                }
+
+                self.io_write_u16(intern, addr & 0x3FE, val, 0xFFFF);
+            }
+            eeprom if self.is_eeprom(eeprom) => self.backup.eeprom_write(val),
+            // 8 bit bus, only the byte selected by the address is written
+            0x0E000000..=0x0FFFFFFF => {
+                let shift = (addr & 0x1) * 8;
+                self.write_u8(intern, addr, (val >> shift) as u8);
+            }
             _ => {
                 self.write_u8(intern, addr + 0, low);
                 self.write_u8(intern, addr + 1, high);
@@ -358,6 +453,15 @@ impl MMU for CPU {
     fn write_u32(&mut self, intern: bool, addr: u32, val: u32) {
         let addr = addr & 0x0FFFFFFF;
 
+        if let Some((region, offset)) = self.pages.write(addr, 4) {
+            if intern {
+                self.mem_ptr = addr;
+            }
+            self.region_mut(region)[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+            self.blocks.written(region, offset, 4);
+            return;
+        }
+
         if intern {
             self.mem_ptr = addr;
 
@@ -373,96 +477,14 @@ impl MMU for CPU {
         let b0 = (val & 0xFF) as u8;
 
         match addr {
-            0x03007FF8 => {
-                let mask = (val & 0xFFFF) as u16;
-                self.io_bios_if &= !mask;
-                if intern {
-                    warn!("Clearing flags={mask:04X} in BIOS_IF");
-                }
-            }
             0x04000000..=0x040003FE => {
-                if intern {
-                    warn!("Write32 to IO register `{:08X}` => {:08X}", addr, val)
-                }
-
-                let io_addr = addr & 0x3FF;
-
-                match io_addr {
-                    0x00..=0x54 => {
-                        info!(
-                            "Write to LCD register `{:X}` at PC={:08X}",
-                            offset,
-                            self.get_program_counter()
-                        );
-                        self.lcd.registers[offset + 3] = ((val >> 24) & 0xFF) as u8;
-                        self.lcd.registers[offset + 2] = ((val >> 16) & 0xFF) as u8;
-                        self.lcd.registers[offset + 1] = ((val >> 8) & 0xFF) as u8;
-                        self.lcd.registers[offset] = (val & 0xFF) as u8;
-                    }
-                    0x60..=0xA4 => {
-                        warn!("Write32 to Sound IO `{:08X}` => {:08X}", addr, val);
-                    }
-                    0xB0..=0xDC => {
-                        let offset = (io_addr - 0xB0) as usize;
-                        self.dma[offset + 3] = ((val >> 24) & 0xFF) as u8;
-                        self.dma[offset + 2] = ((val >> 16) & 0xFF) as u8;
-                        self.dma[offset + 1] = ((val >> 8) & 0xFF) as u8;
-                        self.dma[offset + 0] = ((val >> 0) & 0xFF) as u8;
-                    }
-                    0xDE => {
-                        let offset = (io_addr - 0xB0) as usize;
-                        self.dma[offset + 1] = ((val >> 8) & 0xFF) as u8;
-                        self.dma[offset + 0] = ((val >> 0) & 0xFF) as u8;
-                    }
-                    // Timers
-                    0x100..=0x10E => {
-                        let offset = (io_addr - 0x100) as usize;
-                        self.timers[offset + 3] = b3;
-                        self.timers[offset + 2] = b2;
-                        self.timers[offset + 1] = b1;
-                        self.timers[offset] = b0;
-                    }
-                    // Serial (2)
-                    0x120..=0x12A => self.serial.write_u32(io_addr, val),
-                    0x134..=0x158 => self.serial.write_u32(io_addr, val),
-                    0x200 => {
-                        self.io_ie = (val & 0xFFFF) as u16;
-                        warn!(
-                            "Write32 to Interrupt Enable Register `{:08X}` => {:08X}",
-                            addr, val
-                        );
-                    }
-                    0x202 => {
-                        let val = (val & 0xFFFF) as u16;
-                        self.io_if &= !(val);
-                        warn!(
-                            "Write32 to Interrupt Request Flags Register `{:08X}` => {:08X}",
-                            addr, val
-                        );
-                    }
-                    0x204 => {
-                        self.io_waitcnt = (val & 0xFFFF) as u16;
-                        warn!(
-                            "Write32 to GamePak Waitstate Control `{:08X}` => {:08X}",
-                            addr, val
-                        );
-                    }
-                    0x208 => {
-                        self.io_ime = (val & 0xFF) as u8;
-                        warn!(
-                            "Write32 to Interrupt Master Enable Register `{:08X}` => {:08X}",
-                            addr, val
-                        );
-                    }
-                    _ => {
-                        if intern {
-                            panic!(
-                                "Write32 to unimplemented IO register `{:08X}` => {:08X}",
-                                addr, val
-                            )
-                        }
-                    }
-                }
+                let io_addr = addr & 0x3FC;
+                self.io_write_u16(intern, io_addr, (val & 0xFFFF) as u16, 0xFFFF);
+                self.io_write_u16(intern, io_addr + 2, (val >> 16) as u16, 0xFFFF);
+            }
+            gpio if self.is_gpio(gpio) => {
+                self.write_u16(intern, addr, (val & 0xFFFF) as u16);
+                self.write_u16(intern, addr + 2, (val >> 16) as u16);
             }
             0x08000000..=0x09FFFFFC | 0x0A000000..=0x0BFFFFFC | 0x0C000000..=0x0DFFFFFC => {
                 if ROM_WRITING {
@@ -479,6 +501,10 @@ impl MMU for CPU {
                     self.panic = true;
                 }
             }
+            0x0E000000..=0x0FFFFFFF => {
+                let shift = (addr & 0x3) * 8;
+                self.write_u8(intern, addr, (val >> shift) as u8);
+            }
             _ => {
                 self.write_u8(intern, addr + 0, b0);
                 self.write_u8(intern, addr + 1, b1);
@@ -506,7 +532,7 @@ impl MMU for CPU {
             | (0x08000000..=0x09FFFFFC)
             | (0x0A000000..=0x0BFFFFFC)
             | (0x0C000000..=0x0DFFFFFC)
-            | (0x0E000000..=0x0E00FFFF) => true,
+            | (0x0E000000..=0x0FFFFFFF) => true,
             _ => false,
         }
     }
@@ -514,7 +540,7 @@ impl MMU for CPU {
 
 pub const ARM_MASK_MUL_CLR: u32 = 0xFC00060;
 pub const ARM_MASK_MUL_SET: u32 = 0x0000090;
-pub const ARM_MASK_MUL_LONG_CLR: u32 = 0xF000090;
+pub const ARM_MASK_MUL_LONG_CLR: u32 = 0xF000060;
 pub const ARM_MASK_MUL_LONG_SET: u32 = 0x0800090;
 pub const ARM_MASK_SNGL_SWP_CLR: u32 = 0xEB00F60;
 pub const ARM_MASK_SNGL_SWP_SET: u32 = 0x1000090;
@@ -584,15 +610,11 @@ pub const IRQ_DEBUG1: u16 = 1 << 14;
 pub const IRQ_DEBUG2: u16 = 1 << 15;
 
 impl CPU {
-    pub fn new(
-        vram: &Arc<Mutex<Vec<u8>>>,
-        palette: &Arc<Mutex<Vec<u8>>>,
-        oam: &Arc<Mutex<Vec<u8>>>,
-    ) -> Self {
+    pub fn new() -> Self {
         Self {
             mem_ptr: 0,
             registers: [0; 16],
-            reg_cpsr: STATUS_FLAG_F | STATUS_FLAG_I | (MODE_SUPERVISOR as u32),
+            reg_cpsr: STATUS_FLAG_F | STATUS_FLAG_I | 0x10 | (MODE_SUPERVISOR as u32),
             regs_spsr: [0; 16],
             regs_fiq: [0; 7],
             regs_svc: [0; 2],
@@ -601,15 +623,25 @@ impl CPU {
             regs_und: [0; 2],
             ram_work1: [0; 256 * 1024],
             ram_work2: [0; 32 * 1024],
-            ram_palette: Arc::clone(palette),
-            ram_video: Arc::clone(vram),
-            ram_obj_attr: Arc::clone(oam),
-            ram_sram: [0; 64 * 1024],
+            ram_palette: vec![0; 1 * 1024],
+            ram_video: vec![0; 96 * 1024],
+            ram_obj_attr: vec![0; 1 * 1024],
+            backup: Backup::new(BackupType::Sram),
+            backup_override: None,
+            gpio: Gpio::new(Peripherals::default(), RtcClock::Host, 0),
+            peripherals_override: None,
+            rtc_clock: RtcClock::Host,
+            light_level: 0,
+            tilt: None,
             panic: false,
             rom: Vec::new(),
-            bios: Vec::new(),
+            header: None,
+            bios: bios::replacement_bios(),
+            bios_latch: 0,
             dma: [0; 4 * 3 * 4],
-            timers: [0; 2 * 2 * 4],
+            dma_src: [0; 4],
+            dma_dest: [0; 4],
+            timers: [Timer::new(); 4],
             io_waitcnt: 0,
             io_ie: 0,
             io_ime: 0,
@@ -617,15 +649,107 @@ impl CPU {
             keypad: Keypad::new(),
             lcd: LCD::new(),
             serial: Serial::new(),
+            sound: Sound::new(),
+            io_postflg: 0,
             halt: false,
-            io_bios_if: 0,
+            stop: false,
+            intr_wait: None,
             cycle_count: 0,
+            scheduler: Scheduler::new(),
+            lcd_paused: false,
             mgba_debug: MgbaDebug::new(),
+            idle_loop_skip: true,
+            idle_skipped_cycles: 0,
+            idle_timer_read: false,
+            idle_loops: HashMap::new(),
+            pages: PageTable::new(0),
+            block_cache: false,
+            blocks: BlockCache::new(),
+            arm_table: decode::arm_table(Self::arm_handler),
+            thumb_table: decode::thumb_table(Self::thumb_handler),
         }
     }
 
     pub fn load_rom(&mut self, rom: &Vec<u8>) {
         self.rom = rom.to_vec();
+        self.idle_loops.clear();
+        self.blocks.clear();
+
+        let previous_header = std::mem::replace(&mut self.header, RomHeader::parse(&self.rom));
+        match &self.header {
+            Some(header) => {
+                info!("ROM {}", header);
+                for issue in header.validate(&self.rom) {
+                    warn!("ROM header: {}", issue);
+                }
+            }
+            None => warn!("ROM is too small to have a header"),
+        }
+
+        // The ROM is scanned once for the ID strings of both detections
+        let game_code = self.header.as_ref().map(|header| header.game_code.as_str());
+        let id_strings = header::library_id_strings(&self.rom);
+
+        let peripherals = self
+            .peripherals_override
+            .unwrap_or_else(|| Peripherals::detect(game_code, &id_strings));
+        info!("Peripherals {:?}", peripherals);
+        self.gpio = Gpio::new(peripherals, self.rtc_clock, self.light_level);
+        self.tilt = peripherals.tilt.then(TiltSensor::new);
+
+        let kind = match self.backup_override {
+            Some(kind) => kind,
+            None => backup::detect(game_code, &id_strings).unwrap_or(BackupType::Sram),
+        };
+        info!("Backup type {:?}", kind);
+
+        // Keep the backup contents when reloading the same game, e.g. on a reset
+        // Another game with the same backup type gets an erased chip
+        let same_game = self.header.is_some() && self.header == previous_header;
+        match same_game && kind == self.backup.kind {
+            false => self.set_backup(kind),
+            true => self.map_pages(),
+        }
+    }
+
+    /// Replaces the backup memory of the cartridge with an erased `kind` chip
+    pub fn set_backup(&mut self, kind: BackupType) {
+        self.backup = Backup::new(kind);
+        self.map_pages();
+    }
+
+    /// Maps the memory pages, EEPROM and GPIO accesses take the slow path
+    fn map_pages(&mut self) {
+        self.pages = PageTable::new(self.rom.len());
+
+        if self.backup.kind == BackupType::Eeprom {
+            self.pages.unmap(0x0D000000, 0x01000000);
+        }
+
+        if self.gpio.connected() {
+            self.pages.unmap(0x08000000 + GPIO_DATA, 6);
+        }
+    }
+
+    /// Tilt sensor registers are on the SRAM bus at 0x0E008000-0x0E0085FF
+    fn is_tilt(&self, addr: u32) -> bool {
+        self.tilt.is_some()
+            && (0x0E000000..=0x0FFFFFFF).contains(&(addr & 0x0FFFFFFF))
+            && TiltSensor::is_register(addr)
+    }
+
+    /// GPIO registers are in the ROM area at 0x080000C4-0x080000C9
+    fn is_gpio(&self, addr: u32) -> bool {
+        self.gpio.connected() && (0x080000C4..=0x080000C9).contains(&(addr & 0x0FFFFFFF))
+    }
+
+    /// EEPROM is mapped at 0x0D000000, only at 0x0DFFFF00 with ROMs over 16 MB
+    fn is_eeprom(&self, addr: u32) -> bool {
+        let addr = addr & 0x0FFFFFFF;
+
+        self.backup.kind == BackupType::Eeprom
+            && (0x0D000000..=0x0DFFFFFF).contains(&addr)
+            && (self.rom.len() <= 0x01000000 || addr >= 0x0DFFFF00)
     }
 
     pub fn load_bios(&mut self, bios: &Vec<u8>) {
@@ -685,31 +809,116 @@ impl CPU {
         }
     }
 
-    pub fn reset(&mut self) {
+    /// Power-on reset, the BIOS runs from the reset vector and boots the ROM
+    pub fn reset_bios(&mut self) {
         self.registers = [0; 16];
-        self.reg_cpsr = STATUS_FLAG_F | STATUS_FLAG_I | (MODE_SUPERVISOR as u32);
+        self.reg_cpsr = STATUS_FLAG_F | STATUS_FLAG_I | 0x10 | (MODE_SUPERVISOR as u32);
         self.regs_spsr = [0; 16];
+        self.regs_fiq = [0; 7];
+        self.regs_svc = [0; 2];
+        self.regs_abt = [0; 2];
+        self.regs_irq = [0; 2];
+        self.regs_und = [0; 2];
         self.dma = [0; 48];
+        self.dma_src = [0; 4];
+        self.dma_dest = [0; 4];
+        self.timers = [Timer::new(); 4];
+        self.io_waitcnt = 0;
+
+        // Interrupts are off until the ROM enables them
+        self.io_ime = 0;
+        self.io_ie = 0;
+        self.io_if = 0;
+
+        // Held buttons stay held, only the interrupt control is cleared
+        self.keypad.keycnt = 0;
+        self.lcd.reset();
+        self.sound.reset();
+        self.serial.rcnt = 0;
+        self.io_postflg = 0;
+
+        self.bios_latch = 0;
+        self.set_program_counter(0x00000000);
 
-        self.set_program_counter(0x08000000);
+        // Clear panic flag
+        self.panic = false;
+        self.halt = false;
+        self.stop = false;
+        self.intr_wait = None;
+
+        // Clear cycle counter, restart the LCD from the first scanline
+        self.cycle_count = 0;
+        self.scheduler.reset();
+        self.schedule_lcd();
+        self.schedule_audio();
+    }
+
+    /// Reset to the state left by the BIOS when it jumps to the ROM, skipping the boot logo
+    pub fn reset(&mut self) {
+        self.reset_bios();
+
+        // System mode with IRQs enabled
+        self.reg_cpsr = 0x10 | MODE_SYSTEM as u32;
 
         // Setup stack pointers
         self.regs_svc[0] = 0x03007FE0;
         self.regs_irq[0] = 0x03007FA0;
         self.registers[13] = 0x03007F00;
 
-        // Clear panic flag
-        self.panic = false;
-        self.halt = false;
+        self.sound.write_u16(REG_SOUNDBIAS, 0x200, 0xFFFF);
+        self.serial.rcnt = 0x8000;
+        self.io_postflg = 1;
 
-        // Clear cycle counter
-        self.cycle_count = 0;
+        self.bios_latch = BIOS_LATCH_STARTUP;
+        self.set_program_counter(0x08000000);
     }
 
     pub fn trigger_irq(&mut self, irq: u16) {
         self.io_if |= irq;
+
+        if self.halt {
+            warn!(
+                "IRQ: `{irq:04X}` woke CPU from {}",
+                match self.stop {
+                    false => "Halt",
+                    true => "Stop",
+                }
+            );
+        }
+
         self.halt = false;
+        self.stop = false;
 
+        // Halt is left on (IE & IF), but the IRQ is only taken when enabled
+        // Otherwise it stays pending in IF until `irq_check` finds it enabled
+        let ime_enable = (self.io_ime & 0x1) == 0x1;
+        let irq_enable = (self.reg_cpsr & STATUS_FLAG_I) == 0;
+        if !ime_enable || !irq_enable {
+            warn!("IRQ: Not taken (IME={ime_enable}, CPSR.I={})", !irq_enable);
+            return;
+        }
+
+        self.irq_take();
+    }
+
+    /// An IRQ is requested in IE & IF and enabled by IME and CPSR.I
+    pub fn irq_pending(&self) -> bool {
+        (self.io_ime & 0x1) == 0x1
+            && (self.reg_cpsr & STATUS_FLAG_I) == 0
+            && (self.io_ie & self.io_if) != 0
+    }
+
+    /// Takes an IRQ raised while masked, once IME, IE or CPSR.I enable it
+    /// Called between instructions, as a masked IRQ is not taken when it is raised
+    pub fn irq_check(&mut self) {
+        if self.irq_pending() {
+            warn!("IRQ: Taking pending `{:04X}`", self.io_ie & self.io_if);
+            self.irq_take();
+        }
+    }
+
+    /// Enters the IRQ exception through the BIOS vector at 0x18
+    fn irq_take(&mut self) {
         // Store current CPSR into SPSR[current_mode], store SPSR[IRQ] in CPSR
         self.regs_spsr[MODE_IRQ as usize] = self.reg_cpsr;
         warn!("IRQ: Stored CPSR `{:08X}` in SPSR[IRQ]", self.reg_cpsr);
@@ -733,20 +942,39 @@ impl CPU {
         // 0000013C  subs   r15,r14,4h          ;return from IRQ (PC=LR-4, CPSR=SPSR)
     }
 
+    /// Presses `buttons` on the keypad, raising IRQ_KEYPAD if the KEYCNT condition is met
+    pub fn keypad_press(&mut self, buttons: u16) {
+        if self.keypad.press(buttons) {
+            self.keypad_irq_check();
+        }
+    }
+
+    /// Releases `buttons` on the keypad, raising IRQ_KEYPAD if the KEYCNT condition is met
+    pub fn keypad_release(&mut self, buttons: u16) {
+        if self.keypad.release(buttons) {
+            self.keypad_irq_check();
+        }
+    }
+
+    /// Raises IRQ_KEYPAD (also leaving Stop) when the KEYCNT condition is met
+    fn keypad_irq_check(&mut self) {
+        if self.keypad.irq_condition() && self.can_irq_trigger(IRQ_KEYPAD) {
+            warn!("Keypad IRQ Triggered");
+            self.trigger_irq(IRQ_KEYPAD);
+        }
+    }
+
+    /// Returns whether `irq` is enabled in IE and at its source, so it sets IF and leaves Halt
+    /// IME and CPSR.I only decide whether the IRQ is taken, see `trigger_irq`
     pub fn can_irq_trigger(&mut self, irq: u16) -> bool {
-        let ime_enable = (self.io_ime & 0x1) == 0x1;
         let ie_enable = (self.io_ie & irq) == irq;
-        let if_enable = (self.io_if & irq) == irq;
-        let irq_enable = (self.reg_cpsr & 0x80) != 0x80;
-
-        let bios_if_enable = (self.io_bios_if & irq) == irq;
 
-        if !ie_enable || (!self.halt && (!irq_enable || !ime_enable)) {
+        if !ie_enable {
             return false;
         }
 
-        if self.halt && (self.io_bios_if != 0 && !bios_if_enable) {
-            warn!("Halted (with BIOS IF), but IRQ `{irq:04X}` not in BIOS_IF");
+        // Stop mode has the LCD, sound and timers off, only these can wake it
+        if self.stop && (irq & (IRQ_KEYPAD | IRQ_SERIAL | IRQ_GAMEPAK)) == 0 {
             return false;
         }
 
@@ -754,15 +982,15 @@ impl CPU {
             IRQ_VBLANK => self.lcd.is_vblank_irq_enabled(),
             IRQ_HBLANK => self.lcd.is_hblank_irq_enabled(),
             IRQ_VCOUNT => self.lcd.is_vcount_irq_enabled(),
-            IRQ_TIM0 => false,
-            IRQ_TIM1 => false,
-            IRQ_TIM2 => false,
-            IRQ_TIM3 => false,
-            IRQ_SERIAL => false,
-            IRQ_DMA0 => false,
-            IRQ_DMA1 => false,
-            IRQ_DMA2 => false,
-            IRQ_DMA3 => false,
+            IRQ_TIM0 => self.timers[0].is_irq_enabled(),
+            IRQ_TIM1 => self.timers[1].is_irq_enabled(),
+            IRQ_TIM2 => self.timers[2].is_irq_enabled(),
+            IRQ_TIM3 => self.timers[3].is_irq_enabled(),
+            IRQ_SERIAL => self.serial.is_irq_enabled(),
+            IRQ_DMA0 => (self.dma_register(0, 8) & 0x40000000) != 0,
+            IRQ_DMA1 => (self.dma_register(1, 8) & 0x40000000) != 0,
+            IRQ_DMA2 => (self.dma_register(2, 8) & 0x40000000) != 0,
+            IRQ_DMA3 => (self.dma_register(3, 8) & 0x40000000) != 0,
             IRQ_KEYPAD => self.keypad.is_irq_enabled(),
             IRQ_GAMEPAK => true, // Triggers on cart removal
             IRQ_DEBUG1 => true,
@@ -871,42 +1099,13 @@ impl CPU {
         }
     }
 
-    /// Check DMAs and see if any need to run
-    pub fn dma_check(&mut self) -> Option<u8> {
-        for i in 0..4 {
-            let control = {
-                let reg = self.read_u32(false, 0x040000B0 + ((i * 12) + 8));
-
-                (reg >> 16) & 0xFFFF
-            };
-
-            let timing = ((control >> 12) & 0x3) as u8;
-            let enable = (control & 0x8000) != 0;
-
-            // Skips if not enabled and if SOUND DMA is specified
-            if !enable || (timing == 0x03 && (i == 1 || i == 2)) {
-                continue;
-            }
-
-            match timing {
-                0x00 => return Some(i as u8),
-                0x01 => todo!("Check VBlank DMA start timing => DMA{}", i),
-                0x02 => todo!("Check HBlank DMA start timing => DMA{}", i),
-                0x03 => todo!("Check Special DMA start timing => DMA{}", i),
-                _ => unreachable!(),
-            }
-        }
-
-        None
-    }
-
+    /// Runs the transfer of DMA `num` from its internal addresses
     pub fn dma_run(&mut self, num: u8) {
-        let addr_base: u32 = 0x040000B0;
         let reg_offset = (num as u32) * 12;
-        let src = self.read_u32(true, addr_base + reg_offset + 0);
-        let dest = self.read_u32(true, addr_base + reg_offset + 4);
+        let src = self.dma_src[num as usize];
+        let dest = self.dma_dest[num as usize];
         let (count, control) = {
-            let reg = self.read_u32(true, addr_base + reg_offset + 8);
+            let reg = self.dma_register(num, 8);
             let cnt = reg & 0xFFFF;
 
             let cnt = match (cnt, num) {
@@ -940,28 +1139,26 @@ impl CPU {
         let mut src_ptr = src;
         let mut dest_ptr = dest;
 
+        // The EEPROM size follows from the length of the bit stream sent by DMA3
+        if num == 3 && self.is_eeprom(dest) {
+            self.backup.eeprom_dma(count);
+        }
+
         for _ in 0..count {
             // Read/Write to memory
             if word {
                 let val = self.read_u32(true, src_ptr);
                 self.write_u32(true, dest_ptr, val);
             } else {
-                let low = self.read_u8(true, src_ptr);
-                let high = self.read_u8(true, src_ptr + 1);
-
-                self.write_u8(true, dest_ptr, low);
-                self.write_u8(true, dest_ptr + 1, high);
+                let val = self.read_u16(true, src_ptr);
+                self.write_u16(true, dest_ptr, val);
             }
 
             // Fix pointers
             dest_ptr = match dest_ctrl {
-                0 => dest_ptr + step,
+                0 | 3 => dest_ptr + step,
                 1 => dest_ptr - step,
                 2 => dest_ptr,
-                3 => {
-                    todo!("Implement DMA dest increment+reload");
-                    dest_ptr + step
-                }
                 _ => unreachable!(),
             };
 
@@ -974,11 +1171,18 @@ impl CPU {
             };
         }
 
+        // Increment+reload restores the destination for the next repeat
+        self.dma_src[num as usize] = src_ptr;
+        self.dma_dest[num as usize] = match dest_ctrl {
+            3 => self.dma_register(num, 4),
+            _ => dest_ptr,
+        };
+
         if irq {
-            todo!("Implement DMA finish IRQ");
+            self.dma_irq(num);
         }
 
-        if !repeat {
+        if !repeat || timing == events::DMA_TIMING_IMMEDIATE {
             // Clear enable bit
             self.dma[(reg_offset as usize) + 11] &= !(0x80);
         }
@@ -989,6 +1193,40 @@ impl CPU {
         );
     }
 
+    // SWI 0x00
+    fn syscall_soft_reset(&mut self) {
+        // Return address selected by the flag at 0x03007FFA: 0 => ROM, otherwise EWRAM
+        let flag = self.read_u8(false, 0x03007FFA);
+        let addr = match flag {
+            0 => 0x08000000,
+            _ => 0x02000000,
+        };
+
+        info!("HLE: executing syscall `SoftReset` to `{:08X}`", addr);
+
+        // Stacks, BIOS IRQ vector and flags
+        for i in 0..0x200 {
+            self.write_u8(false, 0x03007E00 + i, 0);
+        }
+
+        self.registers[..15].fill(0);
+        self.regs_svc = [0x03007FE0, 0];
+        self.regs_irq = [0x03007FA0, 0];
+        self.registers[13] = 0x03007F00;
+        self.regs_spsr[MODE_SUPERVISOR as usize] = 0;
+        self.regs_spsr[MODE_IRQ as usize] = 0;
+
+        // The SWI handler steps over the instruction
+        let size = match self.is_thumb() {
+            false => 4,
+            true => 2,
+        };
+
+        self.reg_cpsr = 0x10 | MODE_SYSTEM as u32;
+        self.registers[15] = addr - size;
+        self.bios_latch = BIOS_LATCH_STARTUP;
+    }
+
     // SWI 0x01
     fn syscall_register_ram_reset(&mut self) {
         let flags = self.read_register(0) & 0xFF;
@@ -1006,20 +1244,23 @@ impl CPU {
                 self.ram_work2[i] = 0;
             }
         }
+        if (flags & 0x3) != 0 {
+            self.blocks.clear();
+        }
         if (flags & 0x4) != 0 {
-            self.ram_palette.lock().unwrap().fill(0);
+            self.ram_palette.fill(0);
         }
         if (flags & 0x8) != 0 {
-            self.ram_video.lock().unwrap().fill(0);
+            self.ram_video.fill(0);
         }
         if (flags & 0x10) != 0 {
-            self.ram_obj_attr.lock().unwrap().fill(0);
+            self.ram_obj_attr.fill(0);
         }
         if (flags & 0x20) != 0 {
             // Clear SIO
         }
         if (flags & 0x40) != 0 {
-            // Clear Sound registers
+            self.sound.reset();
         }
         if (flags & 0x80) != 0 {
             self.lcd.reset();
@@ -1045,14 +1286,33 @@ impl CPU {
         //      1=Discard old flags, wait until a new flag becomes set
         //
         // r1 => Interrupt flag(s) to wait for (same format as IE/IF registers)
+        //
+        // The user IRQ handler acknowledges interrupts by setting them in BIOS_IF
+
+        self.io_ime = 0x1;
 
-        if !discard {
-            todo!("Implement IntrWait with discard=0");
+        // Only discard on entry, not when looping back after an IRQ
+        if discard && self.intr_wait.is_none() {
+            let bios_if = self.read_u16(false, BIOS_IF);
+            self.write_u16(false, BIOS_IF, bios_if & !r1);
         }
 
-        self.io_ime |= 0x1;
-        self.io_bios_if = r1;
+        let bios_if = self.read_u16(false, BIOS_IF);
+        if (bios_if & r1) != 0 {
+            self.write_u16(false, BIOS_IF, bios_if & !r1);
+            self.intr_wait = None;
+            return;
+        }
+
+        // Halt, then re-execute the SWI when the IRQ handler returns, like the BIOS loop
+        self.intr_wait = Some(r1);
         self.halt = true;
+
+        let size = match self.is_thumb() {
+            false => 4,
+            true => 2,
+        };
+        self.registers[15] = self.registers[15].wrapping_sub(size);
     }
 
     // SWI 0x05
@@ -1096,11 +1356,76 @@ impl CPU {
 
     // SWI 0x08
     fn syscall_sqrt(&mut self) {
-        let val = self.read_register(0) as f32;
+        let val = self.read_register(0);
 
         info!("HLE: executing syscall `Sqrt` with `{val}`");
 
-        self.write_register(0, val.sqrt() as u32);
+        self.write_register(0, val.isqrt());
+    }
+
+    /// Polynomial approximation of the BIOS for the arctangent of `tan` (1.1.14 fixed point)
+    /// Returns (angle, r1, r3) as left in the registers by the BIOS
+    fn arctan(tan: i32) -> (i32, i32, i32) {
+        let a = -(tan.wrapping_mul(tan) >> 14);
+
+        let mut b = ((0xA9 * a) >> 14) + 0x390;
+        for c in [0x91C, 0xFB6, 0x16AA, 0x2081, 0x3651, 0xA2F9] {
+            b = (b.wrapping_mul(a) >> 14) + c;
+        }
+
+        (tan.wrapping_mul(b) >> 16, a, b)
+    }
+
+    // SWI 0x09
+    fn syscall_arctan(&mut self) {
+        let tan = self.read_register(0) as i32;
+
+        info!("HLE: executing syscall `ArcTan` with `{tan:X}`");
+
+        let (angle, r1, r3) = Self::arctan(tan);
+        self.write_register(0, angle as u32);
+        self.write_register(1, r1 as u32);
+        self.write_register(3, r3 as u32);
+    }
+
+    // SWI 0x0A
+    fn syscall_arctan2(&mut self) {
+        let x = self.read_register(0) as i32;
+        let y = self.read_register(1) as i32;
+
+        info!("HLE: executing syscall `ArcTan2` with `x={x:X}, y={y:X}`");
+
+        let arctan = |tan: i32| Self::arctan(tan).0;
+
+        // Full circle 0x0000..0xFFFF, reduced to the octants covered by ArcTan
+        let angle = if y == 0 {
+            match x >= 0 {
+                true => 0,
+                false => 0x8000,
+            }
+        } else if x == 0 {
+            match y >= 0 {
+                true => 0x4000,
+                false => 0xC000,
+            }
+        } else if y >= 0 {
+            if x >= 0 && x >= y {
+                arctan((y << 14) / x)
+            } else if x < 0 && -x >= y {
+                arctan((y << 14) / x) + 0x8000
+            } else {
+                0x4000 - arctan((x << 14) / y)
+            }
+        } else if x <= 0 && -x > -y {
+            arctan((y << 14) / x) + 0x8000
+        } else if x > 0 && x >= -y {
+            arctan((y << 14) / x) + 0x10000
+        } else {
+            0xC000 - arctan((x << 14) / y)
+        };
+
+        self.write_register(0, (angle as u32) & 0xFFFF);
+        self.write_register(3, 0x170);
     }
 
     // SWI 0x0B
@@ -1180,34 +1505,569 @@ impl CPU {
         }
     }
 
-    // SWI 0x0F
-    fn syscall_obj_affine_set(&mut self) {
-        let src_addr = self.read_register(0);
-        let dest_addr = self.read_register(1);
-        let num_calc = self.read_register(2);
-        let offset = self.read_register(3);
+    // SWI 0x0D
+    fn syscall_get_bios_checksum(&mut self) {
+        info!("HLE: executing syscall `GetBiosChecksum`");
 
-        todo!("Implement ObjAffineSet");
+        // Checksum of the GBA BIOS (the DS BIOS returns BAAE1880h)
+        self.write_register(0, 0xBAAE187F);
+        self.write_register(1, 1);
+        self.write_register(3, 0x4000);
     }
 
-    // SWI 0x12
-    fn syscall_lz77_uncomp_read_normal_write_16bit_vram(&mut self) {
-        let src_addr = self.read_register(0);
-        let dest_addr = self.read_register(1);
-
-        todo!("Implement LZ7UnCompReadNormalWrite16bit");
+    /// Rotation/scaling parameters (PA, PB, PC, PD) in 8.8 fixed point, as computed by the BIOS
+    /// `angle` uses the upper 8 bits, a full turn is 0x10000
+    fn affine_params(scale_x: i16, scale_y: i16, angle: u16) -> (i32, i32, i32, i32) {
+        let theta = (angle >> 8) as usize;
+        let sin = BIOS_SINE_TABLE[theta] as i32;
+        let cos = BIOS_SINE_TABLE[(theta + 64) & 0xFF] as i32;
+
+        let (sx, sy) = (scale_x as i32, scale_y as i32);
+
+        (
+            (sx * cos) >> 14,
+            -((sx * sin) >> 14),
+            (sy * sin) >> 14,
+            (sy * cos) >> 14,
+        )
     }
 
-    // SWI 0x15
-    fn syscall_rl_uncomp_read_normal_write_16bit_vram(&mut self) {
+    // SWI 0x0E
+    fn syscall_bg_affine_set(&mut self) {
         let src_addr = self.read_register(0);
         let dest_addr = self.read_register(1);
+        let num_calc = self.read_register(2);
 
-        todo!("Implement SWI 0x15");
+        info!(
+            "HLE: executing syscall `BgAffineSet` with `src={:08X}, dest={:08X}, count={}`",
+            src_addr, dest_addr, num_calc
+        );
+
+        for i in 0..num_calc {
+            // Source: original center (19.8), display center, scale (8.8), angle
+            let src = src_addr + i * 20;
+            let orig_x = self.read_u32(true, src) as i32;
+            let orig_y = self.read_u32(true, src + 4) as i32;
+            let disp_x = self.read_u16(true, src + 8) as i16 as i32;
+            let disp_y = self.read_u16(true, src + 10) as i16 as i32;
+            let scale_x = self.read_u16(true, src + 12) as i16;
+            let scale_y = self.read_u16(true, src + 14) as i16;
+            let angle = self.read_u16(true, src + 16);
+
+            let (pa, pb, pc, pd) = Self::affine_params(scale_x, scale_y, angle);
+
+            // Reference point: the original center shown at the display center
+            let start_x = orig_x - (pa * disp_x + pb * disp_y);
+            let start_y = orig_y - (pc * disp_x + pd * disp_y);
+
+            let dest = dest_addr + i * 16;
+            self.write_u16(true, dest, pa as u16);
+            self.write_u16(true, dest + 2, pb as u16);
+            self.write_u16(true, dest + 4, pc as u16);
+            self.write_u16(true, dest + 6, pd as u16);
+            self.write_u32(true, dest + 8, start_x as u32);
+            self.write_u32(true, dest + 12, start_y as u32);
+        }
+    }
+
+    // SWI 0x0F
+    fn syscall_obj_affine_set(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+        let num_calc = self.read_register(2);
+        let offset = self.read_register(3);
+
+        info!(
+            "HLE: executing syscall `ObjAffineSet` with `src={:08X}, dest={:08X}, count={}, offset={}`",
+            src_addr, dest_addr, num_calc, offset
+        );
+
+        for i in 0..num_calc {
+            // Source: scale (8.8), angle, padding
+            let src = src_addr + i * 8;
+            let scale_x = self.read_u16(true, src) as i16;
+            let scale_y = self.read_u16(true, src + 2) as i16;
+            let angle = self.read_u16(true, src + 4);
+
+            let (pa, pb, pc, pd) = Self::affine_params(scale_x, scale_y, angle);
+
+            // Parameters are `offset` bytes apart, 2 for an array and 8 for OAM
+            let dest = dest_addr + i * offset * 4;
+            for (n, param) in [pa, pb, pc, pd].iter().enumerate() {
+                self.write_u16(true, dest + (n as u32) * offset, *param as u16);
+            }
+        }
+    }
+
+    // SWI 0x10
+    fn syscall_bit_unpack(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+        let info_addr = self.read_register(2);
+
+        // UnPackInfo: source length, source width, destination width, offset and zero flag
+        let src_len = self.read_u16(true, info_addr) as u32;
+        let src_width = self.read_u8(true, info_addr + 2) as u32;
+        let dest_width = self.read_u8(true, info_addr + 3) as u32;
+        let data_offset = self.read_u32(true, info_addr + 4);
+        let offset = data_offset & 0x7FFFFFFF;
+        let offset_zero = (data_offset & 0x80000000) != 0;
+
+        info!(
+            "HLE: executing syscall `BitUnPack` with `src={:08X}, dest={:08X}, len={}, width={}->{}, offset={:X}, zero={}`",
+            src_addr, dest_addr, src_len, src_width, dest_width, offset, offset_zero
+        );
+
+        if ![1, 2, 4, 8].contains(&src_width) || ![1, 2, 4, 8, 16, 32].contains(&dest_width) {
+            warn!("HLE: BitUnPack with invalid width {src_width}->{dest_width}");
+            return;
+        }
+
+        let mut dest = dest_addr;
+        let mut out = 0u32;
+        let mut out_bits = 0;
+
+        for i in 0..src_len {
+            let byte = self.read_u8(true, src_addr + i) as u32;
+
+            for shift in (0..8).step_by(src_width as usize) {
+                let mut val = (byte >> shift) & ((1 << src_width) - 1);
+
+                // Zero units are only offset with the zero flag
+                if val != 0 || offset_zero {
+                    val = val.wrapping_add(offset);
+                }
+
+                out |= val << out_bits;
+                out_bits += dest_width;
+
+                if out_bits == 32 {
+                    self.write_u32(true, dest, out);
+                    dest += 4;
+                    out = 0;
+                    out_bits = 0;
+                }
+            }
+        }
+    }
+
+    /// Reads the header of compressed data at `src_addr`, returns (type, parameter, size)
+    /// None if the source is in the BIOS (ignored by the BIOS for protection)
+    fn uncomp_header(&mut self, src_addr: u32) -> Option<(u8, u8, usize)> {
+        if (src_addr & 0x0E000000) == 0 {
+            warn!(
+                "HLE: Decompression from BIOS address `{:08X}` ignored",
+                src_addr
+            );
+            return None;
+        }
+
+        let header = self.read_u32(false, src_addr);
+        let kind = ((header >> 4) & 0xF) as u8;
+        let param = (header & 0xF) as u8;
+        let size = (header >> 8) as usize;
+
+        Some((kind, param, size))
+    }
+
+    /// Writes decompressed `data` to `dest_addr`
+    /// VRAM variants write in 16-bit units, a trailing odd byte is not written
+    fn uncomp_write(&mut self, dest_addr: u32, data: &[u8], vram: bool) {
+        match vram {
+            false => {
+                for (i, val) in data.iter().enumerate() {
+                    self.write_u8(true, dest_addr + i as u32, *val);
+                }
+            }
+            true => {
+                for (i, val) in data.chunks_exact(2).enumerate() {
+                    let val = u16::from_le_bytes([val[0], val[1]]);
+                    self.write_u16(true, dest_addr + (i as u32) * 2, val);
+                }
+            }
+        }
+    }
+
+    /// Decompresses LZ77 data at `src_addr`
+    fn lz77_uncomp(&mut self, src_addr: u32) -> Option<Vec<u8>> {
+        let (_, _, size) = self.uncomp_header(src_addr)?;
+        let mut data = Vec::with_capacity(size);
+        let mut src = src_addr + 4;
+
+        while data.len() < size {
+            let flags = self.read_u8(false, src);
+            src += 1;
+
+            for bit in (0..8).rev() {
+                if data.len() >= size {
+                    break;
+                }
+
+                if (flags >> bit) & 0x1 == 0 {
+                    data.push(self.read_u8(false, src));
+                    src += 1;
+                    continue;
+                }
+
+                // Block: bits 12-15 length - 3, bits 0-11 displacement - 1
+                let block = ((self.read_u8(false, src) as usize) << 8)
+                    | (self.read_u8(false, src + 1) as usize);
+                src += 2;
+
+                let len = (block >> 12) + 3;
+                let disp = (block & 0xFFF) + 1;
+
+                for _ in 0..len {
+                    if data.len() >= size {
+                        break;
+                    }
+                    let val = match data.len().checked_sub(disp) {
+                        Some(pos) => data[pos],
+                        None => {
+                            warn!("HLE: LZ77 displacement {disp} before start of output");
+                            0
+                        }
+                    };
+                    data.push(val);
+                }
+            }
+        }
+
+        Some(data)
+    }
+
+    // SWI 0x11
+    fn syscall_lz77_uncomp_wram(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+
+        info!(
+            "HLE: executing syscall `LZ77UnCompReadNormalWrite8bit` with `src={:08X}, dest={:08X}`",
+            src_addr, dest_addr
+        );
+
+        if let Some(data) = self.lz77_uncomp(src_addr) {
+            self.uncomp_write(dest_addr, &data, false);
+        }
+    }
+
+    // SWI 0x12
+    fn syscall_lz77_uncomp_vram(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+
+        info!(
+            "HLE: executing syscall `LZ77UnCompReadNormalWrite16bit` with `src={:08X}, dest={:08X}`",
+            src_addr, dest_addr
+        );
+
+        if let Some(data) = self.lz77_uncomp(src_addr) {
+            self.uncomp_write(dest_addr, &data, true);
+        }
+    }
+
+    // SWI 0x13
+    fn syscall_huff_uncomp(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+
+        info!(
+            "HLE: executing syscall `HuffUnCompReadNormal` with `src={:08X}, dest={:08X}`",
+            src_addr, dest_addr
+        );
+
+        let (_, bits, size) = match self.uncomp_header(src_addr) {
+            Some(header) => header,
+            None => return,
+        };
+
+        if bits != 4 && bits != 8 {
+            warn!("HLE: Huffman data size of {bits} bits not supported");
+            return;
+        }
+
+        // Tree table follows the header, its size byte is (size / 2) - 1
+        let tree_size = (self.read_u8(false, src_addr + 4) as u32 + 1) * 2;
+        let root = src_addr + 5;
+        let mut stream = src_addr + 4 + tree_size;
+
+        let mut node_addr = root;
+        let mut node = self.read_u8(false, node_addr);
+
+        let mut word = 0u32;
+        let mut word_bits = 0;
+        let mut written = 0;
+
+        while written < size {
+            let bitstream = self.read_u32(false, stream);
+            stream += 4;
+
+            for bit in (0..32).rev() {
+                // Node: bits 0-5 offset to the children, bit 7/6 child 0/1 is a leaf
+                let child = (bitstream >> bit) & 0x1;
+                let child_addr = (node_addr & !0x1) + ((node & 0x3F) as u32) * 2 + 2 + child;
+                let leaf = (node >> (7 - child)) & 0x1 != 0;
+
+                node_addr = child_addr;
+                node = self.read_u8(false, node_addr);
+
+                if !leaf {
+                    continue;
+                }
+
+                // Leaf: emit symbol, restart at the root
+                word |= ((node as u32) & ((1 << bits) - 1)) << word_bits;
+                word_bits += bits as u32;
+
+                node_addr = root;
+                node = self.read_u8(false, node_addr);
+
+                if word_bits == 32 {
+                    self.write_u32(true, dest_addr + written as u32, word);
+                    written += 4;
+                    word = 0;
+                    word_bits = 0;
+
+                    if written >= size {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Decompresses run-length encoded data at `src_addr`
+    fn rl_uncomp(&mut self, src_addr: u32) -> Option<Vec<u8>> {
+        let (_, _, size) = self.uncomp_header(src_addr)?;
+        let mut data = Vec::with_capacity(size);
+        let mut src = src_addr + 4;
+
+        while data.len() < size {
+            // Flag: bit 7 compressed run of (len + 3) bytes, else (len + 1) raw bytes
+            let flag = self.read_u8(false, src);
+            src += 1;
+
+            match (flag & 0x80) != 0 {
+                true => {
+                    let len = (flag & 0x7F) as usize + 3;
+                    let val = self.read_u8(false, src);
+                    src += 1;
+                    data.extend(std::iter::repeat_n(val, len));
+                }
+                false => {
+                    let len = (flag & 0x7F) as usize + 1;
+                    for _ in 0..len {
+                        data.push(self.read_u8(false, src));
+                        src += 1;
+                    }
+                }
+            }
+        }
+
+        data.truncate(size);
+        Some(data)
+    }
+
+    // SWI 0x14
+    fn syscall_rl_uncomp_wram(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+
+        info!(
+            "HLE: executing syscall `RLUnCompReadNormalWrite8bit` with `src={:08X}, dest={:08X}`",
+            src_addr, dest_addr
+        );
+
+        if let Some(data) = self.rl_uncomp(src_addr) {
+            self.uncomp_write(dest_addr, &data, false);
+        }
+    }
+
+    // SWI 0x15
+    fn syscall_rl_uncomp_vram(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+
+        info!(
+            "HLE: executing syscall `RLUnCompReadNormalWrite16bit` with `src={:08X}, dest={:08X}`",
+            src_addr, dest_addr
+        );
+
+        if let Some(data) = self.rl_uncomp(src_addr) {
+            self.uncomp_write(dest_addr, &data, true);
+        }
+    }
+
+    /// Unfilters 8-bit differences at `src_addr`, each byte adds to the previous one
+    fn diff8_unfilter(&mut self, vram: bool) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+
+        info!(
+            "HLE: executing syscall `Diff8bitUnFilterWrite{}bit` with `src={:08X}, dest={:08X}`",
+            match vram {
+                false => 8,
+                true => 16,
+            },
+            src_addr,
+            dest_addr
+        );
+
+        let (_, _, size) = match self.uncomp_header(src_addr) {
+            Some(header) => header,
+            None => return,
+        };
+
+        let mut data = Vec::with_capacity(size);
+        let mut val = 0u8;
+        for i in 0..size {
+            val = val.wrapping_add(self.read_u8(false, src_addr + 4 + i as u32));
+            data.push(val);
+        }
+
+        self.uncomp_write(dest_addr, &data, vram);
+    }
+
+    // SWI 0x16
+    fn syscall_diff8_unfilter_wram(&mut self) {
+        self.diff8_unfilter(false);
+    }
+
+    // SWI 0x17
+    fn syscall_diff8_unfilter_vram(&mut self) {
+        self.diff8_unfilter(true);
+    }
+
+    // SWI 0x18
+    fn syscall_diff16_unfilter(&mut self) {
+        let src_addr = self.read_register(0);
+        let dest_addr = self.read_register(1);
+
+        info!(
+            "HLE: executing syscall `Diff16bitUnFilter` with `src={:08X}, dest={:08X}`",
+            src_addr, dest_addr
+        );
+
+        let (_, _, size) = match self.uncomp_header(src_addr) {
+            Some(header) => header,
+            None => return,
+        };
+
+        let mut val = 0u16;
+        for i in 0..(size as u32) / 2 {
+            val = val.wrapping_add(self.read_u16(false, src_addr + 4 + i * 2));
+            self.write_u16(true, dest_addr + i * 2, val);
+        }
+    }
+
+    // SWI 0x19
+    fn syscall_sound_bias(&mut self) {
+        let target = match self.read_register(0) {
+            0 => 0x000,
+            _ => 0x200,
+        };
+
+        info!("HLE: executing syscall `SoundBias` to `{target:03X}`");
+
+        // The bias level (bits 1-9) moves one step at a time with a short delay in between
+        let bias = self.sound.read_u16(REG_SOUNDBIAS);
+        let mut level = bias & 0x3FE;
+
+        while level != target {
+            level = match level < target {
+                true => level + 2,
+                false => level - 2,
+            };
+            self.sound
+                .write_u16(REG_SOUNDBIAS, (bias & !0x3FE) | level, 0xFFFF);
+            self.cycle_count += SOUND_BIAS_STEP_CYCLES;
+        }
+    }
+
+    // SWI 0x1A
+    fn syscall_sound_driver_init(&mut self) {
+        let sound_area = self.read_register(0);
+
+        info!("HLE: executing syscall `SoundDriverInit` with `{sound_area:08X}`");
+
+        // The BIOS driver finds its work area through this pointer
+        self.write_u32(false, BIOS_SOUND_AREA, sound_area);
+    }
+
+    // SWI 0x1F
+    fn syscall_midi_key_2_freq(&mut self) {
+        let wave_data = self.read_register(0);
+        let key = self.read_register(1) as f64;
+        let fine = self.read_register(2) as f64;
+
+        // Frequency of the sample at middle C (key 60), stored after the WaveData header
+        let freq = self.read_u32(true, wave_data + 4) as f64;
+
+        info!("HLE: executing syscall `MidiKey2Freq` with `freq={freq}, key={key}, fine={fine}`");
+
+        let res = freq / 2f64.powf((180.0 - key - fine / 256.0) / 12.0);
+        self.write_register(0, res as u32);
+    }
+
+    // SWI 0x1B-0x1E, 0x20-0x24, 0x28-0x29
+    fn syscall_sound_driver(&mut self, syscall: u8) {
+        let name = match syscall {
+            0x1B => "SoundDriverMode",
+            0x1C => "SoundDriverMain",
+            0x1D => "SoundDriverVSync",
+            0x1E => "SoundChannelClear",
+            0x20..=0x23 => "SoundWhatever",
+            0x24 => "SoundDriverUnknown",
+            0x28 => "SoundDriverVSyncOff",
+            _ => "SoundDriverVSyncOn",
+        };
+
+        // Without a BIOS mixer the driver calls do nothing, the music is simply silent
+        info!("HLE: ignoring syscall `{name}` ({syscall:02X}h)");
+    }
+
+    // SWI 0x2A
+    fn syscall_sound_get_jump_list(&mut self) {
+        let dest = self.read_register(0);
+
+        info!("HLE: executing syscall `SoundGetJumpList` with `{dest:08X}`");
+
+        // Drivers copied from the BIOS call through this table, every entry returns at once
+        for i in 0..SOUND_JUMP_LIST_ENTRIES {
+            self.write_u32(true, dest + i * 4, bios::SOUND_DRIVER_STUB);
+        }
+    }
+
+    // SWI 0x25
+    fn syscall_multi_boot(&mut self) {
+        warn!("HLE: executing syscall `MultiBoot`, no clients connected");
+
+        // 1 => Failure
+        self.write_register(0, 1);
+    }
+
+    // SWI 0x26
+    fn syscall_hard_reset(&mut self) {
+        warn!("HLE: executing syscall `HardReset`");
+
+        self.write_register(0, 0xFF);
+        self.syscall_register_ram_reset();
+        self.write_u8(false, 0x03007FFA, 0);
+        self.syscall_soft_reset();
+    }
+
+    // SWI 0x27
+    fn syscall_custom_halt(&mut self) {
+        let haltcnt = self.read_register(2) as u8;
+
+        info!("HLE: executing syscall `CustomHalt` with `{haltcnt:02X}`");
+
+        self.write_haltcnt(haltcnt);
     }
 
     fn bios_syscall(&mut self, syscall: u8) {
         match syscall {
+            0x00 => self.syscall_soft_reset(),
             0x01 => self.syscall_register_ram_reset(),
             0x02 => self.syscall_halt(),
             0x04 => self.syscall_intr_wait(),
@@ -1215,11 +2075,30 @@ impl CPU {
             0x06 => self.syscall_div(),
             0x07 => self.syscall_div_arm(),
             0x08 => self.syscall_sqrt(),
+            0x09 => self.syscall_arctan(),
+            0x0A => self.syscall_arctan2(),
             0x0B => self.syscall_cpu_set(),
             0x0C => self.syscall_cpu_fast_set(),
+            0x0D => self.syscall_get_bios_checksum(),
+            0x0E => self.syscall_bg_affine_set(),
             0x0F => self.syscall_obj_affine_set(),
-            0x12 => self.syscall_lz77_uncomp_read_normal_write_16bit_vram(),
-            0x15 => self.syscall_rl_uncomp_read_normal_write_16bit_vram(),
+            0x10 => self.syscall_bit_unpack(),
+            0x11 => self.syscall_lz77_uncomp_wram(),
+            0x12 => self.syscall_lz77_uncomp_vram(),
+            0x13 => self.syscall_huff_uncomp(),
+            0x14 => self.syscall_rl_uncomp_wram(),
+            0x15 => self.syscall_rl_uncomp_vram(),
+            0x16 => self.syscall_diff8_unfilter_wram(),
+            0x17 => self.syscall_diff8_unfilter_vram(),
+            0x18 => self.syscall_diff16_unfilter(),
+            0x19 => self.syscall_sound_bias(),
+            0x1A => self.syscall_sound_driver_init(),
+            0x1F => self.syscall_midi_key_2_freq(),
+            0x1B..=0x1E | 0x20..=0x24 | 0x28..=0x29 => self.syscall_sound_driver(syscall),
+            0x2A => self.syscall_sound_get_jump_list(),
+            0x25 => self.syscall_multi_boot(),
+            0x26 => self.syscall_hard_reset(),
+            0x27 => self.syscall_custom_halt(),
             _ => panic!("Unknown BIOS syscall `{:02X}h`", syscall),
         }
     }
@@ -2231,72 +3110,38 @@ impl CPU {
         (result, n, z, c, v)
     }
 
-    fn execute_thumb(&mut self, opcode: u16) {
-        let high = (opcode >> 8) as u8;
-
-        match high {
-            0x00..=0x17 => {
-                self.thumb_move_shifted_register(opcode);
-            } // Move shifted register
-            0x18..=0x1F => {
-                self.thumb_add_subtract(opcode);
-            } // Add/subtract
-            0x20..=0x3F => {
-                self.thumb_mov_cmp_add_sub_imm(opcode);
-            } // Move/compare/add/subtract immediate
-            0x40..=0x43 => {
-                self.thumb_alu(opcode);
-            } // ALU operations
-            0x44..=0x47 => self.thumb_hi_register_op_bx(opcode), // Hi register operations/branch exchange
-            0x48..=0x4F => {
-                self.thumb_pc_relative_load(opcode);
-            } // PC-relative load
-            0x50 | 0x51 | 0x54 | 0x55 | 0x58 | 0x59 | 0x5C | 0x5D => {
-                self.thumb_load_store_register_offset(opcode);
-            } // Load/store with register offset
-            0x52 | 0x53 | 0x56 | 0x57 | 0x5A | 0x5B | 0x5E | 0x5F => {
-                self.thumb_load_store_sign_extended_byte_halfword(opcode);
-            } // Load/store sign-extended byte/halfword
-            0x60..=0x7F => {
-                self.thumb_load_store_immediate(opcode);
-            } // load/store with immediate offset
-            0x80..=0x8F => {
-                self.thumb_load_store_halfword(opcode);
-            } // Load/store halfword
-            0x90..=0x9F => {
-                self.thumb_sp_relative_load_store(opcode);
-            } // SP-relative load/store
-            0xA0..=0xAF => {
-                self.thumb_load_address(opcode);
-            } // Load address
-            0xB0 => {
-                self.thumb_offset_to_sp(opcode);
-            } // Add offset to stack pointer
-            0xB4 | 0xB5 | 0xBC | 0xBD => {
-                self.thumb_push_pop(opcode);
-            } // Push/pop registers
-            0xC0..=0xCF => {
-                self.thumb_multiple_load_store(opcode);
-            } // Multiple load/store
-            0xD0..=0xDE => {
-                self.thumb_conditional_branch(opcode);
-            } // Conditional branch
-            0xDF => {
-                self.thumb_swi(opcode);
-            } // Software interrupt
-            0xE0..=0xE7 => {
-                self.thumb_unconditional_branch(opcode);
-            } // Unconditional branch,
-            0xF0..=0xFF => {
-                self.thumb_long_branch_link(opcode);
-            } // Long branch with link
-            _ => {
+    fn thumb_handler(instr: ThumbInstr) -> ThumbHandler {
+        match instr {
+            ThumbInstr::MoveShiftedRegister => Self::thumb_move_shifted_register,
+            ThumbInstr::AddSubtract => Self::thumb_add_subtract,
+            ThumbInstr::MovCmpAddSubImm => Self::thumb_mov_cmp_add_sub_imm,
+            ThumbInstr::Alu => Self::thumb_alu,
+            ThumbInstr::HiRegisterOpBx => Self::thumb_hi_register_op_bx,
+            ThumbInstr::PcRelativeLoad => Self::thumb_pc_relative_load,
+            ThumbInstr::LoadStoreRegisterOffset => Self::thumb_load_store_register_offset,
+            ThumbInstr::LoadStoreSignExtended => Self::thumb_load_store_sign_extended_byte_halfword,
+            ThumbInstr::LoadStoreImmediate => Self::thumb_load_store_immediate,
+            ThumbInstr::LoadStoreHalfword => Self::thumb_load_store_halfword,
+            ThumbInstr::SpRelativeLoadStore => Self::thumb_sp_relative_load_store,
+            ThumbInstr::LoadAddress => Self::thumb_load_address,
+            ThumbInstr::OffsetToSp => Self::thumb_offset_to_sp,
+            ThumbInstr::PushPop => Self::thumb_push_pop,
+            ThumbInstr::MultipleLoadStore => Self::thumb_multiple_load_store,
+            ThumbInstr::ConditionalBranch => Self::thumb_conditional_branch,
+            ThumbInstr::Swi => Self::thumb_swi,
+            ThumbInstr::UnconditionalBranch => Self::thumb_unconditional_branch,
+            ThumbInstr::LongBranchLink => Self::thumb_long_branch_link,
+            ThumbInstr::Unknown => |cpu, opcode| {
                 error!("[THUMB] Unknown opcode: {:04X}h ({:016b}b)", opcode, opcode);
-                self.panic = true;
-            }
+                cpu.panic = true;
+            },
         }
     }
 
+    fn execute_thumb(&mut self, opcode: u16) {
+        (self.thumb_table[decode::thumb_index(opcode)])(self, opcode);
+    }
+
     fn should_execute(&mut self, conditional: u8) -> bool {
         let n = self.get_flag_n();
         let z = self.get_flag_z();
@@ -2524,6 +3369,12 @@ impl CPU {
         // If not test opcode, store result
         if op < 0x8 || op > 0xB {
             self.write_register(rd, result);
+
+            // Branched by writing the PC
+            if rd == 15 {
+                self.cycle_count += 1 + 2;
+                return;
+            }
         }
 
         self.step_program_counter(4);
@@ -2611,23 +3462,33 @@ impl CPU {
         let rm = (opcode & 0xF) as u8;
         let rm_val = self.read_register(rm);
 
+        // Field mask (bits 19-16): f=31-24, s=23-16, x=15-8, c=7-0
+        let mut mask = 0;
+        for i in 0..4 {
+            if (opcode & (1 << (16 + i))) != 0 {
+                mask |= 0xFF << (i * 8);
+            }
+        }
+
         if dest_spsr {
             info!(
                 "[0x{:08X}] => execute: `MSR SPSR,R{}`",
                 self.registers[15], rm
             );
-            self.regs_spsr[self.get_mode() as usize] = rm_val;
+            let mode = self.get_mode() as usize;
+            self.regs_spsr[mode] = (self.regs_spsr[mode] & !mask) | (rm_val & mask);
         } else {
             info!(
                 "[0x{:08X}] => execute: `MSR CPSR,R{}`",
                 self.registers[15], rm
             );
 
+            // Only the flags can be written in User mode
             if self.get_mode() == MODE_USER {
-                self.reg_cpsr = (self.reg_cpsr & 0x0FFFFFFF) | (rm_val & 0xF0000000);
-            } else {
-                self.reg_cpsr = rm_val;
+                mask &= 0xF0000000;
             }
+
+            self.reg_cpsr = (self.reg_cpsr & !mask) | (rm_val & mask);
         }
 
         self.step_program_counter(4);
@@ -2763,6 +3624,14 @@ impl CPU {
         self.step_program_counter(4);
     }
 
+    fn arm_swi(&mut self, opcode: u32) {
+        // Only the upper 8 bits of the comment field are used by the BIOS
+        let syscall = ((opcode >> 16) & 0xFF) as u8;
+
+        self.operation_swi(syscall);
+        self.step_program_counter(4);
+    }
+
     /// Performs SWI to `syscall`
     /// Updates cycle_count accordingly
     fn operation_swi(&mut self, syscall: u8) {
@@ -2771,6 +3640,8 @@ impl CPU {
             self.registers[15], syscall
         );
 
+        // SWIs are executed in HLE, latch the opcode the BIOS SWI handler returns with
+        self.bios_latch = BIOS_LATCH_SWI;
         self.bios_syscall(syscall);
         self.cycle_count += 3;
     }
@@ -2965,8 +3836,49 @@ impl CPU {
         };
     }
 
+    fn arm_handler(instr: ArmInstr) -> ArmHandler {
+        match instr {
+            ArmInstr::Multiply => Self::arm_multiply,
+            ArmInstr::MultiplyLong => |_, _| todo!("Multiply Long"),
+            ArmInstr::SingleDataSwap => |_, _| todo!("Single Data Swap"),
+            ArmInstr::BranchExchange => Self::arm_branch_exchange,
+            ArmInstr::HalfwordRegister => |_, _| todo!("Halfword Data Transfer: register offset"),
+            ArmInstr::HalfwordImmediate => Self::arm_halfword_data_transfer_imm,
+            ArmInstr::Mrs => Self::arm_mrs,
+            ArmInstr::Msr => Self::arm_msr,
+            ArmInstr::MsrBits => |_, _| todo!("MSR bits"),
+            ArmInstr::DataProcessing => Self::arm_data_processing,
+            ArmInstr::Undefined => |_, _| todo!("Undefined instruction"),
+            ArmInstr::SingleDataTransfer => Self::arm_single_data_transfer,
+            ArmInstr::BlockDataTransfer => Self::arm_block_data_transfer,
+            ArmInstr::Branch => Self::arm_branch,
+            ArmInstr::CoprocessorDataTransfer => |cpu, _| {
+                panic!(
+                    "Coprocessor data transfer @ {:08X}",
+                    cpu.get_program_counter()
+                )
+            },
+            ArmInstr::CoprocessorDataOperation => |cpu, _| {
+                panic!(
+                    "Coprocessor data operation @ {:08X}",
+                    cpu.get_program_counter()
+                )
+            },
+            ArmInstr::CoprocessorRegisterTransfer => |cpu, _| {
+                panic!(
+                    "Coprocessor register transfer @ {:08X}",
+                    cpu.get_program_counter()
+                )
+            },
+            ArmInstr::SoftwareInterrupt => Self::arm_swi,
+        }
+    }
+
     fn execute_arm(&mut self, opcode: u32) {
-        let instr = ((opcode >> 20) & 0xFF) as u8;
+        self.execute_arm_handler(opcode, self.arm_table[decode::arm_index(opcode)]);
+    }
+
+    fn execute_arm_handler(&mut self, opcode: u32, handler: ArmHandler) {
         let cond = ((opcode >> 28) & 0xF) as u8;
 
         // Check conditional
@@ -2978,69 +3890,22 @@ impl CPU {
             return;
         }
 
-        match instr {
-            0x00..=0x3F => {
-                if Self::opcode_match(opcode, ARM_MASK_MUL_CLR, ARM_MASK_MUL_SET) {
-                    self.arm_multiply(opcode);
-                } else if Self::opcode_match(opcode, ARM_MASK_MUL_LONG_CLR, ARM_MASK_MUL_LONG_SET) {
-                    todo!("Multiply Long");
-                } else if Self::opcode_match(opcode, ARM_MASK_SNGL_SWP_CLR, ARM_MASK_SNGL_SWP_SET) {
-                    todo!("Single Data Swap");
-                } else if Self::opcode_match(opcode, ARM_MASK_BX_CLR, ARM_MASK_BX_SET) {
-                    self.arm_branch_exchange(opcode);
-                } else if Self::opcode_match(opcode, ARM_MASK_HW_REG_CLR, ARM_MASK_HW_REG_SET) {
-                    todo!("Halfword Data Transfer: register offset");
-                } else if Self::opcode_match(opcode, ARM_MASK_HW_IMM_CLR, ARM_MASK_HW_IMM_SET) {
-                    self.arm_halfword_data_transfer_imm(opcode);
-                } else if Self::opcode_match(opcode, ARM_MASK_MRS_CLR, ARM_MASK_MRS_SET) {
-                    self.arm_mrs(opcode);
-                } else if Self::opcode_match(opcode, ARM_MASK_MSR_CLR, ARM_MASK_MSR_SET) {
-                    self.arm_msr(opcode);
-                } else if Self::opcode_match(opcode, ARM_MASK_MSR_BITS_CLR, ARM_MASK_MSR_BITS_SET) {
-                    todo!("MSR bits");
-                } else {
-                    self.arm_data_processing(opcode);
-                }
-            }
-            0x40..=0x7F => {
-                if Self::opcode_match(opcode, ARM_MASK_UNDEF_CLR, ARM_MASK_UNDEF_SET) {
-                    todo!("Undefined instruction");
-                } else {
-                    self.arm_single_data_transfer(opcode);
-                }
-            }
-            0x80..=0x9F => self.arm_block_data_transfer(opcode),
-            0xA0..=0xBF => self.arm_branch(opcode),
-            0xC0..=0xDF => panic!(
-                "Coprocessor data transfer @ {:08X}",
-                self.get_program_counter()
-            ),
-            0xE0..=0xEF => {
-                if (opcode & 0x10) == 0 {
-                    panic!(
-                        "Coprocessor data operation @ {:08X}",
-                        self.get_program_counter()
-                    );
-                } else {
-                    panic!(
-                        "Coprocessor register transfer @ {:08X}",
-                        self.get_program_counter()
-                    );
-                }
-            }
-            0xF0..=0xFF => todo!("Software Interrupt"),
-            _ => {
-                error!(
-                    "[ ARM ] Unknown opcode: {:07X}h ({:024b}b)",
-                    opcode & 0x0FFFFFFF,
-                    opcode & 0x0FFFFFFF
-                );
-                self.panic = true;
-            }
-        }
+        handler(self, opcode);
     }
 
     pub fn execute(&mut self, opcode: u32) {
+        // The prefetch 2 instructions ahead is latched for BIOS read protection
+        let pc = self.get_program_counter();
+        if pc <= 0x00003FFF {
+            let prefetch = match self.is_thumb() {
+                false => pc.wrapping_add(8),
+                true => pc.wrapping_add(4),
+            } as usize
+                & 0x3FFC;
+            self.bios_latch =
+                u32::from_le_bytes(self.bios[prefetch..prefetch + 4].try_into().unwrap());
+        }
+
         if self.is_thumb() {
             self.execute_thumb(opcode as u16);
         } else {
@@ -3052,6 +3917,9 @@ impl CPU {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::keypad::{BUTTON_A, BUTTON_B, BUTTON_R, BUTTON_START};
+    use crate::lcd::DISPSTAT_HBLANK;
+    use crate::scheduler::Event;
 
     #[test]
     fn opcode_match_multiply() {
@@ -3071,10 +3939,7 @@ mod tests {
 
     #[test]
     fn arm_mov_imm() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
         cpu.set_thumb(false);
 
         // MOV R0,#0x12
@@ -3087,10 +3952,7 @@ mod tests {
     // Thumb Format1
     #[test]
     fn thumb_move_shifted_register() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let rs = 0b101;
         let rs_signed = 0x97000000;
@@ -3151,10 +4013,7 @@ mod tests {
     /// Thumb Format2
     #[test]
     fn thumb_add_subtract() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let rd = 0;
         let rs = 8;
@@ -3231,10 +4090,7 @@ mod tests {
     /// Thumb Format3
     #[test]
     fn thumb_mov_cmp_add_sub_imm() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // Rd => 0, Offset => 8
         let offset = 8;
@@ -3311,10 +4167,7 @@ mod tests {
     /// Thumb Format4
     #[test]
     fn thumb_alu() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // TST R0, R1 (equal)
         //let opcode_tst_equal = 0b0100_00_1000_001_000;
@@ -3338,10 +4191,7 @@ mod tests {
 
     #[test]
     fn alu() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // TST
         let (_, n, z, _, _) = cpu.alu(ALU_TST, 0x10, 0x10);
@@ -3435,10 +4285,7 @@ mod tests {
     /// Thumb Format5
     #[test]
     fn thumb_hi_register_op_bx() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let opcode_bx_low = 0b0100011100000000;
         let opcode_bx_high = 0b0100011101000000;
@@ -3475,10 +4322,7 @@ mod tests {
     /// Thumb Format6
     #[test]
     fn thumb_pc_relative_load() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let offset = 8;
         let opcode = 0x4800 | ((offset as u16) >> 2);
@@ -3496,10 +4340,7 @@ mod tests {
     /// Thumb Format7
     #[test]
     fn thumb_load_store_register_offset() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // Rd => 0, Rb => 1, Ro => 2
         let rb = 0x03000000;
@@ -3547,10 +4388,7 @@ mod tests {
     /// Thumb Format8
     #[test]
     fn thumb_load_store_sign_extended_byte_halfword() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // Rd => 0
         // Ro => 1
@@ -3600,10 +4438,7 @@ mod tests {
     /// Thumb Format9
     #[test]
     fn thumb_load_store_immediate() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let rd: u32 = 2;
         let rb: u32 = 0x02000000;
@@ -3652,10 +4487,7 @@ mod tests {
     /// Thumb Format10
     #[test]
     fn thumb_load_store_halfword() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // Rd => 0, Rb => 1, Imm => 8
         let rd = 0xDEADBEEF;
@@ -3683,10 +4515,7 @@ mod tests {
     /// Thumb Format11
     #[test]
     fn thumb_sp_relative_load_store() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let rd = 0xDEADBEEF;
         let sp = 0x03000000;
@@ -3712,10 +4541,7 @@ mod tests {
     // Thumb Format12
     #[test]
     fn thumb_load_address() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // rd=0, word8=16
         let val = 16;
@@ -3746,10 +4572,7 @@ mod tests {
     /// Thumb Format13
     #[test]
     fn thumb_offset_to_sp() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let imm = 16;
         let opcode_add = 0xB000 | (imm >> 2);
@@ -3772,10 +4595,7 @@ mod tests {
     // Thumb Format14
     #[test]
     fn thumb_push_pop() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let base = 0x03000000;
 
@@ -3843,10 +4663,7 @@ mod tests {
     /// Thumb Format15
     #[test]
     fn thumb_multiple_load_store() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // STMIA/LDMIA R3!,{R0, R2}
         let rb = 0x03000000;
@@ -3877,10 +4694,7 @@ mod tests {
     /// Thumb Format16
     #[test]
     fn thumb_conditional_branch() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
         cpu.set_thumb(true);
 
         let opcode_bne = 0xD1FC;
@@ -3917,19 +4731,13 @@ mod tests {
     #[ignore]
     #[test]
     fn thumb_swi() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
     }
 
     /// Thumb Format18
     #[test]
     fn thumb_unconditional_branch() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // Forward
         let offset = 8;
@@ -3952,10 +4760,7 @@ mod tests {
     /// Thumb Format19
     #[test]
     fn thumb_long_branch_link() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // Forward
         let addr: u32 = 0x8;
@@ -4007,10 +4812,7 @@ mod tests {
     /// Tests post-increment load
     #[test]
     fn operation_ldmia() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let wb = true;
         let load = true;
@@ -4042,10 +4844,7 @@ mod tests {
     /// Tests pre-decrement store
     #[test]
     fn operation_stmdb() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let wb = true;
         let load = false;
@@ -4078,10 +4877,7 @@ mod tests {
 
     #[test]
     fn syscall_div() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         cpu.write_register(0, (-1234 as i32) as u32);
         cpu.write_register(1, (10 as i32) as u32);
@@ -4093,10 +4889,7 @@ mod tests {
 
     #[test]
     fn memcpy() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let src: u32 = 0x02000000;
         let dest: u32 = 0x03000000;
@@ -4109,10 +4902,7 @@ mod tests {
 
     #[test]
     fn memfill32() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let dest: u32 = 0x03000000;
         let val: u32 = 0xDEADBEEF;
@@ -4126,10 +4916,7 @@ mod tests {
     /// Halt runs until an enabled interrupt is triggered
     #[test]
     fn syscall_halt() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         // Enable VBlank interrupt
         cpu.io_ie |= IRQ_VBLANK;
@@ -4150,10 +4937,7 @@ mod tests {
     /// Halt runs until an eanbled interrupt is triggered
     #[test]
     fn syscall_vblank_intr_wait() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         assert!(!cpu.can_irq_trigger(IRQ_HBLANK));
         assert!(!cpu.can_irq_trigger(IRQ_VBLANK));
@@ -4167,8 +4951,9 @@ mod tests {
         // Enable VBlank, HBlank irq in LCD
         cpu.lcd.set_dispstat(0x0018);
 
+        // IME and CPSR.I only decide whether the IRQ is taken, not whether it triggers
         assert!(!cpu.can_irq_trigger(IRQ_HBLANK));
-        assert!(!cpu.can_irq_trigger(IRQ_VBLANK));
+        assert!(cpu.can_irq_trigger(IRQ_VBLANK));
 
         cpu.syscall_vblank_intr_wait();
         assert!(cpu.halt);
@@ -4180,12 +4965,63 @@ mod tests {
         assert!(!cpu.halt);
     }
 
+    /// IntrWait discards old flags on entry and loops through Halt until BIOS_IF is set
+    #[test]
+    fn syscall_intr_wait_discard() {
+        let mut cpu = CPU::new();
+
+        let pc = 0x08000100;
+        cpu.set_thumb(true);
+        cpu.set_program_counter(pc);
+        cpu.write_u16(false, BIOS_IF, IRQ_VBLANK);
+
+        // SWI 04h with r0=1
+        cpu.write_register(0, 1);
+        cpu.write_register(1, IRQ_VBLANK as u32);
+        cpu.execute_thumb(0xDF04);
+        assert!(cpu.halt);
+        assert_eq!(cpu.io_ime, 1);
+        assert_eq!(cpu.read_u16(false, BIOS_IF), 0);
+        assert_eq!(cpu.get_program_counter(), pc);
+
+        // Woken by an IRQ which the handler did not acknowledge: halt again
+        cpu.halt = false;
+        cpu.execute_thumb(0xDF04);
+        assert!(cpu.halt);
+        assert_eq!(cpu.get_program_counter(), pc);
+
+        // Handler acknowledged VBlank in BIOS_IF: return
+        cpu.halt = false;
+        cpu.write_u16(false, BIOS_IF, IRQ_VBLANK | IRQ_HBLANK);
+        cpu.execute_thumb(0xDF04);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.intr_wait, None);
+        assert_eq!(cpu.read_u16(false, BIOS_IF), IRQ_HBLANK);
+        assert_eq!(cpu.get_program_counter(), pc + 2);
+    }
+
+    /// IntrWait with r0=0 returns immediately if the flag is already set
+    #[test]
+    fn syscall_intr_wait_no_discard() {
+        let mut cpu = CPU::new();
+
+        let pc = 0x08000100;
+        cpu.set_thumb(false);
+        cpu.set_program_counter(pc);
+        cpu.write_u16(false, BIOS_IF, IRQ_VBLANK);
+
+        // SWI 040000h with r0=0
+        cpu.write_register(0, 0);
+        cpu.write_register(1, IRQ_VBLANK as u32);
+        cpu.execute_arm(0xEF040000);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.read_u16(false, BIOS_IF), 0);
+        assert_eq!(cpu.get_program_counter(), pc + 4);
+    }
+
     #[test]
     fn alu_operand2_calc_imm() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let imm: u32 = 0x4D;
         let rot: u32 = 0x3;
@@ -4196,10 +5032,7 @@ mod tests {
 
     #[test]
     fn alu_operand2_calc_shift_imm() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let rm_val = 0x4D;
         cpu.write_register(0, rm_val);
@@ -4229,10 +5062,7 @@ mod tests {
 
     #[test]
     fn alu_operand2_calc_shift_reg() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let rm_val = 0x4D;
         let shift_amount = 5;
@@ -4264,10 +5094,7 @@ mod tests {
 
     #[test]
     fn arm_multiply() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         let rd = 0;
         let rm = 1;
@@ -4302,15 +5129,1249 @@ mod tests {
         assert_eq!(cpu.read_register(rd), 0xFFFFFF39);
     }
 
+    #[test]
+    fn io_interrupt_control_halfword_byte() {
+        let mut cpu = CPU::new();
+
+        // IE: byte writes only touch their byte, bits 14-15 unused
+        cpu.write_u16(true, 0x04000200, 0xFFFF);
+        assert_eq!(cpu.io_ie, 0x3FFF);
+        cpu.write_u8(true, 0x04000201, 0x00);
+        assert_eq!(cpu.io_ie, 0x00FF);
+        assert_eq!(cpu.read_u8(true, 0x04000200), 0xFF);
+        assert_eq!(cpu.read_u16(true, 0x04000200), 0x00FF);
+
+        // IF: write-1-to-clear, halfword and byte
+        cpu.io_if = IRQ_VBLANK | IRQ_HBLANK | IRQ_KEYPAD;
+        cpu.write_u16(true, 0x04000202, IRQ_VBLANK);
+        assert_eq!(cpu.io_if, IRQ_HBLANK | IRQ_KEYPAD);
+        cpu.write_u8(true, 0x04000203, (IRQ_KEYPAD >> 8) as u8);
+        assert_eq!(cpu.io_if, IRQ_HBLANK);
+        assert_eq!(
+            cpu.read_u32(true, 0x04000200),
+            ((IRQ_HBLANK as u32) << 16) | 0x00FF
+        );
+
+        // WAITCNT: bit 15 read-only
+        cpu.write_u16(true, 0x04000204, 0xFFFF);
+        assert_eq!(cpu.read_u16(true, 0x04000204), 0x5FFF);
+
+        // IME: only bit 0
+        cpu.write_u8(true, 0x04000208, 0xFF);
+        assert_eq!(cpu.read_u32(true, 0x04000208), 0x1);
+    }
+
+    #[test]
+    fn io_haltcnt() {
+        let mut cpu = CPU::new();
+
+        // POSTFLG does not halt
+        cpu.write_u8(true, 0x04000300, 0x01);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.read_u8(true, 0x04000300), 0x01);
+
+        // HALTCNT is write-only
+        cpu.write_u8(true, 0x04000301, 0x00);
+        assert!(cpu.halt);
+        assert_eq!(cpu.read_u8(true, 0x04000301), 0x00);
+    }
+
+    /// Stop is only left through Keypad, Serial or Game Pak interrupts
+    #[test]
+    fn io_haltcnt_stop() {
+        let mut cpu = CPU::new();
+
+        cpu.io_ie = IRQ_VBLANK | IRQ_KEYPAD;
+        cpu.lcd.set_dispstat(0x0008);
+        cpu.keypad.keycnt = 0x4000;
+
+        cpu.write_u8(true, 0x04000301, 0x80);
+        assert!(cpu.halt);
+        assert!(cpu.stop);
+
+        assert!(!cpu.can_irq_trigger(IRQ_VBLANK));
+        assert!(cpu.can_irq_trigger(IRQ_KEYPAD));
+
+        cpu.trigger_irq(IRQ_KEYPAD);
+        assert!(!cpu.halt);
+        assert!(!cpu.stop);
+    }
+
+    /// Halt is left with IME=0, but the IRQ is not taken
+    #[test]
+    fn io_haltcnt_wakeup_ime_disabled() {
+        let mut cpu = CPU::new();
+
+        cpu.reg_cpsr = MODE_SYSTEM as u32 | 0x10;
+        cpu.set_program_counter(0x08000100);
+        cpu.io_ie = IRQ_VBLANK;
+        cpu.lcd.set_dispstat(0x0008);
+
+        cpu.write_u8(true, 0x04000301, 0x00);
+        assert!(cpu.can_irq_trigger(IRQ_VBLANK));
+
+        cpu.trigger_irq(IRQ_VBLANK);
+        assert!(!cpu.halt);
+        assert_eq!(cpu.io_if, IRQ_VBLANK);
+        assert_eq!(cpu.get_mode(), MODE_SYSTEM);
+        assert_eq!(cpu.get_program_counter(), 0x08000100);
+    }
+
+    /// KEYCNT OR condition: any selected button
+    #[test]
+    fn keypad_irq_or() {
+        let mut cpu = CPU::new();
+
+        cpu.io_ie = IRQ_KEYPAD;
+        cpu.write_u8(true, 0x04000132, (BUTTON_A | BUTTON_B) as u8);
+        cpu.write_u8(true, 0x04000133, 0x40);
+
+        cpu.keypad_press(BUTTON_START);
+        assert_eq!(cpu.io_if, 0);
+
+        cpu.keypad_press(BUTTON_B);
+        assert_eq!(cpu.io_if, IRQ_KEYPAD);
+    }
+
+    /// KEYCNT AND condition: all selected buttons, wakes from Stop
+    #[test]
+    fn keypad_irq_and_stop() {
+        let mut cpu = CPU::new();
+
+        cpu.io_ie = IRQ_KEYPAD;
+        cpu.write_u16(true, 0x04000132, 0xC000 | BUTTON_A | BUTTON_B);
+
+        cpu.write_u8(true, 0x04000301, 0x80);
+        assert!(cpu.stop);
+
+        cpu.keypad_press(BUTTON_A);
+        assert_eq!(cpu.io_if, 0);
+        assert!(cpu.stop);
+
+        cpu.keypad_press(BUTTON_B);
+        assert_eq!(cpu.io_if, IRQ_KEYPAD);
+        assert!(!cpu.stop);
+        assert!(!cpu.halt);
+
+        // Condition is checked on KEYCNT writes as well
+        cpu.io_if = 0;
+        cpu.keypad_release(BUTTON_A | BUTTON_B);
+        cpu.keypad_press(BUTTON_R);
+        cpu.write_u16(true, 0x04000132, 0xC000 | BUTTON_R);
+        assert_eq!(cpu.io_if, IRQ_KEYPAD);
+    }
+
+    #[test]
+    fn io_read_write_masks() {
+        let mut cpu = CPU::new();
+
+        // DISPSTAT flags and VCOUNT are read-only
+        cpu.lcd.set_vcount(100);
+        cpu.write_u32(true, 0x04000004, 0xFFFFFFFF);
+        assert_eq!(cpu.read_u16(true, 0x04000004), 0xFF38);
+        assert_eq!(cpu.read_u16(true, 0x04000006), 100);
+
+        // BG0HOFS is write-only
+        cpu.write_u16(true, 0x04000010, 0x1234);
+        assert_eq!(cpu.lcd.get_background_offset(0).0, 0x0034);
+        assert_eq!(cpu.read_u16(true, 0x04000010), 0);
+
+        // DMA addresses are write-only, DMA0 source is 27 bit
+        cpu.write_u32(true, 0x040000B0, 0xFFFFFFFF);
+        assert_eq!(cpu.dma_register(0, 0), 0x07FFFFFF);
+        assert_eq!(cpu.read_u32(true, 0x040000B0), 0);
+
+        // KEYINPUT is read-only
+        cpu.write_u16(true, 0x04000130, 0x0000);
+        assert_eq!(cpu.read_u16(true, 0x04000130), 0x03FF);
+        cpu.write_u16(true, 0x04000132, 0xFFFF);
+        assert_eq!(cpu.read_u16(true, 0x04000132), 0xC3FF);
+    }
+
     #[test]
     fn syscall_sqrt() {
-        let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-        let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-        let mut cpu = CPU::new(&vram, &palette, &oam);
+        let mut cpu = CPU::new();
 
         cpu.write_register(0, 2);
         cpu.syscall_sqrt();
         assert_eq!(cpu.read_register(0), 1);
+
+        // Exact above 2^24
+        cpu.write_register(0, 0xFFFFFFFF);
+        cpu.syscall_sqrt();
+        assert_eq!(cpu.read_register(0), 0xFFFF);
+
+        cpu.write_register(0, 0xFFFE0001);
+        cpu.syscall_sqrt();
+        assert_eq!(cpu.read_register(0), 0xFFFF);
+    }
+
+    #[test]
+    fn syscall_arctan() {
+        let mut cpu = CPU::new();
+
+        // tan = 1.0 and 0.5 (1.1.14), 45 degrees is 0x2000
+        cpu.write_register(0, 0x4000);
+        cpu.bios_syscall(0x09);
+        assert_eq!(cpu.read_register(0), 0x2000);
+        assert_eq!(cpu.read_register(1), -0x4000i32 as u32);
+        assert_eq!(cpu.read_register(3), 0x8000);
+
+        cpu.write_register(0, 0x2000);
+        cpu.bios_syscall(0x09);
+        assert_eq!(cpu.read_register(0), 0x12E4);
+
+        cpu.write_register(0, -0x2000i32 as u32);
+        cpu.bios_syscall(0x09);
+        assert_eq!(cpu.read_register(0), -0x12E4i32 as u32);
+
+        // ArcTan2 covers the full circle
+        for (x, y, angle) in [
+            (0x4000, 0x4000, 0x2000),
+            (0x4000, 0x2000, 0x12E4),
+            (0x2000, 0x4000, 0x4000 - 0x12E4),
+            (-0x4000, 0x4000, 0x6000),
+            (-0x4000, 0, 0x8000),
+            (-0x4000, -0x4000, 0xA000),
+            (0, -0x4000, 0xC000),
+            (0x4000, -0x2000, 0x10000 - 0x12E4),
+            (0, 0, 0),
+        ] {
+            cpu.write_register(0, x as u32);
+            cpu.write_register(1, y as u32);
+            cpu.bios_syscall(0x0A);
+            assert_eq!(cpu.read_register(0), angle, "ArcTan2({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn syscall_bit_unpack() {
+        let mut cpu = CPU::new();
+
+        // 2-bit units 3, 2, 1, 0 to bytes with offset 1
+        cpu.write_u8(false, 0x02000000, 0x1B);
+        cpu.write_u16(false, 0x02000010, 1);
+        cpu.write_u8(false, 0x02000012, 2);
+        cpu.write_u8(false, 0x02000013, 8);
+        cpu.write_u32(false, 0x02000014, 1);
+
+        cpu.write_register(0, 0x02000000);
+        cpu.write_register(1, 0x02001000);
+        cpu.write_register(2, 0x02000010);
+        cpu.bios_syscall(0x10);
+        assert_eq!(cpu.read_u32(false, 0x02001000), 0x00020304);
+
+        // Zero flag offsets zero units too
+        cpu.write_u32(false, 0x02000014, 0x80000001);
+        cpu.bios_syscall(0x10);
+        assert_eq!(cpu.read_u32(false, 0x02001000), 0x01020304);
+
+        // 1-bit font to 4-bit pixels
+        cpu.write_u16(false, 0x02000000, 0x0081);
+        cpu.write_u16(false, 0x02000010, 2);
+        cpu.write_u8(false, 0x02000012, 1);
+        cpu.write_u8(false, 0x02000013, 4);
+        cpu.write_u32(false, 0x02000014, 0);
+        cpu.bios_syscall(0x10);
+        assert_eq!(cpu.read_u32(false, 0x02001000), 0x10000001);
+        assert_eq!(cpu.read_u32(false, 0x02001004), 0x00000000);
+    }
+
+    #[test]
+    fn syscall_sound_bias() {
+        let mut cpu = CPU::new();
+        cpu.write_u16(false, 0x04000088, 0xC000);
+
+        // Ramps to 200h in steps of 2, keeping the amplitude resolution bits
+        cpu.write_register(0, 1);
+        cpu.bios_syscall(0x19);
+        assert_eq!(cpu.read_u16(false, 0x04000088), 0xC200);
+        assert_eq!(cpu.cycle_count, 0x100 * SOUND_BIAS_STEP_CYCLES);
+
+        cpu.write_register(0, 0);
+        cpu.bios_syscall(0x19);
+        assert_eq!(cpu.read_u16(false, 0x04000088), 0xC000);
+    }
+
+    #[test]
+    fn syscall_midi_key_2_freq() {
+        let mut cpu = CPU::new();
+
+        // WaveData with a sample rate of 13379 Hz (22.10 fixed point)
+        cpu.write_u32(false, 0x02000004, 13379 << 10);
+        cpu.write_register(0, 0x02000000);
+
+        for (key, fine, freq) in [
+            (60, 0, 13379),
+            (72, 0, 26758),
+            (48, 0, 6689),
+            (60, 128, 13771),
+        ] {
+            cpu.write_register(0, 0x02000000);
+            cpu.write_register(1, key);
+            cpu.write_register(2, fine);
+            cpu.bios_syscall(0x1F);
+            assert_eq!(cpu.read_register(0), freq, "key {key} + {fine}/256");
+        }
+
+        // Sound driver calls are harmless
+        for syscall in [0x1B, 0x1C, 0x1D, 0x1E, 0x20, 0x21, 0x22, 0x23, 0x24, 0x28] {
+            cpu.bios_syscall(syscall);
+        }
+
+        cpu.write_register(0, 0x03001000);
+        cpu.bios_syscall(0x1A);
+        assert_eq!(cpu.read_u32(false, BIOS_SOUND_AREA), 0x03001000);
+
+        // Every jump list entry points to a BIOS stub returning to the caller
+        cpu.write_register(0, 0x03002000);
+        cpu.bios_syscall(0x2A);
+        for i in 0..0x24 {
+            assert_eq!(cpu.read_u32(false, 0x03002000 + i * 4), 0x78);
+        }
+        assert_eq!(cpu.read_u32(false, 0x03002000 + 0x24 * 4), 0);
+
+        let stub = cpu.read_u32(false, 0x03002000);
+        cpu.set_program_counter(stub);
+        cpu.write_register(14, 0x03000101);
+        run_until(&mut cpu, 0x03000100, 2);
+        assert!(cpu.is_thumb());
+    }
+
+    #[test]
+    fn syscall_get_bios_checksum() {
+        let mut cpu = CPU::new();
+
+        cpu.bios_syscall(0x0D);
+        assert_eq!(cpu.read_register(0), 0xBAAE187F);
+    }
+
+    #[test]
+    fn syscall_soft_reset() {
+        let mut cpu = CPU::new();
+        cpu.reset();
+
+        cpu.write_u8(false, 0x03007FFA, 1);
+        cpu.write_u32(false, 0x03007E10, 0x12345678);
+        cpu.write_register(0, 0xAAAA);
+        cpu.set_thumb(true);
+        cpu.set_program_counter(0x08000100);
+
+        // SWI 0x00 from Thumb
+        cpu.execute_thumb(0xDF00);
+
+        assert_eq!(cpu.get_program_counter(), 0x02000000);
+        assert!(!cpu.is_thumb());
+        assert_eq!(cpu.reg_cpsr, 0x1F);
+        assert_eq!(cpu.read_register(0), 0);
+        assert_eq!(cpu.read_register(13), 0x03007F00);
+        assert_eq!(cpu.regs_svc[0], 0x03007FE0);
+        assert_eq!(cpu.regs_irq[0], 0x03007FA0);
+        assert_eq!(cpu.read_u32(false, 0x03007E10), 0);
+        assert_eq!(cpu.read_u8(false, 0x03007FFA), 0);
+
+        // Flag cleared: back to ROM
+        cpu.execute_arm(0xEF000000);
+        assert_eq!(cpu.get_program_counter(), 0x08000000);
+    }
+
+    #[test]
+    fn syscall_obj_affine_set() {
+        let mut cpu = CPU::new();
+
+        // (scale_x, scale_y, angle): identity, 90 degrees, 45 degrees at 2x, -45 degrees
+        let src = [
+            (0x0100, 0x0100, 0x0000),
+            (0x0100, 0x0100, 0x4000),
+            (0x0200, 0x0200, 0x2000),
+            (0x0100, 0x0100, 0xE000),
+        ];
+        for (i, (sx, sy, angle)) in src.iter().enumerate() {
+            let addr = 0x02000000 + (i as u32) * 8;
+            cpu.write_u16(false, addr, *sx);
+            cpu.write_u16(false, addr + 2, *sy);
+            cpu.write_u16(false, addr + 4, *angle);
+        }
+
+        cpu.write_register(0, 0x02000000);
+        cpu.write_register(1, 0x02001000);
+        cpu.write_register(2, 4);
+        cpu.write_register(3, 2);
+        cpu.bios_syscall(0x0F);
+
+        let params: Vec<u16> = (0..16)
+            .map(|i| cpu.read_u16(false, 0x02001000 + i * 2))
+            .collect();
+        assert_eq!(
+            params,
+            [
+                0x0100, 0x0000, 0x0000, 0x0100, // Identity
+                0x0000, 0xFF00, 0x0100, 0x0000, // 90 degrees
+                0x016A, 0xFE96, 0x016A, 0x016A, // sin/cos 0x2D41 at 2x
+                0x00B5, 0x00B6, 0xFF4A, 0x00B5, // Negative products round down
+            ]
+        );
+
+        // OAM stride of 8 bytes: parameters in the 4th halfword of consecutive entries
+        cpu.write_register(0, 0x02000010);
+        cpu.write_register(1, 0x07000006);
+        cpu.write_register(2, 1);
+        cpu.write_register(3, 8);
+        cpu.bios_syscall(0x0F);
+
+        assert_eq!(cpu.read_u16(false, 0x07000006), 0x016A);
+        assert_eq!(cpu.read_u16(false, 0x0700000E), 0xFE96);
+        assert_eq!(cpu.read_u16(false, 0x07000016), 0x016A);
+        assert_eq!(cpu.read_u16(false, 0x0700001E), 0x016A);
+    }
+
+    #[test]
+    fn syscall_bg_affine_set() {
+        let mut cpu = CPU::new();
+
+        // Original center (64.0, 32.0) at display (120, 80), scale 1, angle 0 and 90 degrees
+        for (i, angle) in [0x0000, 0x4000].iter().enumerate() {
+            let addr = 0x02000000 + (i as u32) * 20;
+            cpu.write_u32(false, addr, 64 << 8);
+            cpu.write_u32(false, addr + 4, 32 << 8);
+            cpu.write_u16(false, addr + 8, 120);
+            cpu.write_u16(false, addr + 10, 80);
+            cpu.write_u16(false, addr + 12, 0x0100);
+            cpu.write_u16(false, addr + 14, 0x0100);
+            cpu.write_u16(false, addr + 16, *angle);
+        }
+
+        cpu.write_register(0, 0x02000000);
+        cpu.write_register(1, 0x02001000);
+        cpu.write_register(2, 2);
+        cpu.bios_syscall(0x0E);
+
+        // Identity: start = orig - disp
+        assert_eq!(cpu.read_u32(false, 0x02001000), 0x0000_0100);
+        assert_eq!(cpu.read_u32(false, 0x02001004), 0x0100_0000);
+        assert_eq!(cpu.read_u32(false, 0x02001008) as i32, (64 - 120) << 8);
+        assert_eq!(cpu.read_u32(false, 0x0200100C) as i32, (32 - 80) << 8);
+
+        // 90 degrees: PB = -1, PC = 1
+        assert_eq!(cpu.read_u32(false, 0x02001010), 0xFF00_0000);
+        assert_eq!(cpu.read_u32(false, 0x02001014), 0x0000_0100);
+        assert_eq!(cpu.read_u32(false, 0x02001018) as i32, (64 + 80) << 8);
+        assert_eq!(cpu.read_u32(false, 0x0200101C) as i32, (32 - 120) << 8);
+    }
+
+    /// Runs the decompression `syscall` on `fixture` from EWRAM to `dest`
+    fn uncomp_fixture(syscall: u8, fixture: &[u8], dest: u32) -> CPU {
+        let mut cpu = CPU::new();
+        for (i, val) in fixture.iter().enumerate() {
+            cpu.write_u8(false, 0x02000000 + i as u32, *val);
+        }
+
+        cpu.write_register(0, 0x02000000);
+        cpu.write_register(1, dest);
+        cpu.bios_syscall(syscall);
+        cpu
+    }
+
+    fn read_bytes(cpu: &mut CPU, addr: u32, len: u32) -> Vec<u8> {
+        (0..len).map(|i| cpu.read_u8(false, addr + i)).collect()
+    }
+
+    #[test]
+    fn syscall_lz77_uncomp() {
+        // "AB", copy 6 from 2 back, "X", "ABC", copy 3 from 3 back
+        let fixture = [
+            0x10, 0x0F, 0x00, 0x00, 0x21, b'A', b'B', 0x30, 0x01, b'X', b'A', b'B', b'C', 0x00,
+            0x02,
+        ];
+
+        let mut cpu = uncomp_fixture(0x11, &fixture, 0x02001000);
+        assert_eq!(read_bytes(&mut cpu, 0x02001000, 16), b"ABABABABXABCABC\0");
+
+        // VRAM writes halfwords, the trailing odd byte is dropped
+        let mut cpu = uncomp_fixture(0x12, &fixture, 0x06000000);
+        assert_eq!(read_bytes(&mut cpu, 0x06000000, 16), b"ABABABABXABCAB\0\0");
+
+        // Sources in the BIOS are ignored
+        cpu.write_register(0, 0x00000100);
+        cpu.write_register(1, 0x02002000);
+        cpu.bios_syscall(0x11);
+        assert_eq!(read_bytes(&mut cpu, 0x02002000, 4), [0; 4]);
+    }
+
+    #[test]
+    fn syscall_huff_uncomp() {
+        // 8-bit symbols, tree: root -> ('A', 'B'), bits 0100
+        let fixture = [
+            0x28, 0x04, 0x00, 0x00, 0x01, 0xC0, b'A', b'B', 0x00, 0x00, 0x00, 0x40,
+        ];
+        let mut cpu = uncomp_fixture(0x13, &fixture, 0x02001000);
+        assert_eq!(read_bytes(&mut cpu, 0x02001000, 4), b"ABAA");
+
+        // 4-bit symbols, tree: root -> (1, node -> (2, 3)), bits 0 10 11 0 0 10 11 10
+        let fixture = [
+            0x24, 0x04, 0x00, 0x00, 0x03, 0x80, 0x01, 0xC0, 0x02, 0x03, 0x00, 0x00, 0x00, 0x00,
+            0x70, 0x59,
+        ];
+        let mut cpu = uncomp_fixture(0x13, &fixture, 0x02001000);
+        assert_eq!(
+            read_bytes(&mut cpu, 0x02001000, 4),
+            [0x21, 0x13, 0x21, 0x23]
+        );
+    }
+
+    #[test]
+    fn syscall_rl_uncomp() {
+        // Run of 5 'A', raw "BCD"
+        let fixture = [0x30, 0x08, 0x00, 0x00, 0x82, b'A', 0x02, b'B', b'C', b'D'];
+
+        let mut cpu = uncomp_fixture(0x14, &fixture, 0x02001000);
+        assert_eq!(read_bytes(&mut cpu, 0x02001000, 9), b"AAAAABCD\0");
+
+        let mut cpu = uncomp_fixture(0x15, &fixture, 0x06000000);
+        assert_eq!(read_bytes(&mut cpu, 0x06000000, 9), b"AAAAABCD\0");
+    }
+
+    #[test]
+    fn syscall_diff_unfilter() {
+        let fixture = [0x81, 0x04, 0x00, 0x00, 0x01, 0x01, 0x02, 0xFF];
+
+        let mut cpu = uncomp_fixture(0x16, &fixture, 0x02001000);
+        assert_eq!(
+            read_bytes(&mut cpu, 0x02001000, 4),
+            [0x01, 0x02, 0x04, 0x03]
+        );
+
+        let mut cpu = uncomp_fixture(0x17, &fixture, 0x06000000);
+        assert_eq!(
+            read_bytes(&mut cpu, 0x06000000, 4),
+            [0x01, 0x02, 0x04, 0x03]
+        );
+
+        let fixture = [0x82, 0x06, 0x00, 0x00, 0x00, 0x10, 0x34, 0x02, 0xFF, 0xFF];
+        let mut cpu = uncomp_fixture(0x18, &fixture, 0x06000000);
+        assert_eq!(cpu.read_u16(false, 0x06000000), 0x1000);
+        assert_eq!(cpu.read_u16(false, 0x06000002), 0x1234);
+        assert_eq!(cpu.read_u16(false, 0x06000004), 0x1233);
+    }
+
+    #[test]
+    fn scheduler_lcd_events() {
+        let mut cpu = CPU::new();
+        cpu.reset();
+        cpu.scheduler.cancel(Event::AudioSample);
+
+        cpu.io_ie = IRQ_HBLANK;
+        cpu.lcd.set_dispstat(0x0010);
+        assert_eq!(cpu.scheduler.next_timestamp(), 1006);
+
+        // HBlank is raised once per scanline
+        cpu.cycle_count = 1006;
+        assert!(!cpu.run_events());
+        assert!((cpu.lcd.get_dispstat() & DISPSTAT_HBLANK) != 0);
+        assert_eq!(cpu.io_if, IRQ_HBLANK);
+
+        cpu.io_if = 0;
+        cpu.cycle_count = 1100;
+        cpu.run_events();
+        assert_eq!(cpu.io_if, 0);
+
+        cpu.cycle_count = 1232;
+        cpu.run_events();
+        assert!((cpu.lcd.get_dispstat() & DISPSTAT_HBLANK) == 0);
+        assert_eq!(cpu.lcd.get_vcount(), 1);
+        assert_eq!(cpu.scheduler.next_timestamp(), 1232 + 1006);
+
+        // A full frame later VCount wraps around
+        cpu.cycle_count = 228 * 1232;
+        assert!(cpu.run_events());
+        assert_eq!(cpu.lcd.get_vcount(), 0);
+    }
+
+    #[test]
+    fn audio_sample_event() {
+        let mut cpu = CPU::new();
+        cpu.reset();
+
+        // Nothing is sampled while the sound circuits are off
+        cpu.cycle_count = 512;
+        cpu.run_events();
+        assert!(cpu.sound.samples.is_empty());
+
+        cpu.write_u16(true, 0x04000084, 0x0080);
+        cpu.cycle_count = 512 * 3;
+        cpu.run_events();
+        assert_eq!(cpu.sound.samples.len(), 2);
+        assert!(cpu.scheduler.is_scheduled(Event::AudioSample));
+        assert_eq!(cpu.scheduler.next_timestamp(), 512 * 4);
+    }
+
+    #[test]
+    fn timer_overflow_cascade() {
+        let mut cpu = CPU::new();
+
+        cpu.io_ie = IRQ_TIM0 | IRQ_TIM1;
+
+        // TM1: count-up with IRQ, TM0: F/64 starting at 0xFFF0
+        cpu.write_u16(true, 0x04000104, 0xFFFF);
+        cpu.write_u16(true, 0x04000106, 0x00C4);
+        cpu.write_u16(true, 0x04000100, 0xFFF0);
+        cpu.write_u16(true, 0x04000102, 0x0081);
+        assert_eq!(cpu.read_u16(true, 0x04000100), 0xFFF0);
+
+        cpu.cycle_count = 64 * 4 + 10;
+        cpu.run_events();
+        assert_eq!(cpu.read_u16(true, 0x04000100), 0xFFF4);
+        assert_eq!(cpu.io_if, 0);
+
+        // TM0 overflow reloads and cascades into TM1
+        cpu.cycle_count = 64 * 16;
+        cpu.run_events();
+        assert_eq!(cpu.read_u16(true, 0x04000100), 0xFFF0);
+        assert_eq!(cpu.read_u16(true, 0x04000104), 0xFFFF);
+        assert_eq!(cpu.io_if, IRQ_TIM1);
+
+        // Disabling latches the counter
+        cpu.cycle_count += 64 * 2;
+        cpu.write_u16(true, 0x04000102, 0x0001);
+        cpu.cycle_count += 64 * 8;
+        assert_eq!(cpu.read_u16(true, 0x04000100), 0xFFF2);
+        assert_eq!(cpu.scheduler.next_timestamp(), usize::MAX);
+    }
+
+    #[test]
+    fn dma_immediate_event() {
+        let mut cpu = CPU::new();
+
+        cpu.io_ie = IRQ_DMA3;
+        cpu.write_u32(true, 0x03000000, 0x11223344);
+        cpu.write_u32(true, 0x03000004, 0x55667788);
+
+        cpu.write_u32(true, 0x040000D4, 0x03000000);
+        cpu.write_u32(true, 0x040000D8, 0x02000000);
+        cpu.write_u32(true, 0x040000DC, 0xC400_0002);
+        assert_eq!(cpu.read_u32(true, 0x02000000), 0);
+
+        // Starts 2 cycles after enable
+        cpu.cycle_count = 2;
+        cpu.run_events();
+        assert_eq!(cpu.read_u32(true, 0x02000000), 0x11223344);
+        assert_eq!(cpu.read_u32(true, 0x02000004), 0x55667788);
+        assert_eq!(cpu.read_u16(true, 0x040000DE) & 0x8000, 0);
+        assert_eq!(cpu.io_if, IRQ_DMA3);
+    }
+
+    #[test]
+    fn serial_transfer_event() {
+        let mut cpu = CPU::new();
+
+        cpu.io_ie = IRQ_SERIAL;
+
+        // Normal 8-bit, internal 256KHz clock, IRQ enable, start
+        cpu.write_u16(true, 0x04000128, 0x4081);
+        cpu.cycle_count = 8 * 64 - 1;
+        cpu.run_events();
+        assert_eq!(cpu.io_if, 0);
+
+        cpu.cycle_count = 8 * 64;
+        cpu.run_events();
+        assert_eq!(cpu.read_u16(true, 0x04000128) & 0x80, 0);
+        assert_eq!(cpu.read_u8(true, 0x0400012A), 0xFF);
+        assert_eq!(cpu.io_if, IRQ_SERIAL);
+    }
+
+    #[test]
+    fn run_loop_events() {
+        let code: [u32; 3] = [
+            0xE5801000, // str r1, [r0]         ;DMA3CNT, immediate
+            0xE5932000, // ldr r2, [r3]
+            0xEAFFFFFE, // b .
+        ];
+
+        for use_blocks in [false, true] {
+            let mut cpu = CPU::new();
+            cpu.reg_cpsr = MODE_SYSTEM as u32;
+            for (i, opcode) in code.iter().enumerate() {
+                cpu.write_u32(true, 0x03000000 + (i as u32) * 4, *opcode);
+            }
+            cpu.set_program_counter(0x03000000);
+            cpu.scheduler.reschedule(1000, Event::HBlank);
+
+            cpu.write_u32(true, 0x03001000, 0x11223344);
+            cpu.write_u32(true, 0x040000D4, 0x03001000);
+            cpu.write_u32(true, 0x040000D8, 0x02000000);
+            cpu.write_register(0, 0x040000DC);
+            cpu.write_register(1, 0x8400_0001);
+            cpu.write_register(3, 0x02000000);
+
+            // The DMA scheduled by the first instruction ends the run before the load
+            for _ in 0..4 {
+                if cpu.get_program_counter() == 0x03000008 {
+                    break;
+                }
+                cpu.run_until_event(use_blocks, false, |_, _, _| true);
+                cpu.run_events();
+            }
+            assert_eq!(cpu.read_register(2), 0x11223344, "blocks: {use_blocks}");
+        }
+
+        // Halt fast-forwards to the earliest event, including ones scheduled meanwhile
+        let mut cpu = CPU::new();
+        cpu.scheduler.reschedule(1000, Event::HBlank);
+        cpu.scheduler.reschedule(50, Event::Serial);
+        cpu.halt = true;
+        cpu.run_until_event(false, false, |_, _, _| true);
+        assert_eq!(cpu.cycle_count, 50);
+    }
+
+    #[test]
+    fn masked_irq_pending() {
+        for use_blocks in [false, true] {
+            let mut cpu = CPU::new();
+            cpu.reg_cpsr = MODE_SYSTEM as u32;
+            cpu.write_u32(true, 0x03000000, 0xE5801000); // str r1, [r0]         ;IME
+            cpu.write_u32(true, 0x03000004, 0xE3A02001); // mov r2, #1
+            cpu.write_u32(true, 0x03000008, 0xEAFFFFFE); // b .
+            cpu.set_program_counter(0x03000000);
+            cpu.write_register(0, 0x04000208);
+            cpu.write_register(1, 1);
+
+            // Raised inside an IME=0 critical section, the IRQ stays pending in IF
+            cpu.io_ie = IRQ_VBLANK;
+            cpu.trigger_irq(IRQ_VBLANK);
+            assert_eq!(cpu.io_if, IRQ_VBLANK);
+            assert_eq!(cpu.get_mode(), MODE_SYSTEM);
+
+            // The IME write ends the block
+            match use_blocks {
+                false => cpu.run_until_event(false, true, |_, _, _| true),
+                true => cpu.execute_block().unwrap().0,
+            };
+            assert_eq!(cpu.io_ime, 1);
+            assert_eq!(cpu.get_mode(), MODE_SYSTEM);
+            assert_eq!(cpu.get_program_counter(), 0x03000004);
+
+            // Taken before the next instruction
+            cpu.run_until_event(false, true, |_, _, _| true);
+            assert_eq!(cpu.get_mode(), MODE_IRQ);
+            assert_eq!(cpu.regs_spsr[MODE_IRQ as usize], MODE_SYSTEM as u32);
+            assert_eq!(cpu.read_register(14), 0x03000008);
+            assert_eq!(cpu.read_register(2), 0);
+        }
+    }
+
+    #[test]
+    fn arm_msr_field_mask() {
+        let mut cpu = CPU::new();
+        cpu.reg_cpsr = 0x6000001F;
+
+        // MSR CPSR_c,R0 only writes the control byte
+        cpu.write_register(0, 0xF00000D2);
+        cpu.execute_arm(0xE121F000);
+        assert_eq!(cpu.reg_cpsr, 0x600000D2);
+
+        // MSR SPSR_fc,R0
+        cpu.write_register(0, 0x8000001F);
+        cpu.execute_arm(0xE169F000);
+        assert_eq!(cpu.regs_spsr[MODE_IRQ as usize], 0x8000001F);
+    }
+
+    /// Interpreter and block cache speed, see the README to run it
+    #[ignore]
+    #[test]
+    fn bench_instructions_per_second() {
+        let mut cpu = CPU::new();
+
+        // ARM: ADD R0,R0,#1; SUBS R1,R1,#1; BNE 0x03000000
+        cpu.write_u32(false, 0x03000000, 0xE2800001);
+        cpu.write_u32(false, 0x03000004, 0xE2511001);
+        cpu.write_u32(false, 0x03000008, 0x1AFFFFFC);
+
+        // Thumb: ADD R0,#1; SUB R1,#1; BNE 0x03000100
+        cpu.write_u16(false, 0x03000100, 0x3001);
+        cpu.write_u16(false, 0x03000102, 0x3901);
+        cpu.write_u16(false, 0x03000104, 0xD1FC);
+
+        const INSTRUCTIONS: usize = 3_000_000;
+
+        for (name, pc, thumb, cached) in [
+            ("ARM", 0x03000000, false, false),
+            ("Thumb", 0x03000100, true, false),
+            ("ARM blocks", 0x03000000, false, true),
+            ("Thumb blocks", 0x03000100, true, true),
+        ] {
+            cpu.reg_cpsr = MODE_SYSTEM as u32;
+            cpu.set_thumb(thumb);
+            cpu.set_program_counter(pc);
+            cpu.write_register(0, 0);
+            cpu.write_register(1, u32::MAX);
+
+            let start = std::time::Instant::now();
+            let mut executed = 0;
+            while executed < INSTRUCTIONS {
+                if cached {
+                    executed += cpu.execute_block().unwrap().0;
+                    continue;
+                }
+
+                let pc = cpu.get_program_counter();
+                let opcode = match thumb {
+                    false => cpu.read_u32(false, pc),
+                    true => cpu.read_u16(false, pc) as u32,
+                };
+                cpu.execute(opcode);
+                executed += 1;
+            }
+            let elapsed = start.elapsed().as_secs_f64();
+
+            assert_eq!(cpu.read_register(0), (INSTRUCTIONS / 3) as u32);
+            println!(
+                "{name}: {:.0} instructions/s",
+                (INSTRUCTIONS as f64) / elapsed
+            );
+        }
+    }
+
+    #[test]
+    fn mmu_pages() {
+        let mut cpu = CPU::new();
+        let rom: Vec<u8> = (0..(64 * 1024 + 3)).map(|i| i as u8).collect();
+        cpu.load_rom(&rom);
+
+        // Little-endian loads from ROM and its mirrors, the partial last page takes the slow path
+        assert_eq!(cpu.read_u32(true, 0x08000100), 0x03020100);
+        assert_eq!(cpu.read_u16(true, 0x0D000102), 0x0302);
+        assert_eq!(cpu.read_u8(true, 0x08010002), 0x02);
+
+        // Accesses crossing a page boundary are composed from bytes
+        cpu.write_u32(true, 0x02007FFE, 0xAABBCCDD);
+        assert_eq!(cpu.read_u8(true, 0x02007FFF), 0xCC);
+        assert_eq!(cpu.read_u8(true, 0x02008000), 0xBB);
+        assert_eq!(cpu.read_u16(true, 0x02007FFF), 0xBBCC);
+        assert_eq!(cpu.mem_ptr, 0x02008000);
+
+        cpu.write_u16(true, 0x06017FFE, 0x1234);
+        assert_eq!(cpu.ram_video[0x17FFE..], [0x34, 0x12]);
+
+        // IWRAM mirror still maps to the top of IWRAM
+        cpu.write_u32(true, 0x03FFFFFC, 0x0300_1000);
+        assert_eq!(cpu.read_u32(true, 0x03007FFC), 0x0300_1000);
+
+        // ROM is not writable
+        cpu.write_u32(true, 0x08000100, 0);
+        assert!(cpu.panic);
+        assert_eq!(cpu.read_u32(true, 0x08000100), 0x03020100);
+    }
+
+    #[test]
+    fn idle_loop_skip() {
+        let mut cpu = CPU::new();
+        cpu.set_thumb(true);
+
+        let run = |cpu: &mut CPU, code: &[u16]| -> bool {
+            for (i, opcode) in code.iter().enumerate() {
+                cpu.write_u16(true, 0x03000000 + (i as u32) * 2, *opcode);
+            }
+            let branch_pc = 0x03000000 + (code.len() as u32 - 1) * 2;
+
+            cpu.cycle_count = 100;
+            cpu.scheduler.reschedule(1000, Event::HBlank);
+            cpu.set_program_counter(0x03000000);
+            cpu.skip_idle_loop(branch_pc)
+        };
+
+        // ldrh r0, [r1]; cmp r0, #160; bne start
+        let wait_vcount = [0x8808, 0x28A0, 0xD1FC];
+        assert!(run(&mut cpu, &wait_vcount));
+        assert_eq!(cpu.cycle_count, 1000);
+        assert_eq!(cpu.idle_skipped_cycles, 900);
+
+        // add r0, #1; cmp r0, #160; bne start
+        assert!(!run(&mut cpu, &[0x3001, 0x28A0, 0xD1FC]));
+
+        // ldrh r0, [r1]; strh r0, [r2]; bne start
+        assert!(!run(&mut cpu, &[0x8808, 0x8010, 0xD1FC]));
+
+        // Timer counters change without an event
+        cpu.write_u16(true, 0x04000102, 0x0080);
+        cpu.read_u16(true, 0x04000100);
+        assert!(!run(&mut cpu, &wait_vcount));
+        assert!(run(&mut cpu, &wait_vcount));
+
+        cpu.idle_loop_skip = false;
+        assert!(!run(&mut cpu, &wait_vcount));
+        assert_eq!(cpu.idle_skipped_cycles, 1800);
+    }
+
+    #[test]
+    fn idle_loop_timer_poll() {
+        for use_blocks in [false, true] {
+            let mut cpu = CPU::new();
+            cpu.set_thumb(true);
+            cpu.write_u16(true, 0x03000000, 0x8808); // ldrh r0, [r1]
+            cpu.write_u16(true, 0x03000002, 0x28A0); // cmp r0, #160
+            cpu.write_u16(true, 0x03000004, 0xD1FC); // bne start
+            cpu.set_program_counter(0x03000000);
+            cpu.write_register(1, 0x04000100);
+
+            // TM0CNT_L counts up without an event
+            cpu.write_u16(true, 0x04000102, 0x0080);
+            cpu.scheduler.reschedule(1000, Event::HBlank);
+
+            // Every instruction is run until the event, as main does
+            let mut executed = 0;
+            while cpu.cycle_count < 1000 {
+                executed += cpu.run_until_event(use_blocks, false, |_, _, _| true);
+            }
+            assert_eq!(cpu.idle_skipped_cycles, 0);
+            assert!(executed > 3);
+        }
+    }
+
+    #[test]
+    fn block_cache_lockstep() {
+        let arm: [u32; 9] = [
+            0xE3A00000, // mov r0, #0
+            0xE3A01403, // mov r1, #0x03000000
+            0xE3A0200A, // mov r2, #10
+            0xE0800002, // loop: add r0, r0, r2
+            0xE5810100, // str r0, [r1, #0x100]
+            0xE2522001, // subs r2, r2, #1
+            0x1AFFFFFB, // bne loop
+            0xE28F3001, // add r3, pc, #1
+            0xE12FFF13, // bx r3
+        ];
+        let thumb: [u16; 6] = [
+            0x2405, // mov r4, #5
+            0x1C60, // add r0, r4, #1
+            0x3C01, // loop: sub r4, #1
+            0xD1FD, // bne loop
+            0x600C, // str r4, [r1]
+            0xE7FE, // b .
+        ];
+
+        let mut rom = vec![0; 64 * 1024];
+        for (i, opcode) in arm.iter().enumerate() {
+            rom[i * 4..i * 4 + 4].copy_from_slice(&opcode.to_le_bytes());
+        }
+        for (i, opcode) in thumb.iter().enumerate() {
+            rom[0x24 + i * 2..0x24 + i * 2 + 2].copy_from_slice(&opcode.to_le_bytes());
+        }
+
+        let mut single = CPU::new();
+        let mut blocks = CPU::new();
+        for cpu in [&mut single, &mut blocks] {
+            cpu.load_rom(&rom);
+            cpu.reset();
+        }
+
+        let mut longest = 0;
+        for _ in 0..50 {
+            let (executed, _) = blocks.execute_block().unwrap();
+            longest = longest.max(executed);
+
+            for _ in 0..executed {
+                let pc = single.get_program_counter();
+                let opcode = match single.is_thumb() {
+                    false => single.read_u32(false, pc),
+                    true => single.read_u16(false, pc) as u32,
+                };
+                single.execute(opcode);
+            }
+
+            assert_eq!(single.registers, blocks.registers);
+            assert_eq!(single.reg_cpsr, blocks.reg_cpsr);
+            assert_eq!(single.cycle_count, blocks.cycle_count);
+        }
+
+        assert!(longest > 1);
+        assert!(blocks.is_thumb());
+        assert_eq!(blocks.get_program_counter(), 0x0800002E);
+        assert_eq!(blocks.read_u32(true, 0x03000100), 55);
+        assert_eq!(single.ram_work2, blocks.ram_work2);
+    }
+
+    #[test]
+    fn block_cache_invalidate() {
+        let mut cpu = CPU::new();
+        cpu.set_thumb(true);
+
+        // mov r0, #1; b .
+        cpu.write_u16(true, 0x03000000, 0x2001);
+        cpu.write_u16(true, 0x03000002, 0xE7FE);
+
+        cpu.set_program_counter(0x03000000);
+        assert_eq!(cpu.execute_block(), Some((2, 0x03000002)));
+        assert_eq!(cpu.read_register(0), 1);
+
+        // Overwriting the code drops the cached block: mov r0, #2
+        cpu.write_u16(true, 0x03000000, 0x2002);
+        cpu.set_program_counter(0x03000000);
+        cpu.execute_block();
+        assert_eq!(cpu.read_register(0), 2);
+
+        // Writes through the IWRAM mirror
+        cpu.write_u32(true, 0x03007F00, 0xE7FE2003);
+        cpu.set_program_counter(0x03007F00);
+        cpu.execute_block();
+        assert_eq!(cpu.read_register(0), 3);
+
+        cpu.write_u8(true, 0x03FFFF00, 0x04);
+        cpu.set_program_counter(0x03007F00);
+        cpu.execute_block();
+        assert_eq!(cpu.read_register(0), 4);
+
+        // Video memory is not cached
+        cpu.set_program_counter(0x06000000);
+        assert_eq!(cpu.execute_block(), None);
+    }
+
+    /// Fetches and executes instructions until the PC reaches `pc`
+    fn run_until(cpu: &mut CPU, pc: u32, max_instructions: usize) {
+        for _ in 0..max_instructions {
+            if cpu.get_program_counter() == pc {
+                return;
+            }
+
+            let opcode = match cpu.is_thumb() {
+                false => cpu.read_u32(false, cpu.get_program_counter()),
+                true => cpu.read_u16(false, cpu.get_program_counter()) as u32,
+            };
+            cpu.execute(opcode);
+        }
+
+        panic!(
+            "PC did not reach `{pc:08X}`, at `{:08X}`",
+            cpu.get_program_counter()
+        );
+    }
+
+    #[test]
+    fn replacement_bios() {
+        let mut cpu = CPU::new();
+        cpu.reset_bios();
+
+        // Reset vector sets up the IO and stacks and boots the ROM
+        assert_eq!(cpu.get_program_counter(), 0);
+        run_until(&mut cpu, 0x08000000, 32);
+        assert_eq!(cpu.get_mode(), MODE_SYSTEM);
+        assert!((cpu.reg_cpsr & STATUS_FLAG_I) == 0);
+        assert_eq!(cpu.read_register(13), 0x03007F00);
+        assert_eq!(cpu.regs_svc[0], 0x03007FE0);
+        assert_eq!(cpu.regs_irq[0], 0x03007FA0);
+        assert_eq!(cpu.read_u16(false, 0x04000088), 0x200);
+        assert_eq!(cpu.read_u16(false, 0x04000134), 0x8000);
+        assert_eq!(cpu.read_u8(false, 0x04000300), 1);
+
+        // IRQ dispatcher calls the user handler from 0x03007FFC, which returns with BX LR
+        cpu.write_u32(false, 0x03000000, 0xE3A05005); // mov r5, #5
+        cpu.write_u32(false, 0x03000004, 0xE12FFF1E); // bx lr
+        cpu.write_u32(false, 0x03007FFC, 0x03000000);
+
+        cpu.set_program_counter(0x02000100);
+        cpu.write_register(0, 0x1234);
+        cpu.io_ime = 1;
+        cpu.trigger_irq(IRQ_VBLANK);
+        assert_eq!(cpu.get_program_counter(), 0x18);
+
+        run_until(&mut cpu, 0x03000000, 8);
+        assert_eq!(cpu.get_mode(), MODE_IRQ);
+        run_until(&mut cpu, 0x02000100, 8);
+        assert_eq!(cpu.get_mode(), MODE_SYSTEM);
+        assert_eq!(cpu.read_register(0), 0x1234);
+        assert_eq!(cpu.read_register(5), 5);
+        assert_eq!(cpu.regs_irq[0], 0x03007FA0);
+    }
+
+    #[test]
+    fn reset_post_bios_state() {
+        let mut cpu = CPU::new();
+
+        // IO state of a previous run is cleared
+        cpu.io_ime = 1;
+        cpu.io_ie = IRQ_VBLANK;
+        cpu.io_if = IRQ_VBLANK;
+        cpu.write_u16(false, 0x04000204, 0x4317); // WAITCNT
+        cpu.write_u16(false, 0x04000132, 0xC001); // KEYCNT
+        cpu.write_u16(false, 0x04000000, 0x0403); // DISPCNT
+        cpu.write_u16(false, 0x04000008, 0x1F83); // BG0CNT
+        cpu.write_u16(false, 0x04000102, 0x00C3); // TM0CNT_H
+        cpu.write_u16(false, 0x040000DE, 0x8000); // DMA3CNT_H
+        cpu.reset();
+
+        // Same state the BIOS leaves when it jumps to the ROM
+        assert_eq!(cpu.get_program_counter(), 0x08000000);
+        assert_eq!(cpu.reg_cpsr, 0x1F);
+        assert_eq!(cpu.read_register(13), 0x03007F00);
+        assert_eq!(cpu.regs_svc[0], 0x03007FE0);
+        assert_eq!(cpu.regs_irq[0], 0x03007FA0);
+        assert_eq!(cpu.regs_spsr, [0; 16]);
+        assert_eq!(cpu.read_u16(false, 0x04000088), 0x200);
+        assert_eq!(cpu.read_u16(false, 0x04000134), 0x8000);
+        assert_eq!(cpu.read_u8(false, 0x04000300), 1);
+        assert_eq!(cpu.io_ime, 0);
+        assert_eq!(cpu.io_ie, 0);
+        assert_eq!(cpu.io_if, 0);
+        for addr in [
+            0x04000204, 0x04000132, 0x04000000, 0x04000008, 0x04000102, 0x040000DE,
+        ] {
+            assert_eq!(cpu.read_u16(false, addr), 0, "{addr:08X}");
+        }
+
+        // Power-on state runs the BIOS from the reset vector
+        cpu.reset_bios();
+        assert_eq!(cpu.get_program_counter(), 0);
+        assert_eq!(cpu.reg_cpsr, 0xD3);
+        assert_eq!(cpu.read_register(13), 0);
+        assert_eq!(cpu.read_u16(false, 0x04000088), 0);
+        assert_eq!(cpu.read_u8(false, 0x04000300), 0);
+    }
+
+    #[test]
+    fn bios_read_protection() {
+        let mut cpu = CPU::new();
+        cpu.reset();
+
+        // Outside the BIOS the latched opcode is read, the debugger still sees the BIOS
+        assert_eq!(cpu.read_u32(true, 0x00000000), BIOS_LATCH_STARTUP);
+        assert_eq!(cpu.read_u8(true, 0x00000101), 0xF0);
+        assert_eq!(cpu.read_u16(true, 0x00000002), 0xE129);
+        assert_eq!(cpu.read_u32(false, 0x00000000), 0xEA000006);
+
+        // Boot through the BIOS latches the same opcode
+        cpu.reset_bios();
+        run_until(&mut cpu, 0x08000000, 32);
+        assert_eq!(cpu.read_u32(true, 0x00000000), BIOS_LATCH_STARTUP);
+
+        cpu.write_register(0, 10);
+        cpu.write_register(1, 2);
+        cpu.execute(0xEF060000); // swi 6h
+        assert_eq!(cpu.read_u32(true, 0x00003FFC), BIOS_LATCH_SWI);
+
+        // During and after the IRQ handler
+        cpu.write_u32(false, 0x03000000, 0xE12FFF1E); // bx lr
+        cpu.write_u32(false, 0x03007FFC, 0x03000000);
+
+        cpu.set_program_counter(0x02000100);
+        cpu.io_ime = 1;
+        cpu.trigger_irq(IRQ_VBLANK);
+        run_until(&mut cpu, 0x03000000, 8);
+        assert_eq!(cpu.read_u32(true, 0x00000000), 0xE25EF004);
+        run_until(&mut cpu, 0x02000100, 8);
+        assert_eq!(cpu.read_u32(true, 0x00000000), 0xE55EC002);
+    }
+
+    #[test]
+    fn sram_backup() {
+        let mut cpu = CPU::new();
+        cpu.reset();
+
+        // 8 bit bus: reads replicate the byte, writes store the byte selected by the address
+        cpu.write_u8(true, 0x0E000010, 0xA5);
+        assert_eq!(cpu.read_u16(true, 0x0E000010), 0xA5A5);
+        assert_eq!(cpu.read_u32(true, 0x0E000010), 0xA5A5A5A5);
+        cpu.write_u16(true, 0x0E000021, 0x1234);
+        assert_eq!(cpu.read_u8(true, 0x0E000021), 0x12);
+        cpu.write_u32(true, 0x0E000032, 0x12345678);
+        assert_eq!(cpu.read_u8(true, 0x0E000032), 0x34);
+
+        // Mirrored every 32 KB up to 0x0FFFFFFF
+        assert_eq!(cpu.read_u8(true, 0x0E008010), 0xA5);
+        assert_eq!(cpu.read_u8(true, 0x0FFF8010), 0xA5);
+        assert!(cpu.backup.dirty);
+    }
+
+    #[test]
+    fn eeprom_backup() {
+        /// Sends `bits` (first bit highest) to the EEPROM through DMA3
+        fn send(cpu: &mut CPU, bits: &[(u64, usize)]) {
+            let mut count = 0;
+            for (val, len) in bits {
+                for i in (0..*len).rev() {
+                    cpu.write_u16(true, 0x02000000 + count * 2, ((val >> i) & 0x1) as u16);
+                    count += 1;
+                }
+            }
+
+            dma3(cpu, 0x02000000, 0x0D000000, count);
+        }
+
+        fn dma3(cpu: &mut CPU, src: u32, dest: u32, count: u32) {
+            cpu.write_u32(true, 0x040000D4, src);
+            cpu.write_u32(true, 0x040000D8, dest);
+            cpu.write_u32(true, 0x040000DC, 0x8000_0000 | count);
+            cpu.cycle_count += 2;
+            cpu.run_events();
+        }
+
+        /// Reads the 64 bit block at `addr` through DMA3
+        fn receive(cpu: &mut CPU, addr: u64, addr_bits: usize) -> u64 {
+            send(cpu, &[(0b11, 2), (addr, addr_bits), (0, 1)]);
+            dma3(cpu, 0x0D000000, 0x02001000, 68);
+
+            let bits: Vec<u16> = (0..68)
+                .map(|i| cpu.read_u16(true, 0x02001000 + i * 2))
+                .collect();
+            assert_eq!(bits[..4], [0, 0, 0, 0]);
+            bits[4..]
+                .iter()
+                .fold(0, |data, bit| (data << 1) | (*bit as u64))
+        }
+
+        let mut cpu = CPU::new();
+        cpu.reset();
+        cpu.set_backup(BackupType::Eeprom);
+
+        // The DMA length selects the 8 KB EEPROM, the command bits are sent one per halfword
+        send(
+            &mut cpu,
+            &[(0b10, 2), (0x3FF, 14), (0xFEDCBA9876543210, 64), (0, 1)],
+        );
+        assert_eq!(cpu.backup.data.len(), 8 * 1024);
+        assert_eq!(cpu.backup.data[0x1FF8], 0xFE);
+
+        // Ready once the write completes, the block is read back through DMA3
+        assert_eq!(cpu.read_u16(true, 0x0D000000) & 0x1, 1);
+        assert_eq!(receive(&mut cpu, 0x3FF, 14), 0xFEDCBA9876543210);
+
+        // The ROM is still read below the EEPROM with large ROMs
+        cpu.backup_override = Some(BackupType::Eeprom);
+        cpu.load_rom(&vec![0x5A; 32 * 1024 * 1024]);
+        assert_eq!(cpu.read_u16(true, 0x0D000000), 0x5A5A);
+        assert_eq!(cpu.read_u16(true, 0x0DFFFF00), 1);
+    }
+
+    #[test]
+    fn backup_detection() {
+        use crate::backup::FlashChip;
+
+        fn rom_with(id: &[u8], game_code: &[u8; 4]) -> Vec<u8> {
+            let mut rom = vec![0; 0x1000];
+            rom[0xAC..0xB0].copy_from_slice(game_code);
+            rom[0x800..0x800 + id.len()].copy_from_slice(id);
+            rom
+        }
+
+        // The detected type is used by `load_rom`
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom_with(b"", b"TEST"));
+        assert_eq!(cpu.backup.kind, BackupType::Sram);
+        cpu.load_rom(&rom_with(b"FLASH1M_V103", b"TEST"));
+        assert_eq!(cpu.backup.kind, BackupType::Flash(FlashChip::Macronix128));
+
+        // Reloading the same game keeps the backup, another game gets an erased chip
+        cpu.backup.data[0] = 0x12;
+        cpu.backup.dirty = true;
+        cpu.load_rom(&rom_with(b"FLASH1M_V103", b"TEST"));
+        assert_eq!(cpu.backup.data[0], 0x12);
+        assert!(cpu.backup.dirty);
+        cpu.load_rom(&rom_with(b"FLASH1M_V103", b"TST2"));
+        assert_eq!(cpu.backup.kind, BackupType::Flash(FlashChip::Macronix128));
+        assert_eq!(cpu.backup.data[0], 0xFF);
+        assert!(!cpu.backup.dirty);
+
+        // The override wins over the detection
+        cpu.backup_override = Some(BackupType::Eeprom);
+        cpu.load_rom(&rom_with(b"SRAM_V113", b"BPEE"));
+        assert_eq!(cpu.backup.kind, BackupType::Eeprom);
     }
 }
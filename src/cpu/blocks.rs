@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+use std::hash::{BuildHasherDefault, Hasher};
+use std::rc::Rc;
+
+use super::pages::Region;
+use super::{ArmHandler, ThumbHandler, CPU, MODE_IRQ};
+use crate::decode::{self, ArmInstr, ThumbInstr};
+
+/// Longest basic block (in instructions) decoded at once
+const BLOCK_MAX_INSTRUCTIONS: usize = 64;
+
+/// Work RAM is tracked for code in 256 byte lines, EWRAM lines first then IWRAM
+const LINE_SHIFT: usize = 8;
+const LINES_WORK_RAM1: usize = (256 * 1024) >> LINE_SHIFT;
+const LINES_WORK_RAM2: usize = (32 * 1024) >> LINE_SHIFT;
+
+/// Pre-decoded instructions of a basic block
+enum Instructions {
+    Arm(Vec<(u32, ArmHandler)>),
+    Thumb(Vec<(u16, ThumbHandler)>),
+}
+
+struct Block {
+    /// First and last work RAM line covered by the block, None in ROM
+    lines: Option<(usize, usize)>,
+    instructions: Instructions,
+}
+
+/// Multiplicative hash of the block keys, the default SipHash dominates short blocks
+#[derive(Default)]
+struct KeyHasher(u64);
+
+impl Hasher for KeyHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.write_u64(*byte as u64);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u32(&mut self, i: u32) {
+        self.write_u64(i as u64);
+    }
+
+    fn write_u64(&mut self, i: u64) {
+        self.0 = (self.0.rotate_left(5) ^ i).wrapping_mul(0x51_7C_C1_B7_27_22_0A_95);
+    }
+}
+
+/// Basic blocks keyed by (PC, Thumb state)
+pub(super) struct BlockCache {
+    blocks: HashMap<(u32, bool), Rc<Block>, BuildHasherDefault<KeyHasher>>,
+    /// Work RAM lines holding cached code
+    code_lines: Vec<bool>,
+    /// Incremented whenever blocks are invalidated
+    generation: usize,
+}
+
+impl BlockCache {
+    pub fn new() -> Self {
+        Self {
+            blocks: HashMap::default(),
+            code_lines: vec![false; LINES_WORK_RAM1 + LINES_WORK_RAM2],
+            generation: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.blocks.clear();
+        self.code_lines.fill(false);
+        self.generation += 1;
+    }
+
+    fn line(region: Region, offset: usize) -> Option<usize> {
+        match region {
+            Region::WorkRam1 => Some(offset >> LINE_SHIFT),
+            Region::WorkRam2 => Some(LINES_WORK_RAM1 + (offset >> LINE_SHIFT)),
+            Region::Video | Region::Rom => None,
+        }
+    }
+
+    /// Drops the blocks overlapping `size` bytes written at `offset` of `region`
+    #[inline]
+    pub fn written(&mut self, region: Region, offset: usize, size: usize) {
+        for offset in [offset, offset + size - 1] {
+            if let Some(line) = Self::line(region, offset) {
+                if self.code_lines[line] {
+                    self.invalidate_line(line);
+                }
+            }
+        }
+    }
+
+    fn invalidate_line(&mut self, line: usize) {
+        self.blocks.retain(|_, block| match block.lines {
+            Some((first, last)) => !(first <= line && line <= last),
+            None => true,
+        });
+        self.code_lines[line] = false;
+        self.generation += 1;
+    }
+
+    fn insert(&mut self, key: (u32, bool), block: Block) -> Rc<Block> {
+        if let Some((first, last)) = block.lines {
+            self.code_lines[first..=last].fill(true);
+        }
+
+        let block = Rc::new(block);
+        self.blocks.insert(key, block.clone());
+        block
+    }
+}
+
+/// Instructions which may write the PC or leave the current state end a block
+fn arm_ends_block(opcode: u32) -> bool {
+    let rd = (opcode >> 12) & 0xF;
+    let load = (opcode & 0x100000) != 0;
+
+    match decode::decode_arm(opcode) {
+        ArmInstr::DataProcessing | ArmInstr::Mrs => rd == 15,
+        ArmInstr::SingleDataTransfer | ArmInstr::HalfwordRegister | ArmInstr::HalfwordImmediate => {
+            load && rd == 15
+        }
+        ArmInstr::Multiply | ArmInstr::MultiplyLong | ArmInstr::SingleDataSwap => false,
+        ArmInstr::BlockDataTransfer => load && (opcode & 0x8000) != 0,
+        ArmInstr::Msr | ArmInstr::MsrBits => false,
+        _ => true,
+    }
+}
+
+fn thumb_ends_block(opcode: u16) -> bool {
+    match decode::decode_thumb(opcode) {
+        ThumbInstr::HiRegisterOpBx => {
+            let rd = (opcode & 0x7) | ((opcode >> 4) & 0x8);
+            rd == 15 || (opcode & 0x300) == 0x300
+        }
+        ThumbInstr::PushPop => (opcode & 0x900) == 0x900,
+        ThumbInstr::ConditionalBranch
+        | ThumbInstr::Swi
+        | ThumbInstr::UnconditionalBranch
+        | ThumbInstr::LongBranchLink
+        | ThumbInstr::Unknown => true,
+        _ => false,
+    }
+}
+
+impl CPU {
+    /// Decodes the basic block at `pc`, None if it is not in ROM or work RAM
+    fn block_decode(&self, pc: u32, thumb: bool) -> Option<Block> {
+        let step = match thumb {
+            false => 4,
+            true => 2,
+        };
+
+        if !(pc as usize).is_multiple_of(step) {
+            return None;
+        }
+
+        let (region, start) = match self.pages.read(pc, step)? {
+            (Region::Video, _) => return None,
+            page => page,
+        };
+
+        let mut end = start;
+        let mut arm = Vec::new();
+        let mut thumb_instructions = Vec::new();
+
+        for i in 0..BLOCK_MAX_INSTRUCTIONS {
+            let offset = match self.pages.read(pc + (i * step) as u32, step) {
+                Some((r, offset)) if r == region => offset,
+                _ => break,
+            };
+            let bytes = &self.region(region)[offset..offset + step];
+            end = offset + step - 1;
+
+            let ends = match thumb {
+                false => {
+                    let opcode = u32::from_le_bytes(bytes.try_into().unwrap());
+                    arm.push((opcode, self.arm_table[decode::arm_index(opcode)]));
+                    arm_ends_block(opcode)
+                }
+                true => {
+                    let opcode = u16::from_le_bytes(bytes.try_into().unwrap());
+                    thumb_instructions
+                        .push((opcode, self.thumb_table[decode::thumb_index(opcode)]));
+                    thumb_ends_block(opcode)
+                }
+            };
+
+            if ends {
+                break;
+            }
+        }
+
+        Some(Block {
+            lines: BlockCache::line(region, start).zip(BlockCache::line(region, end)),
+            instructions: match thumb {
+                false => Instructions::Arm(arm),
+                true => Instructions::Thumb(thumb_instructions),
+            },
+        })
+    }
+
+    /// The next block instruction at `pc` runs only if no branch, state change, halt,
+    /// due event, enabled IRQ or code write happened since the previous one
+    /// Events scheduled by the block itself end it as well
+    fn block_continues(&self, pc: u32, thumb: bool, generation: usize) -> bool {
+        self.get_program_counter() == pc
+            && self.is_thumb() == thumb
+            && !(self.halt && self.get_mode() != MODE_IRQ)
+            && !self.panic
+            && !self.irq_pending()
+            && self.cycle_count < self.scheduler.next_timestamp()
+            && self.blocks.generation == generation
+    }
+
+    /// Executes the cached basic block at the PC until it leaves the block or an event is due
+    /// Returns the number of executed instructions and the PC of the last one,
+    /// None if the PC is not in cacheable memory
+    pub fn execute_block(&mut self) -> Option<(usize, u32)> {
+        let pc = self.get_program_counter();
+        let thumb = self.is_thumb();
+
+        let block = match self.blocks.blocks.get(&(pc, thumb)) {
+            Some(block) => block.clone(),
+            None => {
+                let block = self.block_decode(pc, thumb)?;
+                self.blocks.insert((pc, thumb), block)
+            }
+        };
+
+        let generation = self.blocks.generation;
+        let mut executed = 0;
+        let mut last_pc = pc;
+
+        match &block.instructions {
+            Instructions::Arm(instructions) => {
+                for (i, (opcode, handler)) in instructions.iter().enumerate() {
+                    let instr_pc = pc + (i as u32) * 4;
+                    if i > 0 && !self.block_continues(instr_pc, thumb, generation) {
+                        break;
+                    }
+
+                    self.execute_arm_handler(*opcode, *handler);
+                    executed += 1;
+                    last_pc = instr_pc;
+                }
+            }
+            Instructions::Thumb(instructions) => {
+                for (i, (opcode, handler)) in instructions.iter().enumerate() {
+                    let instr_pc = pc + (i as u32) * 2;
+                    if i > 0 && !self.block_continues(instr_pc, thumb, generation) {
+                        break;
+                    }
+
+                    handler(self, *opcode);
+                    executed += 1;
+                    last_pc = instr_pc;
+                }
+            }
+        }
+
+        Some((executed, last_pc))
+    }
+}
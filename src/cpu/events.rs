@@ -0,0 +1,196 @@
+use log::*;
+
+use super::{CPU, IRQ_DMA0, IRQ_HBLANK, IRQ_SERIAL, IRQ_TIM0, IRQ_VBLANK, IRQ_VCOUNT};
+use crate::scheduler::{Event, CYCLES_HDRAW, CYCLES_SAMPLE, CYCLES_SCANLINE};
+
+/// DMA start timing (DMAxCNT_H bits 12-13)
+pub(super) const DMA_TIMING_IMMEDIATE: u8 = 0;
+const DMA_TIMING_VBLANK: u8 = 1;
+const DMA_TIMING_HBLANK: u8 = 2;
+
+impl CPU {
+    /// Schedules the LCD events of the first scanline
+    pub(super) fn schedule_lcd(&mut self) {
+        self.scheduler
+            .reschedule(self.cycle_count + CYCLES_HDRAW, Event::HBlank);
+        self.scheduler
+            .reschedule(self.cycle_count + CYCLES_SCANLINE, Event::Scanline);
+    }
+
+    /// Schedules the first sample of the sound output
+    pub(super) fn schedule_audio(&mut self) {
+        self.scheduler
+            .reschedule(self.cycle_count + CYCLES_SAMPLE, Event::AudioSample);
+    }
+
+    /// Ends the current scanline now
+    pub fn lcd_next_scanline(&mut self) {
+        self.scheduler.reschedule(self.cycle_count, Event::Scanline);
+    }
+
+    /// Handles every event due at the current cycle
+    /// Returns true if a new frame started (VCount wrapped to 0)
+    pub fn run_events(&mut self) -> bool {
+        let mut frame = false;
+
+        while let Some((timestamp, event)) = self.scheduler.pop_due(self.cycle_count) {
+            match event {
+                Event::HBlank => self.lcd_hblank(),
+                Event::Scanline => frame |= self.lcd_scanline(timestamp),
+                Event::TimerOverflow(n) => self.timer_overflow(n as usize, timestamp),
+                Event::Dma(n) => {
+                    if self.dma_timing(n) == Some(DMA_TIMING_IMMEDIATE) {
+                        self.dma_run(n);
+                    }
+                }
+                Event::Serial => {
+                    if self.serial.transfer_done() && self.can_irq_trigger(IRQ_SERIAL) {
+                        warn!("Serial IRQ Triggered");
+                        self.trigger_irq(IRQ_SERIAL);
+                    }
+                }
+                Event::AudioSample => self.audio_sample(timestamp),
+            }
+        }
+
+        frame
+    }
+
+    fn lcd_hblank(&mut self) {
+        if self.lcd_paused {
+            return;
+        }
+
+        self.lcd.set_dispstat_hblank(true);
+
+        if self.can_irq_trigger(IRQ_HBLANK) {
+            warn!("HBlank IRQ Triggered");
+            self.trigger_irq(IRQ_HBLANK);
+        }
+
+        // HBlank DMAs only run during the visible scanlines
+        if self.lcd.get_vcount() < 160 {
+            self.dma_start(DMA_TIMING_HBLANK);
+        }
+    }
+
+    fn lcd_scanline(&mut self, timestamp: usize) -> bool {
+        self.scheduler
+            .reschedule(timestamp + CYCLES_HDRAW, Event::HBlank);
+        self.scheduler
+            .schedule(timestamp + CYCLES_SCANLINE, Event::Scanline);
+
+        if self.lcd_paused {
+            return false;
+        }
+
+        self.lcd.set_dispstat_hblank(false);
+        let vcount = self.lcd.increment_vcount();
+
+        if vcount == 160 {
+            if self.can_irq_trigger(IRQ_VBLANK) {
+                warn!("VBLANK IRQ Triggered");
+                self.trigger_irq(IRQ_VBLANK);
+            }
+
+            self.dma_start(DMA_TIMING_VBLANK);
+        }
+
+        if self.lcd.get_dispstat_vcount_flag() && self.can_irq_trigger(IRQ_VCOUNT) {
+            warn!("VCount IRQ Triggered");
+            self.trigger_irq(IRQ_VCOUNT);
+        }
+
+        vcount == 0
+    }
+
+    fn audio_sample(&mut self, timestamp: usize) {
+        self.scheduler
+            .schedule(timestamp + CYCLES_SAMPLE, Event::AudioSample);
+        self.sound.sample();
+    }
+
+    /// Timer `n` counts up on overflow of timer `n - 1` instead of using its prescaler
+    pub(super) fn timer_is_count_up(&self, n: usize) -> bool {
+        n != 0 && self.timers[n].is_count_up()
+    }
+
+    /// (Re)schedules the overflow of timer `n` from its latched counter
+    pub(super) fn timer_schedule(&mut self, n: usize) {
+        let event = Event::TimerOverflow(n as u8);
+        self.scheduler.cancel(event);
+
+        if self.timers[n].is_enabled() && !self.timer_is_count_up(n) {
+            self.scheduler.schedule(self.timers[n].overflow_at(), event);
+        }
+    }
+
+    fn timer_overflow(&mut self, n: usize, timestamp: usize) {
+        self.timers[n].counter = self.timers[n].reload;
+        self.timers[n].start = timestamp;
+        self.timer_schedule(n);
+
+        let irq = IRQ_TIM0 << n;
+        if self.can_irq_trigger(irq) {
+            warn!("Timer{n} IRQ Triggered");
+            self.trigger_irq(irq);
+        }
+
+        // Cascade into the next timer
+        if n < 3 && self.timers[n + 1].is_enabled() && self.timer_is_count_up(n + 1) {
+            match self.timers[n + 1].counter {
+                0xFFFF => self.timer_overflow(n + 1, timestamp),
+                _ => self.timers[n + 1].counter += 1,
+            }
+        }
+    }
+
+    /// Start timing of DMA `num`, None if the channel is disabled
+    fn dma_timing(&self, num: u8) -> Option<u8> {
+        let control = self.dma_register(num, 8) >> 16;
+
+        match (control & 0x8000) != 0 {
+            false => None,
+            true => Some(((control >> 12) & 0x3) as u8),
+        }
+    }
+
+    /// Runs the enabled DMAs with start `timing`, in priority order
+    fn dma_start(&mut self, timing: u8) {
+        for num in 0..4 {
+            if self.dma_timing(num) == Some(timing) {
+                self.dma_run(num);
+            }
+        }
+    }
+
+    /// Enabling a DMA latches its addresses, immediate transfers start 2 cycles later
+    pub(super) fn dma_enable(&mut self, num: u8) {
+        let n = num as usize;
+        self.dma_src[n] = self.dma_register(num, 0);
+        self.dma_dest[n] = self.dma_register(num, 4);
+
+        if self.dma_timing(num) == Some(DMA_TIMING_IMMEDIATE) {
+            self.scheduler
+                .reschedule(self.cycle_count + 2, Event::Dma(num));
+        }
+    }
+
+    pub(super) fn dma_irq(&mut self, num: u8) {
+        let irq = IRQ_DMA0 << num;
+        if self.can_irq_trigger(irq) {
+            warn!("DMA{num} IRQ Triggered");
+            self.trigger_irq(irq);
+        }
+    }
+
+    /// Schedules the end of a serial transfer started from SIOCNT
+    pub(super) fn serial_check(&mut self) {
+        if let Some(cycles) = self.serial.transfer_cycles() {
+            if !self.scheduler.is_scheduled(Event::Serial) {
+                self.scheduler
+                    .schedule(self.cycle_count + cycles, Event::Serial);
+            }
+        }
+    }
+}
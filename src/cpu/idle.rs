@@ -0,0 +1,283 @@
+use log::*;
+
+use super::{CPU, MMU};
+use crate::decode::{self, ArmInstr, ThumbInstr};
+
+/// Longest loop body (in instructions) considered for idle loop detection
+const IDLE_LOOP_MAX_INSTRUCTIONS: u32 = 8;
+
+/// Pseudo register bit for the condition flags
+const FLAGS: u32 = 1 << 16;
+
+/// Registers (bit 0-15) and flags read and written by a loop instruction
+struct Access {
+    reads: u32,
+    writes: u32,
+    /// Branch target and whether the branch is conditional
+    branch: Option<(u32, bool)>,
+}
+
+impl Access {
+    fn new(reads: u32, writes: u32) -> Self {
+        Self {
+            reads,
+            writes,
+            branch: None,
+        }
+    }
+}
+
+fn reg(r: u32) -> u32 {
+    1 << (r & 0xF)
+}
+
+/// Access of a Thumb instruction allowed in an idle loop, None if it may have side effects
+fn thumb_access(opcode: u16, pc: u32) -> Option<Access> {
+    let op = opcode as u32;
+    let rd = op & 0x7;
+    let rs = (op >> 3) & 0x7;
+    let rb = rs;
+    let load = (op & 0x800) != 0;
+
+    match decode::decode_thumb(opcode) {
+        ThumbInstr::MoveShiftedRegister => Some(Access::new(reg(rs), reg(rd) | FLAGS)),
+        ThumbInstr::AddSubtract => {
+            let rn = match (op & 0x400) != 0 {
+                false => reg((op >> 6) & 0x7),
+                true => 0,
+            };
+            Some(Access::new(reg(rs) | rn, reg(rd) | FLAGS))
+        }
+        ThumbInstr::MovCmpAddSubImm => {
+            let rd = (op >> 8) & 0x7;
+            match (op >> 11) & 0x3 {
+                0 => Some(Access::new(0, reg(rd) | FLAGS)),
+                1 => Some(Access::new(reg(rd), FLAGS)),
+                _ => Some(Access::new(reg(rd), reg(rd) | FLAGS)),
+            }
+        }
+        ThumbInstr::Alu => match (op >> 6) & 0xF {
+            0x5 | 0x6 => Some(Access::new(reg(rd) | reg(rs) | FLAGS, reg(rd) | FLAGS)), // ADC, SBC
+            0x8 | 0xA | 0xB => Some(Access::new(reg(rd) | reg(rs), FLAGS)), // TST, CMP, CMN
+            0x9 | 0xF => Some(Access::new(reg(rs), reg(rd) | FLAGS)),       // NEG, MVN
+            _ => Some(Access::new(reg(rd) | reg(rs), reg(rd) | FLAGS)),
+        },
+        ThumbInstr::HiRegisterOpBx => {
+            let rd = (op & 0x7) | ((op >> 4) & 0x8);
+            let rs = (op >> 3) & 0xF;
+            match ((op >> 8) & 0x3, rd) {
+                (_, 15) | (3, _) => None, // Writes PC, BX
+                (0, _) => Some(Access::new(reg(rd) | reg(rs), reg(rd))),
+                (1, _) => Some(Access::new(reg(rd) | reg(rs), FLAGS)),
+                _ => Some(Access::new(reg(rs), reg(rd))),
+            }
+        }
+        ThumbInstr::PcRelativeLoad => Some(Access::new(0, reg((op >> 8) & 0x7))),
+        ThumbInstr::LoadStoreRegisterOffset if load => {
+            Some(Access::new(reg(rb) | reg((op >> 6) & 0x7), reg(rd)))
+        }
+        // STRH is the only store of the sign-extended format
+        ThumbInstr::LoadStoreSignExtended if (op & 0xC00) != 0 => {
+            Some(Access::new(reg(rb) | reg((op >> 6) & 0x7), reg(rd)))
+        }
+        ThumbInstr::LoadStoreImmediate | ThumbInstr::LoadStoreHalfword if load => {
+            Some(Access::new(reg(rb), reg(rd)))
+        }
+        ThumbInstr::SpRelativeLoadStore if load => Some(Access::new(reg(13), reg((op >> 8) & 0x7))),
+        ThumbInstr::LoadAddress => {
+            let sp = match (op & 0x800) != 0 {
+                false => 0,
+                true => reg(13),
+            };
+            Some(Access::new(sp, reg((op >> 8) & 0x7)))
+        }
+        ThumbInstr::ConditionalBranch => {
+            let offset = ((op & 0xFF) as i8 as i32) << 1;
+            Some(Access {
+                reads: FLAGS,
+                writes: 0,
+                branch: Some(((pc + 4).wrapping_add(offset as u32), true)),
+            })
+        }
+        ThumbInstr::UnconditionalBranch => {
+            let offset = (((op & 0x7FF) << 21) as i32) >> 20;
+            Some(Access {
+                reads: 0,
+                writes: 0,
+                branch: Some(((pc + 4).wrapping_add(offset as u32), false)),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Access of an ARM instruction allowed in an idle loop, None if it may have side effects
+fn arm_access(opcode: u32, pc: u32) -> Option<Access> {
+    let conditional = (opcode >> 28) != 0xE;
+    let rn = (opcode >> 16) & 0xF;
+    let rd = (opcode >> 12) & 0xF;
+    let rm = opcode & 0xF;
+    let imm = (opcode & 0x2000000) != 0;
+    let load = (opcode & 0x100000) != 0;
+    let pre_no_writeback = (opcode & 0x1200000) == 0x1000000;
+
+    let mut access = match decode::decode_arm(opcode) {
+        ArmInstr::DataProcessing if rd != 15 => {
+            let op = (opcode >> 21) & 0xF;
+            let set_flags = (opcode & 0x100000) != 0;
+
+            let mut reads = match op {
+                0xD | 0xF => 0, // MOV, MVN
+                _ => reg(rn),
+            };
+            if !imm {
+                reads |= reg(rm);
+                if (opcode & 0x10) != 0 {
+                    reads |= reg((opcode >> 8) & 0xF);
+                }
+            }
+            if (0x5..=0x7).contains(&op) {
+                reads |= FLAGS; // ADC, SBC, RSC
+            }
+
+            let mut writes = match op {
+                0x8..=0xB => 0, // TST, TEQ, CMP, CMN
+                _ => reg(rd),
+            };
+            if set_flags {
+                writes |= FLAGS;
+            }
+
+            Access::new(reads, writes)
+        }
+        ArmInstr::SingleDataTransfer if load && pre_no_writeback && rd != 15 => {
+            let offset = match imm {
+                false => 0,
+                true => reg(rm),
+            };
+            Access::new(reg(rn) | offset, reg(rd))
+        }
+        ArmInstr::HalfwordImmediate if load && pre_no_writeback && rd != 15 => {
+            Access::new(reg(rn), reg(rd))
+        }
+        ArmInstr::Branch if (opcode & 0x1000000) == 0 => {
+            let offset = (((opcode & 0xFFFFFF) << 8) as i32) >> 6;
+            Access {
+                reads: 0,
+                writes: 0,
+                branch: Some(((pc + 8).wrapping_add(offset as u32), conditional)),
+            }
+        }
+        _ => return None,
+    };
+
+    // A conditional write may keep the previous value
+    if conditional {
+        access.reads |= FLAGS | access.writes;
+    }
+
+    Some(access)
+}
+
+impl CPU {
+    /// Called after executing the instruction at `branch_pc`
+    /// If it branched back into an idle loop, skips the cycles until the next event
+    pub fn skip_idle_loop(&mut self, branch_pc: u32) -> bool {
+        let next_event = self.scheduler.next_timestamp();
+
+        let start = self.get_program_counter();
+        let step = match self.is_thumb() {
+            false => 4,
+            true => 2,
+        };
+
+        // Not the closing branch of a loop, the loop body is still running
+        if start > branch_pc || (branch_pc - start) / step >= IDLE_LOOP_MAX_INSTRUCTIONS {
+            return false;
+        }
+
+        // A timer read anywhere in the last iteration keeps the loop running
+        let timer_read = std::mem::take(&mut self.idle_timer_read);
+
+        if !self.idle_loop_skip || next_event == usize::MAX || next_event <= self.cycle_count {
+            return false;
+        }
+
+        let key = (start, branch_pc, self.is_thumb());
+        let idle = match self.idle_loops.get(&key) {
+            Some(idle) => *idle,
+            None => {
+                let idle = self.is_idle_loop(start, branch_pc);
+
+                // Code in RAM may be overwritten, only remember loops in ROM
+                if start >= 0x08000000 {
+                    self.idle_loops.insert(key, idle);
+                }
+                idle
+            }
+        };
+
+        // Timer counters change without an event
+        if !idle || timer_read {
+            return false;
+        }
+
+        info!(
+            "Idle loop `{:08X}`..`{:08X}`, skipping {} cycles",
+            start,
+            branch_pc,
+            next_event - self.cycle_count
+        );
+
+        self.idle_skipped_cycles += next_event - self.cycle_count;
+        self.cycle_count = next_event;
+
+        true
+    }
+
+    /// A loop is idle if it has no side effects and no register or flag carries a value
+    /// from one iteration into the next, so every iteration is the same until an event
+    fn is_idle_loop(&mut self, start: u32, end: u32) -> bool {
+        let thumb = self.is_thumb();
+        let step = match thumb {
+            false => 4,
+            true => 2,
+        };
+
+        let mut accesses = Vec::new();
+        for pc in (start..=end).step_by(step) {
+            let access = match thumb {
+                false => arm_access(self.read_u32(false, pc), pc),
+                true => thumb_access(self.read_u16(false, pc), pc),
+            };
+
+            let access = match access {
+                Some(access) => access,
+                None => return false,
+            };
+
+            // Only the closing branch may stay in the loop
+            match access.branch {
+                Some((target, _)) if pc == end && target != start => return false,
+                Some(_) if pc == end => {}
+                Some((target, true)) if target < start || target > end => {}
+                Some(_) => return false,
+                None => {}
+            }
+
+            accesses.push(access);
+        }
+
+        let loop_writes = accesses.iter().fold(0, |writes, a| writes | a.writes);
+        let mut written = 0;
+
+        for access in accesses {
+            if (access.reads & loop_writes & !written) != 0 {
+                return false;
+            }
+            written |= access.writes;
+        }
+
+        true
+    }
+}
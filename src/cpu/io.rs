@@ -0,0 +1,217 @@
+use log::*;
+
+use super::CPU;
+use crate::scheduler::Event;
+
+// Interrupt, Waitstate and Power-Down Control
+pub const REG_IE: u32 = 0x200;
+pub const REG_IF: u32 = 0x202;
+pub const REG_WAITCNT: u32 = 0x204;
+pub const REG_IME: u32 = 0x208;
+pub const REG_POSTFLG_HALTCNT: u32 = 0x300;
+
+/// Writes the bits of `val` selected by `mask` into `old`
+fn masked(old: u16, val: u16, mask: u16) -> u16 {
+    (old & !mask) | (val & mask)
+}
+
+impl CPU {
+    /// Reads the halfword IO register at `io_addr` (offset from 0x04000000)
+    /// Every IO read, regardless of width, is routed through here
+    pub(super) fn io_read_u16(&mut self, intern: bool, io_addr: u32) -> u16 {
+        let io_addr = io_addr & 0x3FE;
+
+        match io_addr {
+            0x000..=0x056 => self.lcd.read_u16(io_addr),
+            0x060..=0x0A6 => self.sound.read_u16(io_addr),
+            0x0B0..=0x0DE => self.dma_read_u16(io_addr - 0xB0),
+            0x100..=0x10E => self.timer_read_u16(io_addr - 0x100),
+            0x120..=0x12A | 0x134 | 0x140 | 0x150..=0x158 => self.serial.read_u16(io_addr),
+            0x130 | 0x132 => self.keypad.read_u16(io_addr),
+            REG_IE => self.io_ie,
+            REG_IF => self.io_if,
+            REG_WAITCNT => self.io_waitcnt,
+            REG_IME => self.io_ime as u16,
+            // HALTCNT (upper byte) is write-only
+            REG_POSTFLG_HALTCNT => self.io_postflg as u16,
+            _ => {
+                if intern {
+                    warn!(
+                        "Read from unused IO register `{:08X}`",
+                        0x04000000 | io_addr
+                    );
+                }
+                0
+            }
+        }
+    }
+
+    /// Writes the bits of `val` selected by `mask` to the halfword IO register at `io_addr`
+    /// Byte writes only set the mask of the addressed byte
+    /// Every IO write, regardless of width, is routed through here
+    pub(super) fn io_write_u16(&mut self, intern: bool, io_addr: u32, val: u16, mask: u16) {
+        let io_addr = io_addr & 0x3FE;
+
+        match io_addr {
+            0x000..=0x056 => self.lcd.write_u16(io_addr, val, mask),
+            0x060..=0x0A6 => self.sound.write_u16(io_addr, val, mask),
+            0x0B0..=0x0DE => self.dma_write_u16(io_addr - 0xB0, val, mask),
+            0x100..=0x10E => self.timer_write_u16(io_addr - 0x100, val, mask),
+            0x120..=0x12A | 0x134 | 0x140 | 0x150..=0x158 => {
+                self.serial.write_u16(io_addr, val, mask);
+                self.serial_check();
+            }
+            0x130 | 0x132 => {
+                self.keypad.write_u16(io_addr, val, mask);
+                self.keypad_irq_check();
+            }
+            REG_IE => {
+                self.io_ie = masked(self.io_ie, val, mask & 0x3FFF);
+                warn!("Write to Interrupt Enable Register => {:04X}", self.io_ie);
+            }
+            REG_IF => {
+                // Writing 1 acknowledges (clears) the interrupt
+                self.io_if &= !(val & mask);
+                warn!(
+                    "Acknowledge Interrupt Request Flags {:04X} => {:04X}",
+                    val & mask,
+                    self.io_if
+                );
+            }
+            REG_WAITCNT => {
+                // Bit 15 (Game Pak type) is read-only
+                self.io_waitcnt = masked(self.io_waitcnt, val, mask & 0x5FFF);
+            }
+            REG_IME => {
+                self.io_ime = masked(self.io_ime as u16, val, mask & 0x0001) as u8;
+                warn!(
+                    "Write to Interrupt Master Enable Register => {:X}",
+                    self.io_ime
+                );
+            }
+            REG_POSTFLG_HALTCNT => {
+                if (mask & 0x00FF) != 0 {
+                    self.io_postflg = (val & 0x1) as u8;
+                }
+                if (mask & 0xFF00) != 0 {
+                    self.write_haltcnt((val >> 8) as u8);
+                }
+            }
+            _ => {
+                if intern {
+                    warn!(
+                        "Write to unused IO register `{:08X}` => {:04X}",
+                        0x04000000 | io_addr,
+                        val & mask
+                    );
+                }
+            }
+        }
+    }
+
+    /// Raw value of the DMA register at `reg` (0=SAD, 4=DAD, 8=CNT_L|CNT_H) of channel `num`,
+    /// bypassing the read masks the CPU sees
+    pub fn dma_register(&self, num: u8, reg: usize) -> u32 {
+        let offset = (num as usize) * 12 + reg;
+
+        ((self.dma[offset + 3] as u32) << 24)
+            | ((self.dma[offset + 2] as u32) << 16)
+            | ((self.dma[offset + 1] as u32) << 8)
+            | (self.dma[offset] as u32)
+    }
+
+    /// Only DMAxCNT_H is readable, addresses and word count are write-only
+    fn dma_read_u16(&self, offset: u32) -> u16 {
+        let offset = offset as usize;
+
+        match offset % 12 {
+            10 => ((self.dma[offset + 1] as u16) << 8) | (self.dma[offset] as u16),
+            _ => 0,
+        }
+    }
+
+    fn dma_write_u16(&mut self, offset: u32, val: u16, mask: u16) {
+        let num = offset / 12;
+        let offset = offset as usize;
+
+        let write_mask = match (offset % 12, num) {
+            (0, _) | (4, _) => 0xFFFF,
+            (2, 0) => 0x07FF,  // DMA0 source is internal memory only
+            (2, _) => 0x0FFF,  // SAD bit 27
+            (6, 3) => 0x0FFF,  // DMA3 can write to Game Pak
+            (6, _) => 0x07FF,  // DAD bit 26
+            (8, 3) => 0xFFFF,  // DMA3 word count is 16 bit
+            (8, _) => 0x3FFF,  // DMA0-2 word count is 14 bit
+            (10, 3) => 0xFFE0, // Only DMA3 has Game Pak DRQ
+            (10, _) => 0xF7E0,
+            _ => unreachable!(),
+        };
+
+        let old = ((self.dma[offset + 1] as u16) << 8) | (self.dma[offset] as u16);
+        let new = masked(old, val, mask & write_mask);
+
+        self.dma[offset + 1] = (new >> 8) as u8;
+        self.dma[offset] = (new & 0xFF) as u8;
+
+        if offset % 12 == 10 {
+            match ((old & 0x8000) != 0, (new & 0x8000) != 0) {
+                (false, true) => self.dma_enable(num as u8),
+                (true, false) => self.scheduler.cancel(Event::Dma(num as u8)),
+                _ => {}
+            }
+        }
+    }
+
+    /// TMxCNT_L reads the current counter, writes go to the reload value
+    fn timer_read_u16(&mut self, offset: u32) -> u16 {
+        let n = (offset / 4) as usize;
+
+        match offset % 4 {
+            0 => {
+                // A running counter changes without an event, loops reading it are not idle
+                if self.timers[n].is_enabled() {
+                    self.idle_timer_read = true;
+                }
+                self.timers[n].counter(self.cycle_count, self.timer_is_count_up(n))
+            }
+            _ => self.timers[n].control,
+        }
+    }
+
+    fn timer_write_u16(&mut self, offset: u32, val: u16, mask: u16) {
+        let n = (offset / 4) as usize;
+
+        if offset.is_multiple_of(4) {
+            self.timers[n].reload = masked(self.timers[n].reload, val, mask);
+            return;
+        }
+
+        // Latch the counter before prescaler or timing change
+        let counter = self.timers[n].counter(self.cycle_count, self.timer_is_count_up(n));
+        let was_enabled = self.timers[n].is_enabled();
+
+        let timer = &mut self.timers[n];
+        timer.counter = counter;
+        timer.start = self.cycle_count;
+        timer.control = masked(timer.control, val, mask & 0x00C7);
+
+        // Reload value is copied into the counter when the timer starts
+        if !was_enabled && timer.is_enabled() {
+            timer.counter = timer.reload;
+        }
+
+        self.timer_schedule(n);
+    }
+
+    /// HALTCNT: bit 7 selects Stop (1) or Halt (0)
+    /// Halt is left on any enabled interrupt (IE & IF), Stop only on Keypad, Serial or Game Pak
+    pub(super) fn write_haltcnt(&mut self, val: u8) {
+        self.halt = true;
+        self.stop = (val & 0x80) != 0;
+
+        match self.stop {
+            false => warn!("HALTCNT: CPU Halted"),
+            true => warn!("HALTCNT: CPU Stopped"),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use super::CPU;
+
+/// 32 KB pages over the 28 bit address space
+const PAGE_SHIFT: u32 = 15;
+const PAGE_SIZE: usize = 1 << PAGE_SHIFT;
+const PAGE_COUNT: usize = 1 << (28 - PAGE_SHIFT);
+
+/// Backing memory of a page
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum Region {
+    WorkRam1,
+    WorkRam2,
+    Video,
+    Rom,
+}
+
+/// Maps pages fully backed by RAM or ROM to (region, offset of the page in region)
+/// Everything else (BIOS, IO, palette, OAM, SRAM, mirrors) takes the slow path
+pub(super) struct PageTable {
+    read: Vec<Option<(Region, usize)>>,
+    write: Vec<Option<(Region, usize)>>,
+}
+
+impl PageTable {
+    pub fn new(rom_len: usize) -> Self {
+        let mut pages = Self {
+            read: vec![None; PAGE_COUNT],
+            write: vec![None; PAGE_COUNT],
+        };
+
+        pages.map(0x02000000, 256 * 1024, Region::WorkRam1, true);
+        pages.map(0x03000000, 32 * 1024, Region::WorkRam2, true);
+        pages.map(0x06000000, 96 * 1024, Region::Video, true);
+
+        // ROM is mirrored in the three wait state regions, only whole pages are mapped
+        let rom_len = (rom_len & !(PAGE_SIZE - 1)).min(16 * 1024 * 1024);
+        for base in [
+            0x08000000, 0x09000000, 0x0A000000, 0x0B000000, 0x0C000000, 0x0D000000,
+        ] {
+            pages.map(base, rom_len, Region::Rom, false);
+        }
+
+        pages
+    }
+
+    fn map(&mut self, addr: u32, len: usize, region: Region, writable: bool) {
+        for offset in (0..len).step_by(PAGE_SIZE) {
+            let page = ((addr as usize) + offset) >> PAGE_SHIFT;
+            self.read[page] = Some((region, offset));
+            if writable {
+                self.write[page] = Some((region, offset));
+            }
+        }
+    }
+
+    /// Sends the pages overlapping `len` bytes at `addr` to the slow path
+    pub fn unmap(&mut self, addr: u32, len: usize) {
+        let first = (addr as usize) >> PAGE_SHIFT;
+        let last = ((addr as usize) + len - 1) >> PAGE_SHIFT;
+
+        self.read[first..=last].fill(None);
+        self.write[first..=last].fill(None);
+    }
+
+    /// Returns (region, offset) if `size` bytes at `addr` lie within one mapped page
+    fn lookup(
+        table: &[Option<(Region, usize)>],
+        addr: u32,
+        size: usize,
+    ) -> Option<(Region, usize)> {
+        let addr = (addr & 0x0FFFFFFF) as usize;
+        let page_offset = addr & (PAGE_SIZE - 1);
+
+        if page_offset + size > PAGE_SIZE {
+            return None;
+        }
+
+        table[addr >> PAGE_SHIFT].map(|(region, base)| (region, base + page_offset))
+    }
+
+    pub fn read(&self, addr: u32, size: usize) -> Option<(Region, usize)> {
+        Self::lookup(&self.read, addr, size)
+    }
+
+    pub fn write(&self, addr: u32, size: usize) -> Option<(Region, usize)> {
+        Self::lookup(&self.write, addr, size)
+    }
+}
+
+impl CPU {
+    pub(super) fn region(&self, region: Region) -> &[u8] {
+        match region {
+            Region::WorkRam1 => &self.ram_work1,
+            Region::WorkRam2 => &self.ram_work2,
+            Region::Video => &self.ram_video,
+            Region::Rom => &self.rom,
+        }
+    }
+
+    pub(super) fn region_mut(&mut self, region: Region) -> &mut [u8] {
+        match region {
+            Region::WorkRam1 => &mut self.ram_work1,
+            Region::WorkRam2 => &mut self.ram_work2,
+            Region::Video => &mut self.ram_video,
+            Region::Rom => &mut self.rom,
+        }
+    }
+}
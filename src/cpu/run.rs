@@ -0,0 +1,93 @@
+use log::*;
+
+use super::{CPU, MMU, MODE_IRQ};
+
+impl CPU {
+    /// Fetches the opcode at PC, flags a CPU panic if PC is at an invalid address
+    pub fn fetch_opcode(&mut self) -> Option<u32> {
+        let program_counter = self.get_program_counter();
+
+        if !self.addr_valid(program_counter) {
+            if !self.panic {
+                warn!("Panicked! PC at invalid address `{:08X}`", program_counter);
+                self.panic = true;
+            }
+            return None;
+        }
+
+        Some(match self.is_thumb() {
+            true => {
+                let word = self.read_u32(false, program_counter & 0xFFFFFFFE);
+                let upper = (program_counter & 0x1) == 0x1;
+                match upper {
+                    false => word & 0xFFFF,
+                    true => (word >> 16) & 0xFFFF,
+                }
+            }
+            false => self.read_u32(false, program_counter),
+        })
+    }
+
+    /// Runs the CPU until the next scheduled event is due, the caller then runs the events
+    /// The next event is looked up again before every instruction, so events scheduled while
+    /// running (e.g. an immediate DMA 2 cycles after enable) are not delayed
+    /// `before` is called with the PC and opcode of every interpreted instruction and stops
+    /// the run if it returns false, `single_step` stops after one instruction
+    /// Blocks run several instructions without `before`, they are not used when stepping
+    /// Returns the number of executed instructions
+    pub fn run_until_event<F>(
+        &mut self,
+        use_blocks: bool,
+        single_step: bool,
+        mut before: F,
+    ) -> usize
+    where
+        F: FnMut(&CPU, u32, u32) -> bool,
+    {
+        let mut executed = 0;
+
+        while self.cycle_count < self.scheduler.next_timestamp() {
+            if self.halt && self.get_mode() != MODE_IRQ {
+                // Nothing happens until the next event, fast-forward to it
+                // The LCD is off in Stop mode, only a Keypad/Serial/Game Pak IRQ wakes the CPU
+                if !self.stop {
+                    self.cycle_count = self.scheduler.next_timestamp();
+                }
+                break;
+            }
+
+            // IRQs raised while masked are taken once IME, IE or CPSR.I enable them
+            self.irq_check();
+
+            if use_blocks && !single_step {
+                if let Some((block_executed, last_pc)) = self.execute_block() {
+                    executed += block_executed;
+
+                    if self.skip_idle_loop(last_pc) {
+                        break;
+                    }
+                    continue;
+                }
+            }
+
+            let program_counter = self.get_program_counter();
+            let opcode = match self.fetch_opcode() {
+                Some(opcode) => opcode,
+                None => break,
+            };
+
+            if !before(self, program_counter, opcode) {
+                break;
+            }
+
+            self.execute(opcode);
+            executed += 1;
+
+            if single_step || self.skip_idle_loop(program_counter) {
+                break;
+            }
+        }
+
+        executed
+    }
+}
@@ -25,7 +25,7 @@ use ratatui::{
 use tui_logger::{init_logger, TuiLoggerSmartWidget, TuiWidgetState};
 
 use crate::{
-    cpu::{CPU, MMU},
+    cpu::{self, CPU, MMU},
     disassembler, print_cpu_backtrace,
 };
 
@@ -231,9 +231,11 @@ impl Debugger {
                 let text = Paragraph::new(opcode_text).block(block);
                 f.render_widget(text, hors1[0]);
 
-                let block = Block::default()
-                    .title("Debugger State")
-                    .borders(Borders::ALL);
+                let title = match &cpu.header {
+                    Some(header) => format!("Debugger State - {header}"),
+                    None => String::from("Debugger State"),
+                };
+                let block = Block::default().title(title).borders(Borders::ALL);
                 let text = Paragraph::new(format_debugger_state(
                     self.instruction_counter,
                     self.free_run,
@@ -565,6 +567,17 @@ fn format_debugger_state(
         )
         .as_str(),
     );
+    fmt.push_str(
+        format!(
+            " Idle Skipped: {:8} ({})\n",
+            cpu.idle_skipped_cycles,
+            match cpu.idle_loop_skip {
+                false => "off",
+                true => "on",
+            }
+        )
+        .as_str(),
+    );
     fmt.push_str(format!("     Free run: {}\t\t\n", free_run).as_str());
     fmt.push_str(format!("    Lock Step: {}\t\t\n", lockstep).as_str());
     match input_mode {
@@ -579,10 +592,9 @@ fn format_dma(cpu: &mut CPU) -> String {
     let mut fmt = String::new();
 
     for i in 0..4 {
-        let addr = (0x040000B0 + (i * 12)) as u32;
-        let cnt_ctrl = cpu.read_u32(false, addr + 8);
-        fmt.push_str(format!("DMA{} SRC│ {:08X}h\n", i, cpu.read_u32(false, addr)).as_str());
-        fmt.push_str(format!("DMA{} DST│ {:08X}h\n", i, cpu.read_u32(false, addr + 4)).as_str());
+        let cnt_ctrl = cpu.dma_register(i, 8);
+        fmt.push_str(format!("DMA{} SRC│ {:08X}h\n", i, cpu.dma_register(i, 0)).as_str());
+        fmt.push_str(format!("DMA{} DST│ {:08X}h\n", i, cpu.dma_register(i, 4)).as_str());
         fmt.push_str(format!("DMA{} CNT│     {:04X}h\n", i, cnt_ctrl & 0xFFFF).as_str());
         fmt.push_str(format!("DMA{} CTR│     {:04X}h\n", i, (cnt_ctrl >> 16) & 0xFFFF).as_str());
         fmt.push_str("        │\n");
@@ -602,21 +614,19 @@ fn format_interrupt(cpu: &mut CPU) -> String {
     fmt.push_str(format!("        │\n").as_str());
     fmt.push_str(format!(" WAITCNT│ {:04X}h\n", cpu.io_waitcnt).as_str());
     fmt.push_str(format!("        │\n").as_str());
-    fmt.push_str(format!("TM0CNT_L│ ????h\n").as_str());
-    fmt.push_str(format!("TM0CNT_H│ ????h\n").as_str());
-    fmt.push_str(format!("TM1CNT_L│ ????h\n").as_str());
-    fmt.push_str(format!("TM1CNT_H│ ????h\n").as_str());
-    fmt.push_str(format!("TM2CNT_L│ ????h\n").as_str());
-    fmt.push_str(format!("TM2CNT_H│ ????h\n").as_str());
-    fmt.push_str(format!("TM3CNT_L│ ????h\n").as_str());
-    fmt.push_str(format!("TM3CNT_H│ ????h\n").as_str());
+    for i in 0..4 {
+        let timer = cpu.timers[i];
+        let counter = timer.counter(cpu.cycle_count, i != 0 && timer.is_count_up());
+        fmt.push_str(format!("TM{}CNT_L│ {:04X}h ({:04X}h)\n", i, counter, timer.reload).as_str());
+        fmt.push_str(format!("TM{}CNT_H│ {:04X}h\n", i, timer.control).as_str());
+    }
     fmt.push_str(format!("        │\n").as_str());
     fmt.push_str(format!("KEYINPUT│ {:04X}h\n", cpu.keypad.keyinput).as_str());
     fmt.push_str(format!("  KEYCNT│ {:04X}h\n", cpu.keypad.keycnt).as_str());
     fmt.push_str(format!("        │\n").as_str());
     fmt.push_str(format!("    HALT│ {}\n", cpu.halt).as_str());
-    fmt.push_str(format!(" BIOS_IF│ {:04X}h\n", cpu.io_bios_if).as_str());
-    fmt.push_str(format!("        │\n").as_str());
+    fmt.push_str(format!("    STOP│ {}\n", cpu.stop).as_str());
+    fmt.push_str(format!(" BIOS_IF│ {:04X}h\n", cpu.read_u16(false, cpu::BIOS_IF)).as_str());
     fmt.push_str(format!("        │\n").as_str());
 
     fmt
@@ -699,7 +709,9 @@ fn format_lcd(cpu: &mut CPU) -> String {
 fn format_serial(cpu: &mut CPU) -> String {
     let mut fmt = String::new();
 
-    fmt.push_str(format!("  SIODATA32│ {:08X}h\n", cpu.serial.read_u32(0x120)).as_str());
+    let siodata32 =
+        ((cpu.serial.read_u16(0x122) as u32) << 16) | (cpu.serial.read_u16(0x120) as u32);
+    fmt.push_str(format!("  SIODATA32│ {:08X}h\n", siodata32).as_str());
     fmt.push_str(format!("  SIOMULTI0│     {:04X}h\n", cpu.serial.read_u16(0x120)).as_str());
     fmt.push_str(format!("  SIOMULTI1│     {:04X}h\n", cpu.serial.read_u16(0x122)).as_str());
     fmt.push_str(format!("  SIOMULTI2│     {:04X}h\n", cpu.serial.read_u16(0x124)).as_str());
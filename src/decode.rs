@@ -0,0 +1,198 @@
+use crate::cpu::*;
+
+/// ARM decode only depends on bits 27-20 and 7-4 of the opcode
+pub const ARM_DECODE_MASK: u32 = 0x0FF000F0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArmInstr {
+    Multiply,
+    MultiplyLong,
+    SingleDataSwap,
+    BranchExchange,
+    HalfwordRegister,
+    HalfwordImmediate,
+    Mrs,
+    Msr,
+    MsrBits,
+    DataProcessing,
+    Undefined,
+    SingleDataTransfer,
+    BlockDataTransfer,
+    Branch,
+    CoprocessorDataTransfer,
+    CoprocessorDataOperation,
+    CoprocessorRegisterTransfer,
+    SoftwareInterrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThumbInstr {
+    MoveShiftedRegister,
+    AddSubtract,
+    MovCmpAddSubImm,
+    Alu,
+    HiRegisterOpBx,
+    PcRelativeLoad,
+    LoadStoreRegisterOffset,
+    LoadStoreSignExtended,
+    LoadStoreImmediate,
+    LoadStoreHalfword,
+    SpRelativeLoadStore,
+    LoadAddress,
+    OffsetToSp,
+    PushPop,
+    MultipleLoadStore,
+    ConditionalBranch,
+    Swi,
+    UnconditionalBranch,
+    LongBranchLink,
+    Unknown,
+}
+
+/// Index into the 4096-entry ARM decode table (bits 27-20 and 7-4)
+pub fn arm_index(opcode: u32) -> usize {
+    (((opcode >> 16) & 0xFF0) | ((opcode >> 4) & 0xF)) as usize
+}
+
+/// Index into the 1024-entry Thumb decode table (bits 15-6)
+pub fn thumb_index(opcode: u16) -> usize {
+    (opcode >> 6) as usize
+}
+
+fn arm_match(opcode: u32, mask_clr: u32, mask_set: u32) -> bool {
+    CPU::opcode_match(
+        opcode,
+        mask_clr & ARM_DECODE_MASK,
+        mask_set & ARM_DECODE_MASK,
+    )
+}
+
+/// Decodes the ARM instruction class, only looking at the decode table bits
+pub fn decode_arm(opcode: u32) -> ArmInstr {
+    let instr = ((opcode >> 20) & 0xFF) as u8;
+
+    match instr {
+        0x00..=0x3F => {
+            if arm_match(opcode, ARM_MASK_MUL_CLR, ARM_MASK_MUL_SET) {
+                ArmInstr::Multiply
+            } else if arm_match(opcode, ARM_MASK_MUL_LONG_CLR, ARM_MASK_MUL_LONG_SET) {
+                ArmInstr::MultiplyLong
+            } else if arm_match(opcode, ARM_MASK_SNGL_SWP_CLR, ARM_MASK_SNGL_SWP_SET) {
+                ArmInstr::SingleDataSwap
+            } else if arm_match(opcode, ARM_MASK_BX_CLR, ARM_MASK_BX_SET) {
+                ArmInstr::BranchExchange
+            } else if arm_match(opcode, ARM_MASK_HW_REG_CLR, ARM_MASK_HW_REG_SET) {
+                ArmInstr::HalfwordRegister
+            } else if arm_match(opcode, ARM_MASK_HW_IMM_CLR, ARM_MASK_HW_IMM_SET) {
+                ArmInstr::HalfwordImmediate
+            } else if arm_match(opcode, ARM_MASK_MRS_CLR, ARM_MASK_MRS_SET) {
+                ArmInstr::Mrs
+            } else if arm_match(opcode, ARM_MASK_MSR_CLR, ARM_MASK_MSR_SET) {
+                ArmInstr::Msr
+            } else if arm_match(opcode, ARM_MASK_MSR_BITS_CLR, ARM_MASK_MSR_BITS_SET) {
+                ArmInstr::MsrBits
+            } else {
+                ArmInstr::DataProcessing
+            }
+        }
+        0x40..=0x7F => {
+            if arm_match(opcode, ARM_MASK_UNDEF_CLR, ARM_MASK_UNDEF_SET) {
+                ArmInstr::Undefined
+            } else {
+                ArmInstr::SingleDataTransfer
+            }
+        }
+        0x80..=0x9F => ArmInstr::BlockDataTransfer,
+        0xA0..=0xBF => ArmInstr::Branch,
+        0xC0..=0xDF => ArmInstr::CoprocessorDataTransfer,
+        0xE0..=0xEF => match (opcode & 0x10) == 0 {
+            true => ArmInstr::CoprocessorDataOperation,
+            false => ArmInstr::CoprocessorRegisterTransfer,
+        },
+        0xF0..=0xFF => ArmInstr::SoftwareInterrupt,
+    }
+}
+
+/// Decodes the Thumb instruction format, only looking at the decode table bits
+pub fn decode_thumb(opcode: u16) -> ThumbInstr {
+    let high = (opcode >> 8) as u8;
+
+    match high {
+        0x00..=0x17 => ThumbInstr::MoveShiftedRegister,
+        0x18..=0x1F => ThumbInstr::AddSubtract,
+        0x20..=0x3F => ThumbInstr::MovCmpAddSubImm,
+        0x40..=0x43 => ThumbInstr::Alu,
+        0x44..=0x47 => ThumbInstr::HiRegisterOpBx,
+        0x48..=0x4F => ThumbInstr::PcRelativeLoad,
+        0x50 | 0x51 | 0x54 | 0x55 | 0x58 | 0x59 | 0x5C | 0x5D => {
+            ThumbInstr::LoadStoreRegisterOffset
+        }
+        0x52 | 0x53 | 0x56 | 0x57 | 0x5A | 0x5B | 0x5E | 0x5F => ThumbInstr::LoadStoreSignExtended,
+        0x60..=0x7F => ThumbInstr::LoadStoreImmediate,
+        0x80..=0x8F => ThumbInstr::LoadStoreHalfword,
+        0x90..=0x9F => ThumbInstr::SpRelativeLoadStore,
+        0xA0..=0xAF => ThumbInstr::LoadAddress,
+        0xB0 => ThumbInstr::OffsetToSp,
+        0xB4 | 0xB5 | 0xBC | 0xBD => ThumbInstr::PushPop,
+        0xC0..=0xCF => ThumbInstr::MultipleLoadStore,
+        0xD0..=0xDE => ThumbInstr::ConditionalBranch,
+        0xDF => ThumbInstr::Swi,
+        0xE0..=0xE7 => ThumbInstr::UnconditionalBranch,
+        0xF0..=0xFF => ThumbInstr::LongBranchLink,
+        _ => ThumbInstr::Unknown,
+    }
+}
+
+/// Builds a decode table of `T` for every ARM decode index
+pub fn arm_table<T>(f: impl Fn(ArmInstr) -> T) -> Vec<T> {
+    (0..4096u32)
+        .map(|i| decode_arm(((i & 0xFF0) << 16) | ((i & 0xF) << 4)))
+        .map(f)
+        .collect()
+}
+
+/// Builds a decode table of `T` for every Thumb decode index
+pub fn thumb_table<T>(f: impl Fn(ThumbInstr) -> T) -> Vec<T> {
+    (0..1024u16).map(|i| decode_thumb(i << 6)).map(f).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_tables() {
+        // MUL, UMULL, SWP, BX, LDRH register, STRH immediate
+        assert_eq!(decode_arm(0xE0000291), ArmInstr::Multiply);
+        assert_eq!(decode_arm(0xE0810392), ArmInstr::MultiplyLong);
+        assert_eq!(decode_arm(0xE1001092), ArmInstr::SingleDataSwap);
+        assert_eq!(decode_arm(0xE12FFF10), ArmInstr::BranchExchange);
+        assert_eq!(decode_arm(0xE19100B2), ArmInstr::HalfwordRegister);
+        assert_eq!(decode_arm(0xE1C100B2), ArmInstr::HalfwordImmediate);
+
+        // MRS, MSR CPSR_fc, MSR CPSR_c, MSR CPSR_f immediate, TEQ
+        assert_eq!(decode_arm(0xE10F0000), ArmInstr::Mrs);
+        assert_eq!(decode_arm(0xE129F000), ArmInstr::Msr);
+        assert_eq!(decode_arm(0xE121F000), ArmInstr::Msr);
+        assert_eq!(decode_arm(0xE328F20F), ArmInstr::MsrBits);
+        assert_eq!(decode_arm(0xE1300001), ArmInstr::DataProcessing);
+
+        assert_eq!(decode_arm(0xE5901000), ArmInstr::SingleDataTransfer);
+        assert_eq!(decode_arm(0xE7F000F0), ArmInstr::Undefined);
+        assert_eq!(decode_arm(0xE8BD0003), ArmInstr::BlockDataTransfer);
+        assert_eq!(decode_arm(0xEAFFFFFE), ArmInstr::Branch);
+        assert_eq!(decode_arm(0xEF000005), ArmInstr::SoftwareInterrupt);
+
+        assert_eq!(decode_thumb(0xB500), ThumbInstr::PushPop);
+        assert_eq!(decode_thumb(0xBD00), ThumbInstr::PushPop);
+        assert_eq!(decode_thumb(0xB600), ThumbInstr::Unknown);
+        assert_eq!(decode_thumb(0xDF05), ThumbInstr::Swi);
+        assert_eq!(decode_thumb(0xE800), ThumbInstr::Unknown);
+
+        // Every opcode of a table entry decodes the same
+        let table = arm_table(|instr| instr);
+        for opcode in [0xE0000291, 0xE12FFF10, 0xE121F000, 0xE5901000, 0x1AFFFFFC] {
+            assert_eq!(table[arm_index(opcode)], decode_arm(opcode));
+        }
+    }
+}
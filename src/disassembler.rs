@@ -1,4 +1,4 @@
-use crate::cpu::*;
+use crate::decode::*;
 
 fn rlist_to_string(rlist: u8, str: &mut String) {
     let mut first = true;
@@ -16,191 +16,186 @@ fn rlist_to_string(rlist: u8, str: &mut String) {
 }
 
 pub fn disassemble_arm(opcode: u32, pc: u32) -> (String, String) {
-    let instr = ((opcode >> 20) & 0xFF) as u8;
     let cond = ((opcode >> 28) & 0xF) as u8;
 
-    match instr {
-        0x00..=0x3F => {
-            if CPU::opcode_match(opcode, ARM_MASK_MUL_CLR, ARM_MASK_MUL_SET) {
-                let rm = (opcode & 0xF) as u8;
-                let rs = ((opcode >> 8) & 0xF) as u8;
-                let rn = ((opcode >> 12) & 0xF) as u8;
-                let rd = ((opcode >> 16) & 0xF) as u8;
+    match decode_arm(opcode) {
+        ArmInstr::Multiply => {
+            let rm = (opcode & 0xF) as u8;
+            let rs = ((opcode >> 8) & 0xF) as u8;
+            let rn = ((opcode >> 12) & 0xF) as u8;
+            let rd = ((opcode >> 16) & 0xF) as u8;
 
-                let set_condition = (opcode & 0x100000) != 0;
-                let accumulate = (opcode & 0x200000) != 0;
+            let set_condition = (opcode & 0x100000) != 0;
+            let accumulate = (opcode & 0x200000) != 0;
 
-                let s_str = match set_condition {
-                    false => "",
-                    true => "S",
-                };
-
-                (
-                    match accumulate {
-                        false => format!("MUL{s_str} R{rd},R{rm},R{rs}"),
-                        true => format!("MLA{s_str} R{rd},R{rm},R{rs},R{rn}"),
-                    },
-                    "COND ---- --AS Rd__ Rn__ Rs__ ---- Rm__".to_string(),
-                )
-            } else if CPU::opcode_match(opcode, ARM_MASK_MUL_LONG_CLR, ARM_MASK_MUL_LONG_SET) {
-                ("Multiply Long".to_string(), "???".to_string())
-            } else if CPU::opcode_match(opcode, ARM_MASK_SNGL_SWP_CLR, ARM_MASK_SNGL_SWP_SET) {
-                ("Single Data Swap".to_string(), "???".to_string())
-            } else if CPU::opcode_match(opcode, ARM_MASK_BX_CLR, ARM_MASK_BX_SET) {
-                let rm = (opcode & 0xF) as u8;
-                (
-                    format!("BX R{}", rm),
-                    "COND ---- ---- ---- ---- ---- ---- Rn__".to_string(),
-                )
-            } else if CPU::opcode_match(opcode, ARM_MASK_HW_REG_CLR, ARM_MASK_HW_REG_SET) {
-                (
-                    "Halfword Data Transfer: register offset".to_string(),
-                    "COND ---P U-WL Rn__ Rd__ ---- -SH- Rm__".to_string(),
-                )
-            } else if CPU::opcode_match(opcode, ARM_MASK_HW_IMM_CLR, ARM_MASK_HW_IMM_SET) {
-                let offset = (((opcode >> 4) & 0xF0) as u8) | ((opcode & 0xF) as u8);
-                let h = (opcode & 0x20) != 0;
-                let s = (opcode & 0x40) != 0;
-                let rd = (opcode >> 12) & 0xF;
-                let rn = (opcode >> 16) & 0xF;
-
-                let load = (opcode & 0x100000) != 0;
-                let write_back = (opcode & 0x200000) != 0;
-                let up = (opcode & 0x800000) != 0;
-                let pre = (opcode & 0x1000000) != 0;
-
-                let mut asm = match load {
-                    false => String::from("STR"),
-                    true => String::from("LDR"),
-                };
+            let s_str = match set_condition {
+                false => "",
+                true => "S",
+            };
 
-                if load && s {
-                    asm.push_str("S");
-                }
+            (
+                match accumulate {
+                    false => format!("MUL{s_str} R{rd},R{rm},R{rs}"),
+                    true => format!("MLA{s_str} R{rd},R{rm},R{rs},R{rn}"),
+                },
+                "COND ---- --AS Rd__ Rn__ Rs__ ---- Rm__".to_string(),
+            )
+        }
+        ArmInstr::MultiplyLong => ("Multiply Long".to_string(), "???".to_string()),
+        ArmInstr::SingleDataSwap => ("Single Data Swap".to_string(), "???".to_string()),
+        ArmInstr::BranchExchange => {
+            let rm = (opcode & 0xF) as u8;
+            (
+                format!("BX R{}", rm),
+                "COND ---- ---- ---- ---- ---- ---- Rn__".to_string(),
+            )
+        }
+        ArmInstr::HalfwordRegister => (
+            "Halfword Data Transfer: register offset".to_string(),
+            "COND ---P U-WL Rn__ Rd__ ---- -SH- Rm__".to_string(),
+        ),
+        ArmInstr::HalfwordImmediate => {
+            let offset = (((opcode >> 4) & 0xF0) as u8) | ((opcode & 0xF) as u8);
+            let h = (opcode & 0x20) != 0;
+            let s = (opcode & 0x40) != 0;
+            let rd = (opcode >> 12) & 0xF;
+            let rn = (opcode >> 16) & 0xF;
 
-                match h {
-                    false => asm.push_str("B"),
-                    true => asm.push_str("H"),
-                }
+            let load = (opcode & 0x100000) != 0;
+            let write_back = (opcode & 0x200000) != 0;
+            let up = (opcode & 0x800000) != 0;
+            let pre = (opcode & 0x1000000) != 0;
 
-                asm.push_str(format!(" R{rd},[R{rn}").as_str());
+            let mut asm = match load {
+                false => String::from("STR"),
+                true => String::from("LDR"),
+            };
 
-                match (pre, up) {
-                    (false, false) => asm.push_str(format!("],-#0x{offset:X}").as_str()),
-                    (false, true) => asm.push_str(format!("],#0x{offset:X}").as_str()),
-                    (true, false) => asm.push_str(format!(",-#0x{offset:X}]").as_str()),
-                    (true, true) => asm.push_str(format!(",#0x{offset:X}]").as_str()),
-                }
+            if load && s {
+                asm.push_str("S");
+            }
 
-                if write_back {
-                    asm.push_str("!");
-                }
+            match h {
+                false => asm.push_str("B"),
+                true => asm.push_str("H"),
+            }
 
-                (
-                    format!("{asm}"),
-                    "COND ---P U-WL Rn__ Rd__ OffH -SH- OffL".to_string(),
-                )
-            } else if CPU::opcode_match(opcode, ARM_MASK_MRS_CLR, ARM_MASK_MRS_SET) {
-                let bits = "COND ---- -P-- ---- Rd__  ---- ---- ----".to_string();
-                ("MRS".to_string(), bits)
-            } else if CPU::opcode_match(opcode, ARM_MASK_MSR_CLR, ARM_MASK_MSR_SET) {
-                let dest_spsr = (opcode & 0x400000) != 0;
-                let rm = (opcode & 0xF) as usize;
-
-                let bits = "COND ---- -P-- ---- ---- ---- ---- Rm__".to_string();
-
-                match dest_spsr {
-                    false => (format!("MSR CPSR,R{}", rm), bits),
-                    true => (format!("MSR SPSR,R{}", rm), bits),
-                }
-            } else if CPU::opcode_match(opcode, ARM_MASK_MSR_BITS_CLR, ARM_MASK_MSR_BITS_SET) {
-                ("MSR bits".to_string(), "???".to_string())
-            } else {
-                let rd = ((opcode >> 12) & 0xF) as u8;
-                let rn = ((opcode >> 16) & 0xF) as u8;
-                let set_condition = (opcode & 0x100000) != 0;
-                let op = ((opcode >> 21) & 0xF) as u8;
-                let i = (opcode & 0x2000000) != 0;
-
-                let s_str = match set_condition {
-                    false => "",
-                    true => "S",
-                };
+            asm.push_str(format!(" R{rd},[R{rn}").as_str());
 
-                let mnemonic = match op {
-                    0x0 => format!("AND{} R{},R{},???", s_str, rd, rn),
-                    0x1 => format!("EOR{} R{},R{},???", s_str, rd, rn),
-                    0x2 => format!("SUB{} R{},R{},???", s_str, rd, rn),
-                    0x3 => format!("RSB{} R{},R{},???", s_str, rd, rn),
-                    0x4 => format!("ADD{} R{},R{},???", s_str, rd, rn),
-                    0x5 => format!("ADC{} R{},R{},???", s_str, rd, rn),
-                    0x6 => format!("SBC{} R{},R{},???", s_str, rd, rn),
-                    0x7 => format!("RSC{} R{},R{},???", s_str, rd, rn),
-                    0x8 => format!("TST{} R{},???", s_str, rn),
-                    0x9 => format!("TEQ{} R{},???", s_str, rn),
-                    0xA => format!("CMP{} R{},???", s_str, rn),
-                    0xB => format!("CMN{} R{},???", s_str, rn),
-                    0xC => format!("ORR{} R{},R{},???", s_str, rd, rn),
-                    0xD => format!("MOV{} R{},???", s_str, rd),
-                    0xE => format!("BIC{} R{},R{},???", s_str, rd, rn),
-                    0xF => format!("MVN{} R{},???", s_str, rd),
-                    _ => unreachable!(""),
-                };
-                let bits = "COND --IOpcodS Rn__ Rd__ Operand2______".to_string();
+            match (pre, up) {
+                (false, false) => asm.push_str(format!("],-#0x{offset:X}").as_str()),
+                (false, true) => asm.push_str(format!("],#0x{offset:X}").as_str()),
+                (true, false) => asm.push_str(format!(",-#0x{offset:X}]").as_str()),
+                (true, true) => asm.push_str(format!(",#0x{offset:X}]").as_str()),
+            }
 
-                (mnemonic, bits)
+            if write_back {
+                asm.push_str("!");
             }
+
+            (
+                format!("{asm}"),
+                "COND ---P U-WL Rn__ Rd__ OffH -SH- OffL".to_string(),
+            )
         }
-        0x40..=0x7F => {
-            if CPU::opcode_match(opcode, ARM_MASK_UNDEF_CLR, ARM_MASK_UNDEF_SET) {
-                ("???".to_string(), "???".to_string())
-            } else {
-                let mut offset = opcode & 0xFFF;
-                let rd = (opcode >> 12) & 0xF;
-                let rn = (opcode >> 16) & 0xF;
+        ArmInstr::Mrs => {
+            let bits = "COND ---- -P-- ---- Rd__  ---- ---- ----".to_string();
+            ("MRS".to_string(), bits)
+        }
+        ArmInstr::Msr => {
+            let dest_spsr = (opcode & 0x400000) != 0;
+            let rm = (opcode & 0xF) as usize;
 
-                let load = (opcode & (1 << 20)) != 0;
-                let write_back = (opcode & (1 << 21)) != 0;
-                let byte = (opcode & (1 << 22)) != 0;
-                let add = (opcode & (1 << 23)) != 0;
-                let pre_index = (opcode & (1 << 24)) != 0;
-                let imm = (opcode & (1 << 25)) != 0;
+            let bits = "COND ---- -P-- ---- ---- ---- ---- Rm__".to_string();
+
+            match dest_spsr {
+                false => (format!("MSR CPSR,R{}", rm), bits),
+                true => (format!("MSR SPSR,R{}", rm), bits),
+            }
+        }
+        ArmInstr::MsrBits => ("MSR bits".to_string(), "???".to_string()),
+        ArmInstr::DataProcessing => {
+            let rd = ((opcode >> 12) & 0xF) as u8;
+            let rn = ((opcode >> 16) & 0xF) as u8;
+            let set_condition = (opcode & 0x100000) != 0;
+            let op = ((opcode >> 21) & 0xF) as u8;
+            let i = (opcode & 0x2000000) != 0;
 
-                let mut res = String::new();
+            let s_str = match set_condition {
+                false => "",
+                true => "S",
+            };
 
-                match load {
-                    false => res.push_str("STR"),
-                    true => res.push_str("LDR"),
-                }
+            let mnemonic = match op {
+                0x0 => format!("AND{} R{},R{},???", s_str, rd, rn),
+                0x1 => format!("EOR{} R{},R{},???", s_str, rd, rn),
+                0x2 => format!("SUB{} R{},R{},???", s_str, rd, rn),
+                0x3 => format!("RSB{} R{},R{},???", s_str, rd, rn),
+                0x4 => format!("ADD{} R{},R{},???", s_str, rd, rn),
+                0x5 => format!("ADC{} R{},R{},???", s_str, rd, rn),
+                0x6 => format!("SBC{} R{},R{},???", s_str, rd, rn),
+                0x7 => format!("RSC{} R{},R{},???", s_str, rd, rn),
+                0x8 => format!("TST{} R{},???", s_str, rn),
+                0x9 => format!("TEQ{} R{},???", s_str, rn),
+                0xA => format!("CMP{} R{},???", s_str, rn),
+                0xB => format!("CMN{} R{},???", s_str, rn),
+                0xC => format!("ORR{} R{},R{},???", s_str, rd, rn),
+                0xD => format!("MOV{} R{},???", s_str, rd),
+                0xE => format!("BIC{} R{},R{},???", s_str, rd, rn),
+                0xF => format!("MVN{} R{},???", s_str, rd),
+                _ => unreachable!(""),
+            };
+            let bits = "COND --IOpcodS Rn__ Rd__ Operand2______".to_string();
 
-                if byte {
-                    res.push_str("B");
-                }
+            (mnemonic, bits)
+        }
+        ArmInstr::Undefined => ("???".to_string(), "???".to_string()),
+        ArmInstr::SingleDataTransfer => {
+            let mut offset = opcode & 0xFFF;
+            let rd = (opcode >> 12) & 0xF;
+            let rn = (opcode >> 16) & 0xF;
+
+            let load = (opcode & (1 << 20)) != 0;
+            let write_back = (opcode & (1 << 21)) != 0;
+            let byte = (opcode & (1 << 22)) != 0;
+            let add = (opcode & (1 << 23)) != 0;
+            let pre_index = (opcode & (1 << 24)) != 0;
+            let imm = (opcode & (1 << 25)) != 0;
+
+            let mut res = String::new();
+
+            match load {
+                false => res.push_str("STR"),
+                true => res.push_str("LDR"),
+            }
 
-                res.push_str(format!(" R{},", rd).as_str());
+            if byte {
+                res.push_str("B");
+            }
 
-                if rn == 15 {
-                    let val = match add {
-                        false => pc.wrapping_sub(offset),
-                        true => pc.wrapping_add(offset),
-                    };
+            res.push_str(format!(" R{},", rd).as_str());
 
-                    res.push_str(format!("[0x{:X}]", val + 8).as_str());
-                } else {
-                    match imm {
-                        false => res.push_str(format!("[R{},#0x{:X}]", rn, offset).as_str()),
-                        true => res.push_str("???"),
-                    }
-                }
+            if rn == 15 {
+                let val = match add {
+                    false => pc.wrapping_sub(offset),
+                    true => pc.wrapping_add(offset),
+                };
 
-                if write_back {
-                    res.push_str("!");
+                res.push_str(format!("[0x{:X}]", val + 8).as_str());
+            } else {
+                match imm {
+                    false => res.push_str(format!("[R{},#0x{:X}]", rn, offset).as_str()),
+                    true => res.push_str("???"),
                 }
+            }
 
-                (res, "COND --IP UBWL _Rn_ _Rd_ ____Offset____".to_string())
+            if write_back {
+                res.push_str("!");
             }
+
+            (res, "COND --IP UBWL _Rn_ _Rd_ ____Offset____".to_string())
         }
-        0x80..=0x9F => {
+        ArmInstr::BlockDataTransfer => {
             let rlist = opcode & 0xFFFF;
             let rn = ((opcode >> 16) & 0xF) as u8;
             let load = (opcode & 0x100000) != 0;
@@ -230,7 +225,7 @@ pub fn disassemble_arm(opcode: u32, pc: u32) -> (String, String) {
                 "COND ---P USWL Rn__ Rlist______________".to_string(),
             )
         }
-        0xA0..=0xBF => {
+        ArmInstr::Branch => {
             let link = (opcode & 0x01000000) != 0;
             let mut offset = (opcode & 0xFFFFFF) << 2;
 
@@ -250,27 +245,23 @@ pub fn disassemble_arm(opcode: u32, pc: u32) -> (String, String) {
 
             (res, "COND ---L ____________Offset___________".to_string())
         }
-        0xC0..=0xDF => ("Coprocessor data transfer".to_string(), "???".to_string()),
-        0xE0..=0xEF => {
-            if (opcode & 0x10) == 0 {
-                ("Coprocessor data operation".to_string(), "???".to_string())
-            } else {
-                (
-                    "Coprocessor register transfer".to_string(),
-                    "???".to_string(),
-                )
-            }
+        ArmInstr::CoprocessorDataTransfer => {
+            ("Coprocessor data transfer".to_string(), "???".to_string())
         }
-        0xF0..=0xFF => ("Software Interrupt".to_string(), "???".to_string()),
-        _ => ("???".to_string(), "???".to_string()),
+        ArmInstr::CoprocessorDataOperation => {
+            ("Coprocessor data operation".to_string(), "???".to_string())
+        }
+        ArmInstr::CoprocessorRegisterTransfer => (
+            "Coprocessor register transfer".to_string(),
+            "???".to_string(),
+        ),
+        ArmInstr::SoftwareInterrupt => ("Software Interrupt".to_string(), "???".to_string()),
     }
 }
 
 pub fn disassemble_thumb(opcode: u16) -> (String, String) {
-    let high = (opcode >> 8) as u8;
-
-    match high {
-        0x00..=0x17 => {
+    match decode_thumb(opcode) {
+        ThumbInstr::MoveShiftedRegister => {
             let rd = (opcode & 0x3) as usize;
             let rs = ((opcode >> 3) & 0x3) as usize;
             let offset = ((opcode >> 6) & 0x1F) as u8;
@@ -295,7 +286,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---O pOffsetRs__Rd_".to_string(),
             )
         } // Move shifted register
-        0x18..=0x1F => {
+        ThumbInstr::AddSubtract => {
             let rd = (opcode & 0x7) as u8;
             let rs = ((opcode >> 3) & 0x7) as u8;
             let offset = ((opcode >> 6) & 0x7) as u8;
@@ -312,7 +303,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- -IORn__Rs__Rd_".to_string(),
             )
         } // Add/subtract
-        0x20..=0x3F => {
+        ThumbInstr::MovCmpAddSubImm => {
             let bits = "---O PRd_ Offset___".to_string();
             let offset = (opcode & 0xFF) as u32;
             let rd = ((opcode >> 8) & 0x7) as u8;
@@ -326,7 +317,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 _ => unreachable!(""),
             }
         } // Move/compare/add/subtract immediate
-        0x40..=0x43 => {
+        ThumbInstr::Alu => {
             let rd = (opcode & 0x7) as u8;
             let rs = ((opcode >> 3) & 0x7) as u8;
             let op = ((opcode >> 6) & 0xF) as u8;
@@ -354,7 +345,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- --Op___Rs__Rd_".to_string(),
             ) // ALU operations
         }
-        0x44..=0x47 => {
+        ThumbInstr::HiRegisterOpBx => {
             let rd = opcode & 0x7;
             let rs = (opcode >> 3) & 0x7;
             let h2 = (opcode & 0x40) != 0;
@@ -382,7 +373,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- --OP 12Rs__Rd_".to_string(),
             )
         } // Hi register operations/branch exchange
-        0x48..=0x4F => {
+        ThumbInstr::PcRelativeLoad => {
             let bits = "---- -Rd_ Word_____".to_string();
 
             let word = (opcode & 0xFF) << 2;
@@ -390,7 +381,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
 
             (format!("LDR R{},[PC,#0x{:02X}]", rd, word), bits)
         }
-        0x50 | 0x51 | 0x54 | 0x55 | 0x58 | 0x59 | 0x5C | 0x5D => {
+        ThumbInstr::LoadStoreRegisterOffset => {
             let rd = (opcode & 0x7) as u8;
             let rb = ((opcode >> 3) & 0x7) as u8;
             let ro = ((opcode >> 6) & 0x7) as u8;
@@ -408,7 +399,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- LB-Ro__Rb__Rd_".to_string(),
             )
         } // Load/store with register offset
-        0x52 | 0x53 | 0x56 | 0x57 | 0x5A | 0x5B | 0x5E | 0x5F => {
+        ThumbInstr::LoadStoreSignExtended => {
             let rd = (opcode & 0x7) as u8;
             let rb = ((opcode >> 3) & 0x7) as u8;
             let ro = ((opcode >> 6) & 0x7) as u8;
@@ -425,7 +416,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- HS-Ro__Rb__Rd_".to_string(),
             )
         } // Load/store sign-extended byte/halfword
-        0x60..=0x7F => {
+        ThumbInstr::LoadStoreImmediate => {
             let rd = (opcode & 0x7) as u8;
             let rb = ((opcode >> 3) & 0x7) as u8;
             let offset = ((opcode >> 6) & 0x1F) as u32;
@@ -457,7 +448,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 bits.to_string(),
             )
         }
-        0x80..=0x8F => {
+        ThumbInstr::LoadStoreHalfword => {
             let rd = (opcode & 0x7) as u8;
             let rb = ((opcode >> 3) & 0x7) as u8;
             let offset = (((opcode >> 6) & 0x1F) << 1) as u32;
@@ -472,7 +463,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 bits,
             ) // Load/store halfword
         }
-        0x90..=0x9F => {
+        ThumbInstr::SpRelativeLoadStore => {
             let imm = (opcode & 0xFF) << 2;
             let rd = ((opcode >> 8) & 0x7) as u8;
             let load = (opcode & 0x800) != 0;
@@ -485,7 +476,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- LRd_ Offset__".to_string(),
             )
         } // SP-relative load/store
-        0xA0..=0xAF => {
+        ThumbInstr::LoadAddress => {
             let imm = (opcode & 0xFF) << 2;
             let rd = (opcode >> 8) & 0x7;
             let sp = (opcode & 0x0800) != 0;
@@ -498,7 +489,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- SRd_ Immediate".to_string(),
             )
         } // Load address
-        0xB0 => {
+        ThumbInstr::OffsetToSp => {
             let imm = ((opcode & 0x7F) << 2) as u32;
             let neg = (opcode & 0x80) != 0;
             (
@@ -509,7 +500,10 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- ---- SOffset__".to_string(),
             )
         } // Add offset to stack pointer
-        0xB4 | 0xB5 => {
+        ThumbInstr::PushPop if (opcode & 0x0800) != 0 => {
+            ("POP ???".to_string(), "---- L--R __RLIST__".to_string())
+        } // Push/pop registers
+        ThumbInstr::PushPop => {
             let store_lr = (opcode & 0x0100) != 0;
             let rlist = (opcode & 0xFF) as u8;
 
@@ -525,8 +519,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
 
             (res, "---- L--R __RLIST__".to_string())
         } // Push/pop registers
-        0xBC | 0xBD => ("POP ???".to_string(), "---- L--R __RLIST__".to_string()), // Push/pop registers
-        0xC0..=0xCF => {
+        ThumbInstr::MultipleLoadStore => {
             let rlist = (opcode & 0xFF) as u8;
             let rb = ((opcode >> 8) & 0x7) as u8;
             let load = (opcode & 0x0800) != 0;
@@ -543,7 +536,7 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- LRb_ Rlist____".to_string(),
             )
         } // Multiple load/store
-        0xD0..=0xDE => {
+        ThumbInstr::ConditionalBranch => {
             let offset = ((opcode & 0xFF) << 1) as u32;
             let cond = (opcode >> 8) & 0xF;
 
@@ -570,12 +563,12 @@ pub fn disassemble_thumb(opcode: u16) -> (String, String) {
                 "---- COND Immediate".to_string(),
             )
         } // Conditional branch
-        0xDF => (
+        ThumbInstr::Swi => (
             format!("SWI {:2X}", opcode & 0xFF),
             "---- ---- __Value8__".to_string(),
         ), // Software interrupt
-        0xE0..=0xE7 => ("Unconditional branch,".to_string(), "???".to_string()), // Unconditional branch,
-        0xF0..=0xFF => ("BL ???".to_string(), "---- H____Offset___".to_string()), // Long branch with link
-        _ => ("???".to_string(), "???".to_string()),
+        ThumbInstr::UnconditionalBranch => ("Unconditional branch,".to_string(), "???".to_string()), // Unconditional branch,
+        ThumbInstr::LongBranchLink => ("BL ???".to_string(), "---- H____Offset___".to_string()), // Long branch with link
+        ThumbInstr::Unknown => ("???".to_string(), "???".to_string()),
     }
 }
@@ -1,7 +1,8 @@
 use std::cmp::Ordering;
 
-use log::info;
+use log::{info, warn};
 use sdl2::{
+    controller::{Axis, GameController},
     event::Event,
     keyboard::Keycode,
     pixels::PixelFormatEnum,
@@ -16,6 +17,9 @@ use crate::{
     renderer::{self, RenderMessage},
 };
 
+/// Longest rumble of the game controller, stops a motor left on while the emulator is paused
+const RUMBLE_DURATION_MS: u32 = 5000;
+
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum Dump {
     Video,
@@ -35,6 +39,8 @@ pub enum WindowEvent {
     Debug(u8),
     Dump(Dump),
     ForceRender,
+    /// Cartridge tilt on the X and Y axis
+    Tilt(i16, i16),
 }
 
 pub struct GameWindow {
@@ -42,10 +48,13 @@ pub struct GameWindow {
     pub texture_creator: TextureCreator<WindowContext>,
     pub event_pump: EventPump,
     pub paused: bool,
+    /// The left stick tilts the cartridge, the motor rumbles with the cartridge
+    controller: Option<GameController>,
+    tilt: (i16, i16),
 }
 
 impl GameWindow {
-    pub fn new() -> Self {
+    pub fn new(title: &str) -> Self {
         let sdl_context = sdl2::init().expect("[SDL] Failed to create context");
         let video_subsystem = sdl_context
             .video()
@@ -58,7 +67,7 @@ impl GameWindow {
         //let height = 512;
 
         let window = video_subsystem
-            .window("pGBA", width, height)
+            .window(title, width, height)
             .opengl()
             .position(0, 0)
             .build()
@@ -77,11 +86,24 @@ impl GameWindow {
             .event_pump()
             .expect("[SDL] Failed to get event pump");
 
+        // First connected game controller, if any
+        let controller = sdl_context.game_controller().ok().and_then(|subsystem| {
+            (0..subsystem.num_joysticks().unwrap_or(0))
+                .filter(|i| subsystem.is_game_controller(*i))
+                .find_map(|i| subsystem.open(i).ok())
+        });
+
+        if let Some(controller) = &controller {
+            info!("[SDL] Using game controller `{}`", controller.name());
+        }
+
         Self {
             canvas,
             texture_creator,
             event_pump,
             paused: false,
+            controller,
+            tilt: (0, 0),
         }
     }
 
@@ -179,8 +201,25 @@ impl GameWindow {
         self.canvas.present();
     }
 
+    /// Switches the game controller rumble with the motor of the cartridge
+    pub fn set_rumble(&mut self, on: bool) {
+        let controller = match &mut self.controller {
+            Some(controller) => controller,
+            None => return,
+        };
+
+        let strength = match on {
+            true => u16::MAX,
+            false => 0,
+        };
+        if let Err(e) = controller.set_rumble(strength, strength, RUMBLE_DURATION_MS) {
+            warn!("[SDL] Failed to set rumble: {e}");
+        }
+    }
+
     pub fn update(&mut self) -> Option<Vec<WindowEvent>> {
         let mut events = Vec::new();
+        let tilt = self.tilt;
         for event in self.event_pump.poll_iter() {
             match event {
                 // Keymap:
@@ -197,6 +236,8 @@ impl GameWindow {
                 //
                 // Start => Enter
                 // Select => Backspace
+                //
+                // Tilt sensor => I, J, K, L or left stick
                 Event::Quit { .. }
                 | Event::KeyDown {
                     keycode: Some(Keycode::Escape),
@@ -225,6 +266,10 @@ impl GameWindow {
                     Keycode::F2 => events.push(WindowEvent::Debug(2)),
                     Keycode::F3 => events.push(WindowEvent::Debug(3)),
                     Keycode::V => events.push(WindowEvent::Dump(Dump::Video)),
+                    Keycode::J => self.tilt.0 = -i16::MAX,
+                    Keycode::L => self.tilt.0 = i16::MAX,
+                    Keycode::I => self.tilt.1 = -i16::MAX,
+                    Keycode::K => self.tilt.1 = i16::MAX,
                     _ => {}
                 },
                 Event::KeyUp {
@@ -245,12 +290,29 @@ impl GameWindow {
                     Keycode::Down => events.push(WindowEvent::ButtonRelease(keypad::BUTTON_DOWN)),
                     Keycode::S => events.push(WindowEvent::ButtonRelease(keypad::BUTTON_R)),
                     Keycode::A => events.push(WindowEvent::ButtonRelease(keypad::BUTTON_L)),
+                    Keycode::J | Keycode::L => self.tilt.0 = 0,
+                    Keycode::I | Keycode::K => self.tilt.1 = 0,
                     _ => {}
                 },
+                Event::ControllerAxisMotion {
+                    axis: Axis::LeftX,
+                    value,
+                    ..
+                } => self.tilt.0 = value,
+                Event::ControllerAxisMotion {
+                    axis: Axis::LeftY,
+                    value,
+                    ..
+                } => self.tilt.1 = value,
                 _ => {}
             }
         }
 
+        // Report the tilt whenever it changed
+        if tilt != self.tilt {
+            events.push(WindowEvent::Tilt(self.tilt.0, self.tilt.1));
+        }
+
         match events.is_empty() {
             false => Some(events),
             true => None,
@@ -0,0 +1,634 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::*;
+
+/// GPIO registers in the ROM area, offsets from 0x08000000
+pub const GPIO_DATA: u32 = 0xC4;
+pub const GPIO_DIRECTION: u32 = 0xC6;
+pub const GPIO_CONTROL: u32 = 0xC8;
+
+/// RTC pins of the GPIO port
+const RTC_PIN_SCK: u8 = 0x1;
+const RTC_PIN_SIO: u8 = 0x2;
+const RTC_PIN_CS: u8 = 0x4;
+
+/// RTC commands (bits 4-6 of the command byte) and their number of parameter bytes
+const RTC_COMMAND_RESET: u8 = 0;
+const RTC_COMMAND_DATETIME: u8 = 2;
+const RTC_COMMAND_CONTROL: u8 = 4;
+const RTC_COMMAND_TIME: u8 = 6;
+const RTC_COMMAND_BYTES: [usize; 8] = [0, 0, 7, 0, 1, 0, 3, 0];
+
+/// RTC control register, 24 hour mode
+const RTC_CONTROL_24H: u8 = 0x40;
+
+/// Solar sensor pins, the sensor is ignored while the RTC is selected
+const SOLAR_PIN_CLOCK: u8 = 0x1;
+const SOLAR_PIN_RESET: u8 = 0x2;
+const SOLAR_PIN_CS: u8 = 0x4;
+const SOLAR_PIN_FLAG: u8 = 0x8;
+
+/// Rumble motor pin
+const RUMBLE_PIN: u8 = 0x8;
+
+/// Tilt sensor registers on the SRAM bus, offsets from 0x0E000000
+const TILT_START: u32 = 0x8000;
+const TILT_LATCH: u32 = 0x8100;
+const TILT_X_LOW: u32 = 0x8200;
+const TILT_X_HIGH: u32 = 0x8300;
+const TILT_Y_LOW: u32 = 0x8400;
+const TILT_Y_HIGH: u32 = 0x8500;
+
+/// Tilt sensor value when the GBA lies flat
+const TILT_CENTER: u16 = 0x3A0;
+
+/// Peripherals of the cartridge, connected when the ROM is loaded
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Peripherals {
+    pub rtc: bool,
+    pub rumble: bool,
+    pub solar: bool,
+    pub tilt: bool,
+}
+
+const RTC: Peripherals = Peripherals {
+    rtc: true,
+    rumble: false,
+    solar: false,
+    tilt: false,
+};
+const RTC_SOLAR: Peripherals = Peripherals { solar: true, ..RTC };
+const RUMBLE: Peripherals = Peripherals {
+    rtc: false,
+    rumble: true,
+    solar: false,
+    tilt: false,
+};
+const TILT: Peripherals = Peripherals {
+    rtc: false,
+    rumble: false,
+    solar: false,
+    tilt: true,
+};
+
+/// Peripherals of the games using them, keyed by game code
+const PERIPHERALS_DATABASE: &[(&[u8; 4], Peripherals)] = &[
+    (b"AXVE", RTC),       // Pokemon Ruby
+    (b"AXPE", RTC),       // Pokemon Sapphire
+    (b"BPEE", RTC),       // Pokemon Emerald
+    (b"U3IE", RTC_SOLAR), // Boktai
+    (b"U3IJ", RTC_SOLAR), // Bokura no Taiyou
+    (b"U3IP", RTC_SOLAR), // Boktai
+    (b"U32E", RTC_SOLAR), // Boktai 2
+    (b"U32J", RTC_SOLAR), // Zoku Bokura no Taiyou
+    (b"U32P", RTC_SOLAR), // Boktai 2
+    (b"U33J", RTC_SOLAR), // Shin Bokura no Taiyou
+    (b"V49E", RUMBLE),    // Drill Dozer
+    (b"V49J", RUMBLE),    // Screw Breaker
+    (b"RZWE", RUMBLE),    // WarioWare: Twisted!
+    (b"RZWJ", RUMBLE),    // Mawaru Made in Wario
+    (b"KYGE", TILT),      // Yoshi Topsy-Turvy
+    (b"KYGJ", TILT),      // Yoshi no Banyuu Inryoku
+    (b"KYGP", TILT),      // Yoshi's Universal Gravitation
+    (b"KHPJ", TILT),      // Koro Koro Puzzle Happy Panechu!
+];
+
+/// ID string of the RTC library
+const RTC_ID_STRING: &[u8] = b"SIIRTC_V";
+
+/// ID strings the Nintendo peripheral libraries leave in the ROM
+pub const PERIPHERALS_ID_STRINGS: &[&[u8]] = &[RTC_ID_STRING];
+
+impl Peripherals {
+    /// Selects the peripherals from the game database,
+    /// otherwise from the ID strings found in the ROM
+    pub fn detect(game_code: Option<&str>, id_strings: &[&[u8]]) -> Self {
+        let known = PERIPHERALS_DATABASE
+            .iter()
+            .find(|(code, _)| Some(code.as_slice()) == game_code.map(str::as_bytes));
+
+        if let Some((_, peripherals)) = known {
+            return *peripherals;
+        }
+
+        Self {
+            rtc: id_strings.contains(&RTC_ID_STRING),
+            ..Self::default()
+        }
+    }
+}
+
+/// Time source of the RTC
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RtcClock {
+    /// Host clock (UTC)
+    Host,
+    /// Always the same time in seconds since the Unix epoch, for deterministic runs
+    Fixed(u64),
+}
+
+impl RtcClock {
+    fn now(self) -> u64 {
+        match self {
+            RtcClock::Host => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |time| time.as_secs()),
+            RtcClock::Fixed(time) => time,
+        }
+    }
+}
+
+fn bcd(val: u64) -> u8 {
+    (((val / 10) << 4) | (val % 10)) as u8
+}
+
+/// Seiko S-3511 real-time clock, a serial device on the GPIO pins SCK, SIO and CS
+/// Bytes are transferred LSB first, the data bit is sampled on the rising edge of SCK
+pub struct Rtc {
+    clock: RtcClock,
+    control: u8,
+    /// 0 = waiting for SCK high with CS low, 1 = waiting for CS high, 2 = transfer
+    transfer_step: u8,
+    /// Command byte, bit 7 set for reads, None until it is received
+    command: Option<u8>,
+    bits: u8,
+    bits_transferred: usize,
+    bytes_remaining: usize,
+    /// Date and time latched by the command: year, month, day, weekday, hour, minute, second
+    time: [u8; 7],
+}
+
+impl Rtc {
+    pub fn new(clock: RtcClock) -> Self {
+        Self {
+            clock,
+            control: RTC_CONTROL_24H,
+            transfer_step: 0,
+            command: None,
+            bits: 0,
+            bits_transferred: 0,
+            bytes_remaining: 0,
+            time: [0; 7],
+        }
+    }
+
+    /// Latches the current date and time in BCD
+    fn latch_time(&mut self) {
+        let now = self.clock.now();
+        let days = now / 86400;
+        let seconds = now % 86400;
+
+        // Civil date from days since 1970-01-01
+        let z = days + 719468;
+        let era = z / 146097;
+        let doe = z % 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + (month <= 2) as u64;
+
+        let hour = seconds / 3600;
+        let hour_bcd = match (self.control & RTC_CONTROL_24H) != 0 {
+            true => bcd(hour),
+            false => bcd(hour % 12),
+        };
+        let pm = match hour >= 12 {
+            true => 0x80,
+            false => 0x00,
+        };
+
+        self.time = [
+            bcd(year % 100),
+            bcd(month),
+            bcd(day),
+            bcd((days + 4) % 7), // 1970-01-01 was a Thursday
+            hour_bcd | pm,
+            bcd((seconds / 60) % 60),
+            bcd(seconds % 60),
+        ];
+    }
+
+    /// Bit sent to the GBA during a read command
+    fn output(&self) -> u8 {
+        let command = match self.command {
+            Some(command) if self.bytes_remaining > 0 => command,
+            _ => return 0,
+        };
+
+        let byte = match (command >> 4) & 0x7 {
+            RTC_COMMAND_CONTROL => self.control,
+            RTC_COMMAND_DATETIME | RTC_COMMAND_TIME => self.time[7 - self.bytes_remaining],
+            _ => 0,
+        };
+
+        (byte >> self.bits_transferred) & 0x1
+    }
+
+    /// Handles a received byte, the command or a parameter of a write command
+    fn process_byte(&mut self) {
+        let byte = self.bits;
+        self.bits = 0;
+        self.bits_transferred = 0;
+
+        match self.command {
+            None => {
+                // Bits 0-3 of the command byte are always 0110
+                if (byte & 0xF) != 0x6 {
+                    warn!("RTC: invalid command `{:02X}`", byte);
+                    return;
+                }
+
+                let command = (byte >> 4) & 0x7;
+                self.bytes_remaining = RTC_COMMAND_BYTES[command as usize];
+                self.command = Some(byte);
+
+                match command {
+                    RTC_COMMAND_RESET => self.control = 0,
+                    RTC_COMMAND_DATETIME | RTC_COMMAND_TIME => self.latch_time(),
+                    _ => {}
+                }
+            }
+            Some(command) => {
+                if (command >> 4) & 0x7 == RTC_COMMAND_CONTROL {
+                    self.control = byte;
+                }
+                self.bytes_remaining = self.bytes_remaining.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Updates the RTC with the pins written by the GBA
+    /// Returns the SIO bit driven by the RTC during reads
+    pub fn write_pins(&mut self, pins: u8) -> Option<u8> {
+        match self.transfer_step {
+            0 => {
+                if (pins & (RTC_PIN_SCK | RTC_PIN_CS)) == RTC_PIN_SCK {
+                    self.transfer_step = 1;
+                }
+            }
+            1 => match pins & (RTC_PIN_SCK | RTC_PIN_CS) {
+                0x5 => self.transfer_step = 2,
+                RTC_PIN_SCK => {}
+                _ => self.transfer_step = 0,
+            },
+            _ => {
+                if (pins & RTC_PIN_CS) == 0 {
+                    // CS low ends the transfer
+                    self.transfer_step = 0;
+                    self.command = None;
+                    self.bits = 0;
+                    self.bits_transferred = 0;
+                    self.bytes_remaining = 0;
+                } else if (pins & RTC_PIN_SCK) == 0 {
+                    // Data is set while SCK is low
+                    let bit = 1 << self.bits_transferred;
+                    self.bits = match (pins & RTC_PIN_SIO) != 0 {
+                        true => self.bits | bit,
+                        false => self.bits & !bit,
+                    };
+                } else {
+                    // Rising edge of SCK
+                    match self.command {
+                        Some(command) if (command & 0x80) != 0 => {
+                            let bit = self.output();
+                            self.bits_transferred += 1;
+                            if self.bits_transferred == 8 {
+                                self.bits_transferred = 0;
+                                self.bytes_remaining = self.bytes_remaining.saturating_sub(1);
+                            }
+                            return Some(bit);
+                        }
+                        _ => {
+                            self.bits_transferred += 1;
+                            if self.bits_transferred == 8 {
+                                self.process_byte();
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// Solar sensor of the Boktai games, a light dependent counter threshold
+/// Clock pulses count up after a reset until the flag shows the light level was reached
+pub struct SolarSensor {
+    /// 0 (dark) to 255 (direct sunlight)
+    pub level: u8,
+    counter: u8,
+    clock: bool,
+}
+
+impl SolarSensor {
+    pub fn new(level: u8) -> Self {
+        Self {
+            level,
+            counter: 0,
+            clock: false,
+        }
+    }
+
+    /// Updates the sensor with the pins written by the GBA, returns the flag pin
+    fn write_pins(&mut self, pins: u8) -> Option<u8> {
+        if (pins & SOLAR_PIN_CS) != 0 {
+            return None;
+        }
+
+        if (pins & SOLAR_PIN_RESET) != 0 {
+            self.counter = 0;
+        }
+
+        let clock = (pins & SOLAR_PIN_CLOCK) != 0;
+        if clock && !self.clock {
+            self.counter = self.counter.saturating_add(1);
+        }
+        self.clock = clock;
+
+        match self.counter >= 0xFF - self.level {
+            true => Some(SOLAR_PIN_FLAG),
+            false => Some(0),
+        }
+    }
+}
+
+/// Tilt sensor of Yoshi Topsy-Turvy and Koro Koro Puzzle on the SRAM bus at 0x0E008000
+/// Writing 0x55 to 0x8000 then 0xAA to 0x8100 samples both 12 bit axes
+pub struct TiltSensor {
+    /// Tilt from the input, full range of the axis at the maximum tilt
+    input: (i16, i16),
+    sample: (u16, u16),
+    started: bool,
+}
+
+impl TiltSensor {
+    pub fn new() -> Self {
+        Self {
+            input: (0, 0),
+            sample: (TILT_CENTER, TILT_CENTER),
+            started: false,
+        }
+    }
+
+    /// Tilts the cartridge from a keyboard or gamepad axis
+    pub fn set(&mut self, x: i16, y: i16) {
+        self.input = (x, y);
+    }
+
+    /// The register at `offset` from 0x0E000000 belongs to the sensor
+    pub fn is_register(offset: u32) -> bool {
+        (TILT_START..=TILT_Y_HIGH + 0xFF).contains(&(offset & 0xFFFF))
+    }
+
+    pub fn read_u8(&self, offset: u32) -> u8 {
+        match offset & 0xFF00 {
+            TILT_X_LOW => self.sample.0 as u8,
+            // Bit 7 flags the sample as ready
+            TILT_X_HIGH => ((self.sample.0 >> 8) as u8 & 0xF) | 0x80,
+            TILT_Y_LOW => self.sample.1 as u8,
+            TILT_Y_HIGH => (self.sample.1 >> 8) as u8 & 0xF,
+            _ => 0,
+        }
+    }
+
+    pub fn write_u8(&mut self, offset: u32, val: u8) {
+        match (offset & 0xFF00, val) {
+            (TILT_START, 0x55) => self.started = true,
+            (TILT_LATCH, 0xAA) if self.started => {
+                let axis = |tilt: i16| TILT_CENTER.wrapping_add_signed(-(tilt >> 6)) & 0xFFF;
+                self.sample = (axis(self.input.0), axis(self.input.1));
+                self.started = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// General purpose I/O port of the cartridge at 0x080000C4-0x080000C9
+pub struct Gpio {
+    /// Level of the 4 pins
+    data: u8,
+    /// Pins driven by the GBA (1) or by the cartridge (0)
+    direction: u8,
+    /// The registers read back as GPIO instead of ROM
+    readable: bool,
+    pub rtc: Option<Rtc>,
+    pub solar: Option<SolarSensor>,
+    /// Rumble motor state, None without a motor
+    rumble: Option<bool>,
+    /// The motor was switched since the last `take_rumble`
+    rumble_changed: bool,
+}
+
+impl Gpio {
+    pub fn new(peripherals: Peripherals, clock: RtcClock, light_level: u8) -> Self {
+        Self {
+            data: 0,
+            direction: 0,
+            readable: false,
+            rtc: peripherals.rtc.then(|| Rtc::new(clock)),
+            solar: peripherals.solar.then(|| SolarSensor::new(light_level)),
+            rumble: peripherals.rumble.then_some(false),
+            rumble_changed: false,
+        }
+    }
+
+    /// A device is connected to the port
+    pub fn connected(&self) -> bool {
+        self.rtc.is_some() || self.solar.is_some() || self.rumble.is_some()
+    }
+
+    /// Returns the new rumble motor state if it was switched since the last call
+    pub fn take_rumble(&mut self) -> Option<bool> {
+        match std::mem::take(&mut self.rumble_changed) {
+            true => self.rumble,
+            false => None,
+        }
+    }
+
+    /// Drives the input `pin` from the cartridge, output pins are left to the GBA
+    fn set_input(&mut self, pin: u8, val: u8) {
+        if (self.direction & pin) == 0 {
+            self.data = (self.data & !pin) | (val & pin);
+        }
+    }
+
+    /// Reads the register at `offset` from 0x08000000, None if the ROM is read instead
+    pub fn read_u16(&self, offset: u32) -> Option<u16> {
+        if !self.readable {
+            return None;
+        }
+
+        match offset & !0x1 {
+            GPIO_DATA => Some(self.data as u16),
+            GPIO_DIRECTION => Some(self.direction as u16),
+            GPIO_CONTROL => Some(self.readable as u16),
+            _ => None,
+        }
+    }
+
+    pub fn write_u16(&mut self, offset: u32, val: u16) {
+        match offset & !0x1 {
+            GPIO_DATA => {
+                // Only output pins are set by the GBA
+                self.data = (self.data & !self.direction) | ((val as u8) & self.direction & 0xF);
+
+                let pins = self.data;
+
+                if let Some(bit) = self.rtc.as_mut().and_then(|rtc| rtc.write_pins(pins)) {
+                    self.set_input(RTC_PIN_SIO, bit << 1);
+                }
+
+                if let Some(flag) = self.solar.as_mut().and_then(|solar| solar.write_pins(pins)) {
+                    self.set_input(SOLAR_PIN_FLAG, flag);
+                }
+
+                if let Some(rumble) = self.rumble {
+                    let on = (pins & self.direction & RUMBLE_PIN) != 0;
+                    if on != rumble {
+                        self.rumble = Some(on);
+                        self.rumble_changed = true;
+                    }
+                }
+            }
+            GPIO_DIRECTION => self.direction = (val & 0xF) as u8,
+            GPIO_CONTROL => self.readable = (val & 0x1) != 0,
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::{CPU, MMU};
+
+    #[test]
+    fn gpio_rtc() {
+        const SCK: u16 = 0x1;
+        const CS: u16 = 0x4;
+
+        fn send_byte(cpu: &mut CPU, byte: u8) {
+            for i in 0..8 {
+                let sio = (((byte >> i) & 0x1) as u16) << 1;
+                cpu.write_u16(true, 0x080000C4, CS | sio);
+                cpu.write_u16(true, 0x080000C4, CS | sio | SCK);
+            }
+        }
+
+        fn receive_byte(cpu: &mut CPU) -> u8 {
+            (0..8).fold(0, |byte, i| {
+                cpu.write_u16(true, 0x080000C4, CS);
+                cpu.write_u16(true, 0x080000C4, CS | SCK);
+                byte | ((((cpu.read_u16(true, 0x080000C4) >> 1) & 0x1) as u8) << i)
+            })
+        }
+
+        /// Sends `command` and reads `len` bytes back
+        fn command(cpu: &mut CPU, command: u8, params: &[u8], len: usize) -> Vec<u8> {
+            cpu.write_u16(true, 0x080000C6, 0x7);
+            cpu.write_u16(true, 0x080000C4, SCK);
+            cpu.write_u16(true, 0x080000C4, CS | SCK);
+            send_byte(cpu, command);
+            for param in params {
+                send_byte(cpu, *param);
+            }
+
+            cpu.write_u16(true, 0x080000C6, 0x5);
+            let bytes = (0..len).map(|_| receive_byte(cpu)).collect();
+            cpu.write_u16(true, 0x080000C4, SCK);
+            bytes
+        }
+
+        let mut rom = vec![0; 0x200];
+        rom[0xC4] = 0xAB;
+        rom[0x100..0x108].copy_from_slice(b"SIIRTC_V");
+
+        let mut cpu = CPU::new();
+        cpu.rtc_clock = RtcClock::Fixed(1095860730); // 2004-09-22 13:45:30, Wednesday
+        cpu.load_rom(&rom);
+        assert!(cpu.gpio.rtc.is_some());
+
+        // The ROM is read until the registers are made readable
+        assert_eq!(cpu.read_u8(true, 0x080000C4), 0xAB);
+        cpu.write_u16(true, 0x080000C8, 0x1);
+        assert_eq!(cpu.read_u16(true, 0x080000C8), 0x1);
+
+        // Date and time in BCD, 24 hour mode
+        assert_eq!(
+            command(&mut cpu, 0xA6, &[], 7),
+            [0x04, 0x09, 0x22, 0x03, 0x93, 0x45, 0x30]
+        );
+
+        // 12 hour mode through the control register
+        command(&mut cpu, 0x46, &[0x00], 0);
+        assert_eq!(command(&mut cpu, 0xC6, &[], 1), [0x00]);
+        assert_eq!(command(&mut cpu, 0xE6, &[], 3), [0x81, 0x45, 0x30]);
+
+        // Without the ID string there is no RTC and the ROM is read
+        rom[0x100..0x108].fill(0);
+        cpu.load_rom(&rom);
+        assert!(!cpu.gpio.connected());
+        assert_eq!(cpu.read_u8(true, 0x080000C4), 0xAB);
+    }
+
+    #[test]
+    fn cartridge_peripherals() {
+        let mut rom = vec![0; 0x200];
+
+        // Boktai, the solar sensor counts clock pulses up to the light level
+        rom[0xAC..0xB0].copy_from_slice(b"U3IE");
+        let mut cpu = CPU::new();
+        cpu.light_level = 0x80;
+        cpu.load_rom(&rom);
+        assert!(cpu.gpio.rtc.is_some() && cpu.gpio.solar.is_some());
+        assert!(cpu.tilt.is_none());
+
+        cpu.write_u16(true, 0x080000C8, 0x1);
+        cpu.write_u16(true, 0x080000C6, 0x7);
+        cpu.write_u16(true, 0x080000C4, 0x2);
+        cpu.write_u16(true, 0x080000C4, 0x0);
+        for i in 0..0x7F {
+            assert_eq!(cpu.read_u16(true, 0x080000C4) & 0x8, 0, "{}", i);
+            cpu.write_u16(true, 0x080000C4, 0x1);
+            cpu.write_u16(true, 0x080000C4, 0x0);
+        }
+        assert_eq!(cpu.read_u16(true, 0x080000C4) & 0x8, 0x8);
+
+        // Drill Dozer, the rumble motor is reported once per switch
+        rom[0xAC..0xB0].copy_from_slice(b"V49E");
+        cpu.load_rom(&rom);
+        assert!(cpu.gpio.rtc.is_none());
+        assert_eq!(cpu.gpio.take_rumble(), None);
+
+        cpu.write_u16(true, 0x080000C6, 0x8);
+        cpu.write_u16(true, 0x080000C4, 0x8);
+        assert_eq!(cpu.gpio.take_rumble(), Some(true));
+        assert_eq!(cpu.gpio.take_rumble(), None);
+        cpu.write_u16(true, 0x080000C4, 0x0);
+        assert_eq!(cpu.gpio.take_rumble(), Some(false));
+
+        // Yoshi Topsy-Turvy, the tilt is sampled on the SRAM bus
+        rom[0xAC..0xB0].copy_from_slice(b"KYGE");
+        cpu.load_rom(&rom);
+        assert!(!cpu.gpio.connected());
+
+        if let Some(tilt) = &mut cpu.tilt {
+            tilt.set(i16::MAX, -i16::MAX);
+        }
+        assert_eq!(cpu.read_u8(true, 0x0E008300), 0x83);
+        cpu.write_u8(true, 0x0E008000, 0x55);
+        cpu.write_u8(true, 0x0E008100, 0xAA);
+
+        let x =
+            cpu.read_u8(true, 0x0E008200) as u16 | ((cpu.read_u8(true, 0x0E008300) as u16) << 8);
+        let y =
+            cpu.read_u8(true, 0x0E008400) as u16 | ((cpu.read_u8(true, 0x0E008500) as u16) << 8);
+        assert_eq!(x, 0x8000 | (0x3A0 - 0x1FF));
+        assert_eq!(y, 0x3A0 + 0x200);
+    }
+}
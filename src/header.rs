@@ -0,0 +1,252 @@
+use std::fmt;
+
+use crate::{backup, gpio};
+
+/// Size of the cartridge header at the start of the ROM
+const HEADER_SIZE: usize = 0xC0;
+
+/// Largest ROM mapped at 0x08000000
+const ROM_MAX_SIZE: usize = 32 * 1024 * 1024;
+
+/// Compressed Nintendo logo at 0x04, checked by the BIOS before booting the ROM
+pub const NINTENDO_LOGO: [u8; 156] = [
+    0x24, 0xFF, 0xAE, 0x51, 0x69, 0x9A, 0xA2, 0x21, 0x3D, 0x84, 0x82, 0x0A, 0x84, 0xE4, 0x09, 0xAD,
+    0x11, 0x24, 0x8B, 0x98, 0xC0, 0x81, 0x7F, 0x21, 0xA3, 0x52, 0xBE, 0x19, 0x93, 0x09, 0xCE, 0x20,
+    0x10, 0x46, 0x4A, 0x4A, 0xF8, 0x27, 0x31, 0xEC, 0x58, 0xC7, 0xE8, 0x33, 0x82, 0xE3, 0xCE, 0xBF,
+    0x85, 0xF4, 0xDF, 0x94, 0xCE, 0x4B, 0x09, 0xC1, 0x94, 0x56, 0x8A, 0xC0, 0x13, 0x72, 0xA7, 0xFC,
+    0x9F, 0x84, 0x4D, 0x73, 0xA3, 0xCA, 0x9A, 0x61, 0x58, 0x97, 0xA3, 0x27, 0xFC, 0x03, 0x98, 0x76,
+    0x23, 0x1D, 0xC7, 0x61, 0x03, 0x04, 0xAE, 0x56, 0xBF, 0x38, 0x84, 0x00, 0x40, 0xA7, 0x0E, 0xFD,
+    0xFF, 0x52, 0xFE, 0x03, 0x6F, 0x95, 0x30, 0xF1, 0x97, 0xFB, 0xC0, 0x85, 0x60, 0xD6, 0x80, 0x25,
+    0xA9, 0x63, 0xBE, 0x03, 0x01, 0x4E, 0x38, 0xE2, 0xF9, 0xA2, 0x34, 0xFF, 0xBB, 0x3E, 0x03, 0x44,
+    0x78, 0x00, 0x90, 0xCB, 0x88, 0x11, 0x3A, 0x94, 0x65, 0xC0, 0x7C, 0x63, 0x87, 0xF0, 0x3C, 0xAF,
+    0xD6, 0x25, 0xE4, 0x8B, 0x38, 0x0A, 0xAC, 0x72, 0x21, 0xD4, 0xF8, 0x07,
+];
+
+/// Value required at 0xB2
+const FIXED_VALUE: u8 = 0x96;
+
+/// Problems of a ROM found by `RomHeader::validate`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum HeaderIssue {
+    /// The header checksum at 0xBD does not match the header
+    Checksum { expected: u8, found: u8 },
+    /// The ROM is larger than 32 MB or not a power of two
+    Size(usize),
+    /// The Nintendo logo at 0x04 is missing or modified
+    Logo,
+    /// The fixed value at 0xB2 is not 0x96
+    FixedValue(u8),
+    /// The first instruction is not an ARM branch to the entry point
+    Entry,
+}
+
+impl fmt::Display for HeaderIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HeaderIssue::Checksum { expected, found } => {
+                write!(
+                    f,
+                    "Header checksum is {found:02X}h, expected {expected:02X}h"
+                )
+            }
+            HeaderIssue::Size(size) => write!(f, "Non-standard ROM size of {size} bytes"),
+            HeaderIssue::Logo => write!(f, "Nintendo logo is missing"),
+            HeaderIssue::FixedValue(val) => write!(f, "Fixed value is {val:02X}h, expected 96h"),
+            HeaderIssue::Entry => write!(f, "No branch to the entry point"),
+        }
+    }
+}
+
+/// Cartridge header at 0x08000000-0x080000BF
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RomHeader {
+    /// Target of the branch at 0x08000000, None if the ROM does not start with one
+    pub entry: Option<u32>,
+    pub title: String,
+    pub game_code: String,
+    pub maker_code: String,
+    pub version: u8,
+    pub checksum: u8,
+}
+
+impl RomHeader {
+    /// Parses the header of `rom`, None if the ROM is too small to have one
+    pub fn parse(rom: &[u8]) -> Option<Self> {
+        if rom.len() < HEADER_SIZE {
+            return None;
+        }
+
+        // Fields are padded with zeros
+        let text = |range: std::ops::Range<usize>| {
+            rom[range]
+                .iter()
+                .take_while(|byte| **byte != 0)
+                .map(|byte| match byte.is_ascii_graphic() || *byte == b' ' {
+                    true => *byte as char,
+                    false => '?',
+                })
+                .collect::<String>()
+        };
+
+        let branch = u32::from_le_bytes(rom[0..4].try_into().unwrap());
+        let entry = match branch & 0xFF000000 {
+            // b <offset>, relative to PC+8
+            0xEA000000 => {
+                let offset = (((branch & 0xFFFFFF) << 8) as i32) >> 6;
+                Some(0x08000008u32.wrapping_add(offset as u32))
+            }
+            _ => None,
+        };
+
+        Some(Self {
+            entry,
+            title: text(0xA0..0xAC),
+            game_code: text(0xAC..0xB0),
+            maker_code: text(0xB0..0xB2),
+            version: rom[0xBC],
+            checksum: rom[0xBD],
+        })
+    }
+
+    /// Complement check of the header bytes 0xA0-0xBC, as computed by the BIOS
+    pub fn compute_checksum(rom: &[u8]) -> u8 {
+        rom[0xA0..0xBD]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_sub(*byte))
+            .wrapping_sub(0x19)
+    }
+
+    /// Reports the problems of `rom` a real GBA or flash cartridge would stumble over
+    pub fn validate(&self, rom: &[u8]) -> Vec<HeaderIssue> {
+        let mut issues = Vec::new();
+
+        if self.entry.is_none() {
+            issues.push(HeaderIssue::Entry);
+        }
+
+        if rom[0x04..0xA0] != NINTENDO_LOGO {
+            issues.push(HeaderIssue::Logo);
+        }
+
+        if rom[0xB2] != FIXED_VALUE {
+            issues.push(HeaderIssue::FixedValue(rom[0xB2]));
+        }
+
+        let expected = Self::compute_checksum(rom);
+        if self.checksum != expected {
+            issues.push(HeaderIssue::Checksum {
+                expected,
+                found: self.checksum,
+            });
+        }
+
+        if rom.len() > ROM_MAX_SIZE || !rom.len().is_power_of_two() {
+            issues.push(HeaderIssue::Size(rom.len()));
+        }
+
+        issues
+    }
+}
+
+impl fmt::Display for RomHeader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} [{}] v{}",
+            self.title.trim_end(),
+            self.game_code,
+            self.version
+        )
+    }
+}
+
+/// Finds the `ids` in `rom` in a single pass, in the order they first appear
+/// The ID strings the Nintendo libraries leave in the ROM are word aligned
+pub fn find_id_strings<'a>(rom: &[u8], ids: &[&'a [u8]]) -> Vec<&'a [u8]> {
+    let mut found = Vec::new();
+
+    for offset in (0..rom.len()).step_by(4) {
+        for id in ids {
+            if rom[offset..].starts_with(id) && !found.contains(id) {
+                found.push(*id);
+            }
+        }
+    }
+
+    found
+}
+
+/// ID strings of `rom` used by the backup and peripheral detection
+pub fn library_id_strings(rom: &[u8]) -> Vec<&'static [u8]> {
+    let ids = backup::BACKUP_ID_STRINGS
+        .iter()
+        .map(|(id, _)| *id)
+        .chain(gpio::PERIPHERALS_ID_STRINGS.iter().copied())
+        .collect::<Vec<_>>();
+
+    find_id_strings(rom, &ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::CPU;
+
+    #[test]
+    fn id_strings() {
+        let mut rom = vec![0; 0x400];
+        rom[0x200..0x20B].copy_from_slice(b"FLASH1M_V10");
+        rom[0x100..0x108].copy_from_slice(b"SIIRTC_V");
+        rom[0x300..0x308].copy_from_slice(b"SIIRTC_V");
+        rom[0x182..0x18A].copy_from_slice(b"EEPROM_V");
+
+        // Found once, in ROM order, unaligned strings are skipped
+        let found = library_id_strings(&rom);
+        assert_eq!(found, [b"SIIRTC_V".as_slice(), b"FLASH1M_V"]);
+        assert_eq!(find_id_strings(&rom, &[b"EEPROM_V"]), Vec::<&[u8]>::new());
+    }
+
+    #[test]
+    fn rom_header() {
+        let mut rom = vec![0; 0x400];
+        rom[0..4].copy_from_slice(&0xEA00002Eu32.to_le_bytes()); // b 080000C0h
+        rom[0x04..0xA0].copy_from_slice(&NINTENDO_LOGO);
+        rom[0xA0..0xA9].copy_from_slice(b"TEST GAME");
+        rom[0xAC..0xB0].copy_from_slice(b"ATGE");
+        rom[0xB0..0xB2].copy_from_slice(b"01");
+        rom[0xB2] = 0x96;
+        rom[0xBC] = 2;
+        rom[0xBD] = RomHeader::compute_checksum(&rom);
+
+        let mut cpu = CPU::new();
+        cpu.load_rom(&rom);
+
+        let header = cpu.header.clone().unwrap();
+        assert_eq!(header.entry, Some(0x080000C0));
+        assert_eq!(header.title, "TEST GAME");
+        assert_eq!(header.game_code, "ATGE");
+        assert_eq!(header.maker_code, "01");
+        assert_eq!(header.version, 2);
+        assert_eq!(header.to_string(), "TEST GAME [ATGE] v2");
+        assert!(header.validate(&rom).is_empty());
+
+        // A changed title breaks the checksum, the logo and size are checked as well
+        rom[0xA0] = b'B';
+        rom[0x10] ^= 0xFF;
+        rom.push(0);
+        let header = RomHeader::parse(&rom).unwrap();
+        assert_eq!(
+            header.validate(&rom),
+            [
+                HeaderIssue::Logo,
+                HeaderIssue::Checksum {
+                    expected: header.checksum.wrapping_add(b'T' - b'B'),
+                    found: header.checksum,
+                },
+                HeaderIssue::Size(0x401),
+            ]
+        );
+
+        assert_eq!(RomHeader::parse(&[0xFE, 0xFF, 0xFF, 0xEA]), None);
+    }
+}
@@ -22,15 +22,56 @@ impl Keypad {
         }
     }
 
-    pub fn press(&mut self, buttons: u16) {
+    /// Presses `buttons`, returns whether the KEYCNT interrupt condition is met
+    pub fn press(&mut self, buttons: u16) -> bool {
         self.keyinput &= !(buttons);
+        self.irq_condition()
     }
 
-    pub fn release(&mut self, buttons: u16) {
+    /// Releases `buttons`, returns whether the KEYCNT interrupt condition is met
+    pub fn release(&mut self, buttons: u16) -> bool {
         self.keyinput |= buttons;
+        self.irq_condition()
+    }
+
+    pub fn read_u16(&self, addr: u32) -> u16 {
+        match addr {
+            0x130 => self.keyinput & 0x3FF,
+            0x132 => self.keycnt,
+            _ => panic!("Addr out of range for Keypad `{:04X}`", addr),
+        }
+    }
+
+    /// KEYINPUT is read-only, KEYCNT only has bits 0-9, 14 and 15
+    pub fn write_u16(&mut self, addr: u32, val: u16, mask: u16) {
+        match addr {
+            0x130 => {}
+            0x132 => {
+                let mask = mask & 0xC3FF;
+                self.keycnt = (self.keycnt & !mask) | (val & mask);
+            }
+            _ => panic!("Addr out of range for Keypad `{:04X}`", addr),
+        }
     }
 
     pub fn is_irq_enabled(&self) -> bool {
         (self.keycnt & 0x4000) != 0
     }
+
+    /// KEYCNT bits 0-9 select the buttons, bit 15 selects the condition:
+    ///     0=OR, any of the selected buttons pressed
+    ///     1=AND, all of the selected buttons pressed
+    pub fn irq_condition(&self) -> bool {
+        if !self.is_irq_enabled() {
+            return false;
+        }
+
+        let select = self.keycnt & 0x3FF;
+        let pressed = !self.keyinput & select;
+
+        match (self.keycnt & 0x8000) != 0 {
+            false => pressed != 0,
+            true => select != 0 && pressed == select,
+        }
+    }
 }
@@ -26,6 +26,41 @@ impl LCD {
         self.registers[addr] = (val & 0xFF) as u8;
     }
 
+    /// Reads LCD IO register as seen by the CPU
+    /// Write-only registers read back as 0
+    pub fn read_u16(&self, addr: u32) -> u16 {
+        match addr {
+            0x00..=0x0E | 0x48 | 0x4A | 0x50 | 0x52 => self.get_u16(addr),
+            // BGxHOFS/VOFS, BG2/3 affine, WINxH/V, MOSAIC, BLDY
+            _ => 0,
+        }
+    }
+
+    /// Writes LCD IO register from the CPU, only bits in `mask` are written
+    /// Read-only bits (DISPSTAT flags, VCOUNT) are preserved
+    pub fn write_u16(&mut self, addr: u32, val: u16, mask: u16) {
+        let write_mask = match addr {
+            0x00 => 0xFFF7,
+            0x02 => 0x0001,
+            0x04 => 0xFF38,
+            0x06 => 0x0000,
+            0x08 | 0x0A => 0xDFFF,
+            0x0C | 0x0E => 0xFFFF,
+            0x10..=0x1E => 0x01FF,
+            0x20..=0x3E => 0xFFFF,
+            0x40..=0x46 => 0xFFFF,
+            0x48 | 0x4A => 0x3F3F,
+            0x4C => 0xFFFF,
+            0x50 => 0x3FFF,
+            0x52 => 0x1F1F,
+            0x54 => 0x001F,
+            _ => return,
+        };
+
+        let mask = mask & write_mask;
+        self.set_u16(addr, (self.get_u16(addr) & !mask) | (val & mask));
+    }
+
     pub fn get_dispcnt(&self) -> u16 {
         self.get_u16(0)
     }
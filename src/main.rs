@@ -2,32 +2,46 @@ use std::backtrace::Backtrace;
 use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::process::{exit, Command};
-use std::sync::{mpsc, Arc, Mutex};
-use std::time::Instant;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
 use std::{panic, thread};
 
-use cpu::MMU;
 use debugger::Debugger;
 use log::warn;
 
 use crate::backtrace::print_cpu_backtrace;
 use crate::backtrace::PC_BACKTRACE;
+use crate::backup::BackupType;
 use crate::cpu::CPU;
 use crate::debugger::{Breakpoint, DebuggerEvent};
 use crate::game_window::{Dump, GameWindow, WindowEvent};
-use crate::renderer::{BackgroundMessage, RenderMessage};
+use crate::gpio::{Peripherals, RtcClock};
+use crate::header::{library_id_strings, RomHeader};
+use crate::renderer::{BackgroundMessage, RenderMessage, VideoMemory};
 
+mod archive;
 mod backtrace;
+mod backup;
+mod bios;
 mod cpu;
 mod debugger;
+mod decode;
 mod disassembler;
 mod game_window;
+mod gpio;
+mod header;
 mod keypad;
 mod lcd;
 mod renderer;
+mod scheduler;
 mod serial;
 mod sound;
+mod timer;
+
+/// Interval between flushes of a modified battery backup to the `.sav` file
+const SAVE_INTERVAL: Duration = Duration::from_secs(5);
 
 fn main() {
     panic::set_hook(Box::new(|panic_info| {
@@ -38,57 +52,74 @@ fn main() {
         print_cpu_backtrace();
     }));
 
-    let vram = Arc::new(Mutex::new(vec![0; 96 * 1024]));
-    let palette = Arc::new(Mutex::new(vec![0; 1 * 1024]));
-    let oam = Arc::new(Mutex::new(vec![0; 1 * 1024]));
+    let mut cpu = CPU::new();
+
+    // Run the BIOS boot logo and header check instead of starting the ROM directly
+    let boot_bios = std::env::args().any(|arg| arg == "--boot-bios");
+    match boot_bios {
+        false => cpu.reset(),
+        true => cpu.reset_bios(),
+    }
+
+    // Accuracy testing: execute idle loops instead of skipping to the next event
+    if std::env::args().any(|arg| arg == "--no-idle-skip") {
+        cpu.idle_loop_skip = false;
+    }
+
+    // Run pre-decoded basic blocks instead of single instructions
+    if std::env::args().any(|arg| arg == "--block-cache") {
+        cpu.block_cache = true;
+    }
 
-    let mut cpu = CPU::new(&vram, &palette, &oam);
-    cpu.reset();
-    cpu.load_bios(&std::fs::read("bios/gba_bios.bin").unwrap());
+    // Without a BIOS dump the built-in replacement BIOS is used
+    match std::fs::read("bios/gba_bios.bin") {
+        Ok(bios) if !std::env::args().any(|arg| arg == "--builtin-bios") => cpu.load_bios(&bios),
+        _ => warn!("Using the built-in replacement BIOS"),
+    }
 
     /* Halt ROM */
     //let rom: Vec<u8> = vec![0xFE, 0xFF, 0xFF, 0xEA];
 
     /* Games */
     // Implement CpuSet::fill halfword
-    //let rom = std::fs::read("roms/pokemon_emerald.gba").unwrap();
-    //let rom = std::fs::read("roms/super_mario_advance2.gba").unwrap();
-    //let rom = std::fs::read("roms/super_mario_advance4.gba").unwrap();
-    //let rom = std::fs::read("roms/mario_kart_super_circuit.gba").unwrap();
+    //let rom_path = "roms/pokemon_emerald.gba";
+    //let rom_path = "roms/super_mario_advance2.gba";
+    //let rom_path = "roms/super_mario_advance4.gba";
+    //let rom_path = "roms/mario_kart_super_circuit.gba";
 
     // Super Dodgeball Advance:
     //      - Next step: Get past title screen :D
     //      - ATLUS should fade-in
-    let rom = std::fs::read("roms/super_dodgeball_advance.gba").unwrap();
+    let rom_path = "roms/super_dodgeball_advance.gba";
 
     /* Test ROMs */
-    //let rom = std::fs::read("roms/rgb_test.gba").unwrap();
-    //let rom = std::fs::read("roms/CPUTest.gba").unwrap();
+    //let rom_path = "roms/rgb_test.gba";
+    //let rom_path = "roms/CPUTest.gba";
 
-    //let rom = std::fs::read("../gba_suite/deploy/suite.gba").unwrap();
+    //let rom_path = "../gba_suite/deploy/suite.gba";
 
     // SWI_VSYNC:
     //      obj_mode=11
-    //let rom = std::fs::read("roms/tonc/swi_vsync.gba").unwrap();
+    //let rom_path = "roms/tonc/swi_vsync.gba";
 
     // BIG_MAP:
-    //let rom = std::fs::read("roms/tonc/bigmap.gba").unwrap();
+    //let rom_path = "roms/tonc/bigmap.gba";
 
     // BLD_DEMO:
     //      Requires GFX_MODE=01 for sprites
-    //let rom = std::fs::read("roms/tonc/bld_demo.gba").unwrap();
+    //let rom_path = "roms/tonc/bld_demo.gba";
 
     // DMA_DEMO:
     //      - 'not yet implemented: Check HBlank DMA start timing => DMA3', src/cpu.rs:1037:25
-    //let rom = std::fs::read("roms/tonc/dma_demo.gba").unwrap();
+    //let rom_path = "roms/tonc/dma_demo.gba";
 
-    //let rom = std::fs::read("roms/tonc/swi_demo.gba").unwrap();
+    //let rom_path = "roms/tonc/swi_demo.gba";
 
-    //let rom = std::fs::read("roms/tonc/tmr_demo.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/txt_obj.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/txt_se1.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/txt_se2.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/win_demo.gba").unwrap();
+    //let rom_path = "roms/tonc/tmr_demo.gba";
+    //let rom_path = "roms/tonc/txt_obj.gba";
+    //let rom_path = "roms/tonc/txt_se1.gba";
+    //let rom_path = "roms/tonc/txt_se2.gba";
+    //let rom_path = "roms/tonc/win_demo.gba";
 
     /* TONC */
     // IRQ_DEMO:
@@ -99,31 +130,98 @@ fn main() {
     //          Should switch priorities of HBlank and VCount
     //      - No rendering of interrupts
     //      - Wrong rendering of text
-    //let rom = std::fs::read("roms/tonc/irq_demo.gba").unwrap();
+    //let rom_path = "roms/tonc/irq_demo.gba";
 
     // TTE_DEMO:
     //      - Requires SWI 15h
-    //let rom = std::fs::read("roms/tonc/tte_demo.gba").unwrap();
+    //let rom_path = "roms/tonc/tte_demo.gba";
 
     // TXT_BM:
-    //let rom = std::fs::read("roms/tonc/txt_bm.gba").unwrap();
+    //let rom_path = "roms/tonc/txt_bm.gba";
 
     // SBB_REG:
     //      - Sprite being rendered
     //          Apparently sprites are turned on in DISPCNT
     //          and OAM is not cleared, which means
     //          that every sprite will be rendered.
-    //let rom = std::fs::read("roms/tonc/sbb_reg.gba").unwrap();
+    //let rom_path = "roms/tonc/sbb_reg.gba";
 
     /* Working ROMs */
-    //let rom = std::fs::read("roms/tonc/key_demo.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/pageflip.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/bm_modes.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/m3_demo.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/brin_demo.gba").unwrap();
-    //let rom = std::fs::read("roms/tonc/obj_demo.gba").unwrap();
+    //let rom_path = "roms/tonc/key_demo.gba";
+    //let rom_path = "roms/tonc/pageflip.gba";
+    //let rom_path = "roms/tonc/bm_modes.gba";
+    //let rom_path = "roms/tonc/m3_demo.gba";
+    //let rom_path = "roms/tonc/brin_demo.gba";
+    //let rom_path = "roms/tonc/obj_demo.gba";
+
+    // A ROM given on the command line replaces the one selected above
+    let rom_path = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(rom_path));
+
+    // ROMs may be stored in zip, gzip or 7z archives, e.g. `--entry=game.gba` picks the file
+    let rom_entry = std::env::args().find_map(|arg| arg.strip_prefix("--entry=").map(String::from));
+    let rom = match archive::read_rom(&rom_path, rom_entry.as_deref()) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Could not load `{}`: {e}", rom_path.display());
+            exit(1);
+        }
+    };
+
+    // Only print the ROM header and its validation report
+    if std::env::args().any(|arg| arg == "--info") {
+        print_rom_info(&rom);
+        exit(0);
+    }
+
+    // Backup chip of the cartridge, e.g. `--backup=flash128`, detected from the ROM otherwise
+    let backup = std::env::args().find_map(|arg| arg.strip_prefix("--backup=").map(String::from));
+    if let Some(name) = backup {
+        match BackupType::parse(&name) {
+            Some(kind) => cpu.backup_override = Some(kind),
+            None => warn!("Unknown backup type `{name}`"),
+        }
+    }
+
+    // Cartridge RTC, detected from the ROM unless forced with `--rtc`
+    if std::env::args().any(|arg| arg == "--rtc") {
+        cpu.peripherals_override = Some(Peripherals {
+            rtc: true,
+            ..Peripherals::default()
+        });
+    }
+
+    // Light level of the solar sensor from 0 (dark) to 255, e.g. `--light-level=128`
+    let light_level =
+        std::env::args().find_map(|arg| arg.strip_prefix("--light-level=").map(String::from));
+    if let Some(level) = light_level {
+        match level.parse() {
+            Ok(level) => cpu.light_level = level,
+            Err(_) => warn!("Invalid light level `{level}`"),
+        }
+    }
+
+    // Deterministic RTC time in seconds since the Unix epoch, e.g. `--rtc-time=1095811200`
+    let rtc_time =
+        std::env::args().find_map(|arg| arg.strip_prefix("--rtc-time=").map(String::from));
+    if let Some(time) = rtc_time {
+        match time.parse() {
+            Ok(time) => cpu.rtc_clock = RtcClock::Fixed(time),
+            Err(_) => warn!("Invalid RTC time `{time}`"),
+        }
+    }
+
     cpu.load_rom(&rom.clone());
 
+    // Battery backup is kept in a `.sav` next to the ROM, named after the archive if any
+    let save_path = rom_path.with_extension("sav");
+    if let Ok(save) = std::fs::read(&save_path) {
+        cpu.backup.load(&save);
+    }
+
     let mut dbg = Debugger::new();
     Debugger::set_panic_hook();
     dbg.breakpoints = HashMap::from([
@@ -135,29 +233,31 @@ fn main() {
     ]);
 
     let (win_tx, win_rx) = mpsc::channel();
-    let (game_tx, game_rx) = mpsc::channel();
+    let (game_tx, game_rx) = mpsc::channel::<(RenderMessage, VideoMemory)>();
+    let (rumble_tx, rumble_rx) = mpsc::channel::<bool>();
 
-    let window_handle = thread::spawn(move || {
-        let mut window = GameWindow::new();
+    let title = match &cpu.header {
+        Some(header) => format!("pGBA - {header}"),
+        None => String::from("pGBA"),
+    };
 
-        let vram = Arc::clone(&vram);
-        let palette = Arc::clone(&palette);
+    let window_handle = thread::spawn(move || {
+        let mut window = GameWindow::new(&title);
 
         loop {
             if let Some(events) = window.update() {
                 win_tx.send(events).unwrap();
             }
 
-            if let Ok(mut msg) = game_rx.try_recv() {
+            if let Ok((mut msg, memory)) = game_rx.try_recv() {
                 if !window.paused {
-                    window.draw(
-                        &mut msg,
-                        &vram.lock().unwrap(),
-                        &palette.lock().unwrap(),
-                        &oam.lock().unwrap(),
-                    );
+                    window.draw(&mut msg, &memory.vram, &memory.palette, &memory.oam);
                 }
             }
+
+            if let Ok(on) = rumble_rx.try_recv() {
+                window.set_rumble(on);
+            }
         }
     });
 
@@ -169,45 +269,14 @@ fn main() {
     //dbg.paused = true;
     //dbg.lockstep = true;
 
-    let mut timer_scanline: usize = 0;
-    let mut dt_cycles: usize = 0;
-    let mut lcd_pause = false;
     let mut previous_pc = 0x08000000;
+    let mut last_save = Instant::now();
 
     'running: loop {
-        let is_thumb = cpu.is_thumb();
-        let program_counter = cpu.get_program_counter();
-        let opcode: u32 = if !cpu.addr_valid(program_counter) {
-            if !cpu.panic {
-                warn!("Panicked! PC at invalid address `{:08X}`", program_counter);
-                cpu.panic = true;
-            }
-            dbg.opcode
-        } else {
-            match is_thumb {
-                true => {
-                    let word = cpu.read_u32(false, program_counter & 0xFFFFFFFE);
-                    let upper = (program_counter & 0x1) == 0x1;
-                    match upper {
-                        false => word & 0xFFFF,
-                        true => (word >> 16) & 0xFFFF,
-                    }
-                }
-                false => cpu.read_u32(false, program_counter),
-            }
-        };
-
-        dbg.opcode = opcode;
-
-        #[cfg(feature = "debugger")]
-        if !cpu.panic && dbg.should_break(program_counter) {
-            warn!("Breakpoint hit at `{:08X}`", program_counter);
-            dbg.free_run = false;
-            dbg.paused = true;
-            dbg.lockstep = true;
-        }
-
         if cpu.panic || dbg.lockstep || !dbg.free_run {
+            if let Some(opcode) = cpu.fetch_opcode() {
+                dbg.opcode = opcode;
+            }
             dbg.draw(&mut cpu);
         }
 
@@ -217,7 +286,10 @@ fn main() {
             DebuggerEvent::Reset => {
                 warn!("CPU Reset");
                 cpu.load_rom(&rom.clone());
-                cpu.reset();
+                match boot_bios {
+                    false => cpu.reset(),
+                    true => cpu.reset_bios(),
+                }
             }
             DebuggerEvent::Back => {
                 warn!("Jumping 1 instruction back");
@@ -230,24 +302,29 @@ fn main() {
                 match event {
                     WindowEvent::Quit => break 'running,
                     WindowEvent::ButtonPress(button) => {
-                        cpu.keypad.press(button);
+                        cpu.keypad_press(button);
                         warn!(
                             "Press 0x{button:X}, buttons:{:010b}",
                             cpu.keypad.keyinput & 0x3FF
                         );
                     }
                     WindowEvent::ButtonRelease(button) => {
-                        cpu.keypad.release(button);
+                        cpu.keypad_release(button);
                         warn!(
                             "Release 0x{button:X}, buttons:{:010b}",
                             cpu.keypad.keyinput & 0x3FF
                         );
                     }
-                    WindowEvent::Pause(paused) => lcd_pause = paused,
-                    WindowEvent::NextVCount => timer_scanline = 1232,
+                    WindowEvent::Pause(paused) => cpu.lcd_paused = paused,
+                    WindowEvent::Tilt(x, y) => {
+                        if let Some(tilt) = &mut cpu.tilt {
+                            tilt.set(x, y);
+                        }
+                    }
+                    WindowEvent::NextVCount => cpu.lcd_next_scanline(),
                     WindowEvent::ForceRender => {
-                        timer_scanline = 1232;
                         cpu.lcd.set_vcount(227);
+                        cpu.lcd_next_scanline();
                     }
                     WindowEvent::Debug(1) => {
                         dbg.free_run = false;
@@ -272,8 +349,8 @@ fn main() {
                     }
                     WindowEvent::Dump(d) => {
                         let (file, data) = match d {
-                            Dump::Video => ("vram", cpu.ram_video.lock().unwrap()),
-                            Dump::Palette => ("pal", cpu.ram_palette.lock().unwrap()),
+                            Dump::Video => ("vram", &cpu.ram_video),
+                            Dump::Palette => ("pal", &cpu.ram_palette),
                             _ => todo!("Implement dump {d:#?}"),
                         };
 
@@ -282,7 +359,7 @@ fn main() {
                         // Dump to path
                         let path = format!("dump/{file}");
                         let mut file = File::create(&path).expect("Could not create `{path}`");
-                        file.write_all(&data).expect("Failed to write to `{path}`");
+                        file.write_all(data).expect("Failed to write to `{path}`");
 
                         // Create hex file
                         Command::new("sh")
@@ -299,131 +376,123 @@ fn main() {
         }
 
         if !cpu.panic && (!dbg.paused || dbg.free_run) {
-            if let Some(num) = cpu.dma_check() {
-                cpu.dma_run(num);
-            }
+            // Blocks bypass the per-instruction breakpoints and backtrace
+            // Breakpoints are only checked with the debugger feature
+            let use_blocks = cpu.block_cache
+                && dbg.free_run
+                && (!cfg!(feature = "debugger") || dbg.breakpoints.is_empty())
+                && !cfg!(feature = "backtrace");
+            let single_step = !dbg.free_run;
+
+            // Run the CPU until the next scheduled event
+            dbg.instruction_counter +=
+                cpu.run_until_event(use_blocks, single_step, |_cpu, program_counter, opcode| {
+                    dbg.opcode = opcode;
+
+                    #[cfg(feature = "debugger")]
+                    if dbg.should_break(program_counter) {
+                        warn!("Breakpoint hit at `{:08X}`", program_counter);
+                        dbg.free_run = false;
+                        dbg.paused = true;
+                        dbg.lockstep = true;
+                        return false;
+                    }
 
-            if !cpu.halt || (cpu.halt && cpu.get_mode() == cpu::MODE_IRQ) {
-                #[cfg(feature = "backtrace")]
-                {
-                    let (asm, _) = match is_thumb {
-                        false => disassembler::disassemble_arm(opcode, program_counter),
-                        true => disassembler::disassemble_thumb(opcode as u16),
-                    };
-                    #[cfg(feature = "full-backtrace")]
-                    let asm_reg = backtrace::replace_registers_in_string(&cpu, &asm);
-
-                    #[cfg(not(feature = "full-backtrace"))]
-                    let asm_reg = String::from("full-backtrace disabled");
-
-                    // Unsafe due to static mut PC_BACKTRACE
-                    unsafe {
-                        if PC_BACKTRACE.len() == 32 {
-                            PC_BACKTRACE.pop_back();
+                    #[cfg(feature = "backtrace")]
+                    {
+                        let is_thumb = _cpu.is_thumb();
+                        let (asm, _) = match is_thumb {
+                            false => disassembler::disassemble_arm(opcode, program_counter),
+                            true => disassembler::disassemble_thumb(opcode as u16),
+                        };
+                        #[cfg(feature = "full-backtrace")]
+                        let asm_reg = backtrace::replace_registers_in_string(_cpu, &asm);
+
+                        #[cfg(not(feature = "full-backtrace"))]
+                        let asm_reg = String::from("full-backtrace disabled");
+
+                        // Unsafe due to static mut PC_BACKTRACE
+                        unsafe {
+                            if PC_BACKTRACE.len() == 32 {
+                                PC_BACKTRACE.pop_back();
+                            }
+
+                            PC_BACKTRACE.push_front((
+                                program_counter,
+                                opcode,
+                                is_thumb,
+                                asm,
+                                asm_reg,
+                            ));
                         }
-
-                        PC_BACKTRACE.push_front((program_counter, opcode, is_thumb, asm, asm_reg));
                     }
-                }
-
-                previous_pc = program_counter;
-
-                let cycles = cpu.cycle_count;
-                cpu.execute(opcode);
 
-                dt_cycles = cpu.cycle_count - cycles;
+                    previous_pc = program_counter;
+                    true
+                });
 
-                dbg.instruction_counter += 1;
-            } else {
-                cpu.cycle_count += 1;
-                warn!("CPU Halted");
+            // LCD is off in Stop mode, wait for a Keypad/Serial/Game Pak IRQ
+            if cpu.stop {
+                thread::sleep(std::time::Duration::from_millis(1));
             }
 
             if !dbg.free_run {
                 dbg.paused = true;
             }
-        }
 
-        // Hdraw => 960
-        // HBlank => 272
-        // scanline => 1232
-        // Vdraw => 160*scanline => 197120
-        // VBlank => 68*scanline => 83776
-        // refresh => Vdraw+VBlank => 280896
+            if cpu.run_events() {
+                // Rumble is sent to the game controller as the motor switches
+                if let Some(on) = cpu.gpio.take_rumble() {
+                    rumble_tx.send(on).unwrap();
+                }
 
-        //frame_timer += dt;
-        if !lcd_pause {
-            timer_scanline += dt_cycles;
-        }
+                // Flush the backup once per interval while the game writes to it
+                if cpu.backup.dirty && last_save.elapsed() >= SAVE_INTERVAL {
+                    save_backup(&mut cpu, &save_path);
+                    last_save = Instant::now();
+                }
 
-        if timer_scanline >= 1232 {
-            timer_scanline -= 1232;
-            let vcount = cpu.lcd.increment_vcount();
+                let memory = VideoMemory {
+                    vram: cpu.ram_video.clone(),
+                    palette: cpu.ram_palette.clone(),
+                    oam: cpu.ram_obj_attr.clone(),
+                };
 
-            if vcount == 0 {
                 game_tx
-                    .send(RenderMessage {
-                        dispcnt: cpu.lcd.get_dispcnt(),
-                        frame: cpu.lcd.get_dispcnt_frame(),
-                        backgrounds: [
-                            BackgroundMessage {
-                                control: cpu.lcd.get_background_control(0),
-                                offset: cpu.lcd.get_background_offset(0),
-                                width: 0,
-                                height: 0,
-                            },
-                            BackgroundMessage {
-                                control: cpu.lcd.get_background_control(1),
-                                offset: cpu.lcd.get_background_offset(1),
-                                width: 0,
-                                height: 0,
-                            },
-                            BackgroundMessage {
-                                control: cpu.lcd.get_background_control(2),
-                                offset: cpu.lcd.get_background_offset(2),
-                                width: 0,
-                                height: 0,
-                            },
-                            BackgroundMessage {
-                                control: cpu.lcd.get_background_control(3),
-                                offset: cpu.lcd.get_background_offset(3),
-                                width: 0,
-                                height: 0,
-                            },
-                        ],
-                    })
+                    .send((
+                        RenderMessage {
+                            dispcnt: cpu.lcd.get_dispcnt(),
+                            frame: cpu.lcd.get_dispcnt_frame(),
+                            backgrounds: [
+                                BackgroundMessage {
+                                    control: cpu.lcd.get_background_control(0),
+                                    offset: cpu.lcd.get_background_offset(0),
+                                    width: 0,
+                                    height: 0,
+                                },
+                                BackgroundMessage {
+                                    control: cpu.lcd.get_background_control(1),
+                                    offset: cpu.lcd.get_background_offset(1),
+                                    width: 0,
+                                    height: 0,
+                                },
+                                BackgroundMessage {
+                                    control: cpu.lcd.get_background_control(2),
+                                    offset: cpu.lcd.get_background_offset(2),
+                                    width: 0,
+                                    height: 0,
+                                },
+                                BackgroundMessage {
+                                    control: cpu.lcd.get_background_control(3),
+                                    offset: cpu.lcd.get_background_offset(3),
+                                    width: 0,
+                                    height: 0,
+                                },
+                            ],
+                        },
+                        memory,
+                    ))
                     .unwrap();
-            } else if vcount == 160 {
-                if dbg.free_run && cpu.can_irq_trigger(cpu::IRQ_VBLANK) {
-                    //dbg.lockstep = true;
-                    //dbg.paused = true;
-                    //dbg.free_run = false;
-                    warn!("VBLANK IRQ Triggered");
-
-                    cpu.trigger_irq(cpu::IRQ_VBLANK);
-                }
-            }
-
-            if dbg.free_run
-                && cpu.lcd.get_dispstat_vcount_flag()
-                && cpu.can_irq_trigger(cpu::IRQ_VCOUNT)
-            {
-                warn!("VCount IRQ Triggered");
-
-                cpu.trigger_irq(cpu::IRQ_VCOUNT);
-            }
-        }
-
-        //  Although the drawing time is only 960 cycles (240*4),
-        //  the H-Blank flag is "0" for a total of 1006 cycles. (GBATEK)
-        if timer_scanline <= 1006 {
-            cpu.lcd.set_dispstat_hblank(false); // Hdraw
-        } else {
-            cpu.lcd.set_dispstat_hblank(true); // Hblank
-            if dbg.free_run && cpu.can_irq_trigger(cpu::IRQ_HBLANK) {
-                warn!("HBlank IRQ Triggered");
-
-                cpu.trigger_irq(cpu::IRQ_HBLANK);
             }
         }
 
@@ -434,7 +503,58 @@ fn main() {
 
     let end = Instant::now();
 
+    if cpu.backup.dirty {
+        save_backup(&mut cpu, &save_path);
+    }
+
     dbg.exit();
     let cps = (cpu.cycle_count as f64) / (end.duration_since(start).as_secs_f64());
     println!("{cps:.0} CPS, {:.3} MHz", cps / 1000000.0);
 }
+
+/// Writes the battery backup to `path`
+fn save_backup(cpu: &mut CPU, path: &Path) {
+    match cpu.backup.save(path) {
+        Ok(()) => warn!("Saved backup to `{}`", path.display()),
+        Err(e) => warn!("Could not save backup to `{}`: {e}", path.display()),
+    }
+}
+
+/// Prints the header of `rom`, the detected cartridge hardware and the header problems
+fn print_rom_info(rom: &[u8]) {
+    let header = match RomHeader::parse(rom) {
+        Some(header) => header,
+        None => {
+            println!("ROM is too small to have a header ({} bytes)", rom.len());
+            return;
+        }
+    };
+
+    println!("Title:       {}", header.title);
+    println!("Game code:   {}", header.game_code);
+    println!("Maker code:  {}", header.maker_code);
+    println!("Version:     {}", header.version);
+    match header.entry {
+        Some(entry) => println!("Entry point: {entry:08X}h"),
+        None => println!("Entry point: -"),
+    }
+    println!("Checksum:    {:02X}h", header.checksum);
+    println!("Size:        {} bytes", rom.len());
+    let id_strings = library_id_strings(rom);
+    let game_code = Some(header.game_code.as_str());
+    println!("Backup:      {:?}", backup::detect(game_code, &id_strings));
+    println!(
+        "Peripherals: {:?}",
+        Peripherals::detect(game_code, &id_strings)
+    );
+
+    let issues = header.validate(rom);
+    match issues.is_empty() {
+        true => println!("Header is valid"),
+        false => {
+            for issue in issues {
+                println!("Warning:     {issue}");
+            }
+        }
+    }
+}
@@ -6,6 +6,14 @@ use sdl2::{
     video::Window,
 };
 
+/// Copy of VRAM, palette and OAM taken at the frame boundary, so the CPU
+/// owns the video memories without locking
+pub struct VideoMemory {
+    pub vram: Vec<u8>,
+    pub palette: Vec<u8>,
+    pub oam: Vec<u8>,
+}
+
 #[derive(Debug)]
 pub struct RenderMessage {
     pub dispcnt: u16,
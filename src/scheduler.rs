@@ -0,0 +1,96 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Hdraw => 960
+// HBlank => 272
+// scanline => 1232
+// Vdraw => 160*scanline => 197120
+// VBlank => 68*scanline => 83776
+// refresh => Vdraw+VBlank => 280896
+
+/// Cycles of a full scanline (HDraw + HBlank)
+pub const CYCLES_SCANLINE: usize = 1232;
+
+/// Although the drawing time is only 960 cycles (240*4),
+/// the H-Blank flag is "0" for a total of 1006 cycles. (GBATEK)
+pub const CYCLES_HDRAW: usize = 1006;
+
+/// Cycles between two samples of the sound output at 32768 Hz
+pub const CYCLES_SAMPLE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    /// H-Blank flag goes high, starts HBlank DMAs
+    HBlank,
+    /// End of scanline, VCount is incremented
+    Scanline,
+    /// Timer `n` overflows (non count-up timers only)
+    TimerOverflow(u8),
+    /// Immediate DMA `n` start, 2 cycles after enable
+    Dma(u8),
+    /// Serial transfer with internal clock done
+    Serial,
+    /// The sound output is sampled, Direct Sound and the FIFOs are mixed in here
+    AudioSample,
+}
+
+/// Cycle-timestamped min-heap of pending events
+/// Events with equal timestamps are handled in the order they were scheduled
+pub struct Scheduler {
+    events: BinaryHeap<Reverse<(usize, u64, Event)>>,
+    sequence: u64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            events: BinaryHeap::new(),
+            sequence: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.events.clear();
+        self.sequence = 0;
+    }
+
+    /// Schedules `event` at cycle `timestamp`
+    pub fn schedule(&mut self, timestamp: usize, event: Event) {
+        self.events.push(Reverse((timestamp, self.sequence, event)));
+        self.sequence += 1;
+    }
+
+    /// Removes every pending `event`
+    pub fn cancel(&mut self, event: Event) {
+        self.events.retain(|Reverse((_, _, e))| *e != event);
+    }
+
+    /// Removes every pending `event` and schedules it at cycle `timestamp`
+    pub fn reschedule(&mut self, timestamp: usize, event: Event) {
+        self.cancel(event);
+        self.schedule(timestamp, event);
+    }
+
+    pub fn is_scheduled(&self, event: Event) -> bool {
+        self.events.iter().any(|Reverse((_, _, e))| *e == event)
+    }
+
+    /// Timestamp of the next pending event, `usize::MAX` if there is none
+    pub fn next_timestamp(&self) -> usize {
+        match self.events.peek() {
+            Some(Reverse((timestamp, _, _))) => *timestamp,
+            None => usize::MAX,
+        }
+    }
+
+    /// Pops the next event due at cycle `now`, returns its timestamp and the event
+    pub fn pop_due(&mut self, now: usize) -> Option<(usize, Event)> {
+        if self.next_timestamp() > now {
+            return None;
+        }
+
+        self.events
+            .pop()
+            .map(|Reverse((timestamp, _, event))| (timestamp, event))
+    }
+}
@@ -20,6 +20,59 @@ impl Serial {
         }
     }
 
+    pub fn get_siocnt(&self) -> u16 {
+        ((self.registers_1[9] as u16) << 8) | (self.registers_1[8] as u16)
+    }
+
+    fn set_siocnt(&mut self, val: u16) {
+        self.registers_1[9] = (val >> 8) as u8;
+        self.registers_1[8] = (val & 0xFF) as u8;
+    }
+
+    pub fn is_irq_enabled(&self) -> bool {
+        (self.get_siocnt() & 0x4000) != 0
+    }
+
+    /// Cycles until a started Normal mode transfer with internal clock is done
+    /// None if no such transfer is active (external clock waits for a link partner)
+    pub fn transfer_cycles(&self) -> Option<usize> {
+        let siocnt = self.get_siocnt();
+        let normal = (self.rcnt & 0x8000) == 0 && (siocnt & 0x2000) == 0;
+        let start = (siocnt & 0x80) != 0;
+        let internal_clock = (siocnt & 0x1) != 0;
+
+        if !normal || !start || !internal_clock {
+            return None;
+        }
+
+        let bits = match (siocnt & 0x1000) != 0 {
+            false => 8,
+            true => 32,
+        };
+
+        // 256KHz or 2MHz shift clock
+        let cycles_per_bit = match (siocnt & 0x2) != 0 {
+            false => 64,
+            true => 8,
+        };
+
+        Some(bits * cycles_per_bit)
+    }
+
+    /// Finishes the Normal mode transfer, without a link partner every received bit is 1
+    /// Returns true if the transfer raises the Serial IRQ
+    pub fn transfer_done(&mut self) -> bool {
+        let siocnt = self.get_siocnt();
+
+        match (siocnt & 0x1000) != 0 {
+            false => self.registers_1[10] = 0xFF,      // SIODATA8
+            true => self.registers_1[0..4].fill(0xFF), // SIODATA32
+        }
+
+        self.set_siocnt(siocnt & !0x80);
+        self.is_irq_enabled()
+    }
+
     pub fn read_u16(&self, addr: u32) -> u16 {
         let ptr = (addr - 0x120) as usize;
 
@@ -38,44 +91,24 @@ impl Serial {
         }
     }
 
-    pub fn read_u32(&self, addr: u32) -> u32 {
-        let ptr = (addr - 0x120) as usize;
-
-        match addr {
-            0x120..=0x128 => {
-                ((self.registers_1[ptr + 3] as u32) << 24)
-                    | ((self.registers_1[ptr + 2] as u32) << 16)
-                    | ((self.registers_1[ptr + 1] as u32) << 8)
-                    | (self.registers_1[ptr] as u32)
-            }
-            0x12A => ((self.registers_1[ptr + 1] as u32) << 8) | (self.registers_1[ptr] as u32),
-            0x134 => self.rcnt as u32,
-            0x140 => self.joy_cnt as u32,
-            0x150 => self.joy_recv,
-            0x154 => self.joy_trans,
-            0x158 => self.joy_stat as u32,
-            _ => panic!("Addr out of range for Serial `{:04X}`", addr),
-        }
-    }
-
     pub fn write_u8(&mut self, addr: usize, val: u8) {
         let ptr = (addr - 0x120) as usize;
 
         let val_high = (val as u16) << 8;
 
         match addr {
-            0x120..=0x12A => self.registers_1[ptr] = val,
+            0x120..=0x12B => self.registers_1[ptr] = val,
             0x134 => self.rcnt = (self.rcnt & 0xFF00) | val as u16,
             0x135 => self.rcnt = (self.rcnt & 0x00FF) | val_high,
             0x140 => self.joy_cnt = (self.joy_cnt & 0xFF00) | val as u16,
             0x141 => self.joy_cnt = (self.joy_cnt & 0x00FF) | val_high,
             0x150..=0x153 => {
                 let shift = 8 * (addr - 0x150);
-                self.joy_recv = (self.joy_recv & (0xFF << shift)) | ((val as u32) << shift);
+                self.joy_recv = (self.joy_recv & !(0xFF << shift)) | ((val as u32) << shift);
             }
             0x154..=0x157 => {
                 let shift = 8 * (addr - 0x154);
-                self.joy_trans = (self.joy_trans & (0xFF << shift)) | ((val as u32) << shift);
+                self.joy_trans = (self.joy_trans & !(0xFF << shift)) | ((val as u32) << shift);
             }
             0x158 => self.joy_stat = (self.joy_stat & 0xFF00) | val as u16,
             0x159 => self.joy_stat = (self.joy_stat & 0x00FF) | val_high,
@@ -83,33 +116,12 @@ impl Serial {
         }
     }
 
-    pub fn write_u32(&mut self, addr: u32, val: u32) {
-        let ptr = (addr - 0x120) as usize;
-
-        let byte0 = (val & 0xFF) as u8;
-        let byte1 = ((val >> 8) & 0xFF) as u8;
-        let byte2 = ((val >> 16) & 0xFF) as u8;
-        let byte3 = ((val >> 24) & 0xFF) as u8;
-
-        let u16 = (val & 0xFFFF) as u16;
-
-        match addr {
-            0x120..=0x128 => {
-                self.registers_1[ptr + 3] = byte3;
-                self.registers_1[ptr + 2] = byte2;
-                self.registers_1[ptr + 1] = byte1;
-                self.registers_1[ptr + 0] = byte0;
-            }
-            0x12A => {
-                self.registers_1[ptr + 1] = byte1;
-                self.registers_1[ptr + 0] = byte0;
-            }
-            0x134 => self.rcnt = u16,
-            0x140 => self.joy_cnt = u16,
-            0x150 => self.joy_recv = val,
-            0x154 => self.joy_trans = val,
-            0x158 => self.joy_stat = u16,
-            _ => panic!("Addr out of range for Serial `{:04X}`", addr),
+    pub fn write_u16(&mut self, addr: u32, val: u16, mask: u16) {
+        if (mask & 0x00FF) != 0 {
+            self.write_u8(addr as usize, (val & 0xFF) as u8);
+        }
+        if (mask & 0xFF00) != 0 {
+            self.write_u8((addr + 1) as usize, (val >> 8) as u8);
         }
     }
 }
@@ -1,13 +1,83 @@
-#[derive(Default)]
+use std::collections::VecDeque;
+
+/// Output samples kept for the frontend, 1/8 second at 32768 Hz
+const SAMPLE_BUFFER_SIZE: usize = 4096;
+
 pub struct Sound {
-    pub io_soundcnt: [u32; 3],   // L, H, X
-    pub io_soundcnt_1: [u32; 3], // L, H, X
-    pub io_soundcnt_2: [u32; 2], // L, H
-    pub io_soundcnt_3: [u32; 3], // L, H, X
-    pub io_soundcnt_4: [u32; 2], // L, H
+    pub registers: [u8; 0x48], // 0x060 -> 0x0A7
+    /// Stereo output samples, oldest first
+    pub samples: VecDeque<(i16, i16)>,
+}
+
+impl Sound {
+    pub fn new() -> Self {
+        Self {
+            registers: [0; 0x48],
+            samples: VecDeque::with_capacity(SAMPLE_BUFFER_SIZE),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.registers = [0; 0x48];
+        self.samples.clear();
+    }
+
+    /// Appends the current output to `samples`, dropping the oldest one when the buffer is full
+    /// The PSG channels and Direct Sound are not mixed yet, an enabled output is silent
+    pub fn sample(&mut self) {
+        // SOUNDCNT_X bit 7 switches all sound circuits off
+        if (self.get_u16(0x84) & 0x80) == 0 {
+            return;
+        }
+
+        if self.samples.len() == SAMPLE_BUFFER_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((0, 0));
+    }
+
+    fn get_u16(&self, addr: u32) -> u16 {
+        let addr = (addr - 0x60) as usize;
+        ((self.registers[addr + 1] as u16) << 8) | (self.registers[addr] as u16)
+    }
+
+    fn set_u16(&mut self, addr: u32, val: u16) {
+        let addr = (addr - 0x60) as usize;
+        self.registers[addr + 1] = ((val >> 8) & 0xFF) as u8;
+        self.registers[addr] = (val & 0xFF) as u8;
+    }
+
+    /// Returns (read mask, write mask) of sound register at `addr`
+    fn masks(addr: u32) -> (u16, u16) {
+        match addr {
+            0x60 => (0x007F, 0x007F),        // SOUND1CNT_L
+            0x62 => (0xFFC0, 0xFFFF),        // SOUND1CNT_H
+            0x64 => (0x4000, 0xC7FF),        // SOUND1CNT_X
+            0x68 => (0xFFC0, 0xFFFF),        // SOUND2CNT_L
+            0x6C => (0x4000, 0xC7FF),        // SOUND2CNT_H
+            0x70 => (0x00E0, 0x00E0),        // SOUND3CNT_L
+            0x72 => (0xE000, 0xE0FF),        // SOUND3CNT_H
+            0x74 => (0x4000, 0xC7FF),        // SOUND3CNT_X
+            0x78 => (0xFF00, 0xFF3F),        // SOUND4CNT_L
+            0x7C => (0x40FF, 0xC0FF),        // SOUND4CNT_H
+            0x80 => (0xFF77, 0xFF77),        // SOUNDCNT_L
+            0x82 => (0x770F, 0xFF0F),        // SOUNDCNT_H
+            0x84 => (0x008F, 0x0080),        // SOUNDCNT_X
+            0x88 => (0xC3FE, 0xC3FE),        // SOUNDBIAS
+            0x90..=0x9E => (0xFFFF, 0xFFFF), // WAVE_RAM
+            0xA0..=0xA6 => (0x0000, 0xFFFF), // FIFO_A, FIFO_B
+            _ => (0x0000, 0x0000),
+        }
+    }
 
-    pub io_bias: u16,
+    pub fn read_u16(&self, addr: u32) -> u16 {
+        let (read_mask, _) = Self::masks(addr);
+        self.get_u16(addr) & read_mask
+    }
 
-    pub io_fifo_a: u32,
-    pub io_fifo_b: u32,
+    pub fn write_u16(&mut self, addr: u32, val: u16, mask: u16) {
+        let (_, write_mask) = Self::masks(addr);
+        let mask = mask & write_mask;
+        self.set_u16(addr, (self.get_u16(addr) & !mask) | (val & mask));
+    }
 }
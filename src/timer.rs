@@ -0,0 +1,54 @@
+/// Prescaler selection F/1, F/64, F/256, F/1024
+const PRESCALER: [usize; 4] = [1, 64, 256, 1024];
+
+#[derive(Clone, Copy)]
+pub struct Timer {
+    pub reload: u16,
+    pub control: u16,
+    /// Counter value latched at cycle `start`
+    pub counter: u16,
+    pub start: usize,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Self {
+            reload: 0,
+            control: 0,
+            counter: 0,
+            start: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        (self.control & 0x80) != 0
+    }
+
+    pub fn is_irq_enabled(&self) -> bool {
+        (self.control & 0x40) != 0
+    }
+
+    /// Count-up timing, incremented on overflow of the previous timer (ignored for timer 0)
+    pub fn is_count_up(&self) -> bool {
+        (self.control & 0x4) != 0
+    }
+
+    pub fn prescaler(&self) -> usize {
+        PRESCALER[(self.control & 0x3) as usize]
+    }
+
+    /// Counter value at cycle `now`, count-up timers only change on cascade
+    pub fn counter(&self, now: usize, count_up: bool) -> u16 {
+        if !self.is_enabled() || count_up {
+            return self.counter;
+        }
+
+        let ticks = now.saturating_sub(self.start) / self.prescaler();
+        (self.counter as usize + ticks).min(0xFFFF) as u16
+    }
+
+    /// Cycle at which the counter overflows from 0xFFFF
+    pub fn overflow_at(&self) -> usize {
+        self.start + (0x10000 - self.counter as usize) * self.prescaler()
+    }
+}